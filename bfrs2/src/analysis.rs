@@ -0,0 +1,412 @@
+//! Abstract interpretation of byte nodes.
+//!
+//! Each node has [`Facts`], which over-approximate the set of values it can
+//! take as a range and known bits. The facts of every node are cached in the
+//! `Graph` and computed from the facts of its operands. The facts of copies
+//! and inputs are unknown, unless an analysis has proven an assumption about
+//! them with [`Graph::assume`](crate::egraph::Graph::assume).
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+};
+
+use crate::{
+    egraph::{ByteNode, Graph},
+    node::{choose, Node},
+};
+
+/// Facts about the values of a byte, as an unsigned range and bits which are
+/// known to be 0 or 1. Both are kept consistent with each other.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Facts {
+    min: u8,
+    max: u8,
+    /// Bits which are known to be 0.
+    zeros: u8,
+    /// Bits which are known to be 1.
+    ones: u8,
+}
+
+impl Facts {
+    /// Facts about a value which can be anything.
+    pub const TOP: Facts = Facts {
+        min: 0,
+        max: 255,
+        zeros: 0,
+        ones: 0,
+    };
+
+    /// Facts about a value which is 0 or 1.
+    pub const BOOLEAN: Facts = Facts {
+        min: 0,
+        max: 1,
+        zeros: 0xfe,
+        ones: 0,
+    };
+
+    /// Facts about a constant.
+    pub fn constant(c: u8) -> Self {
+        Facts {
+            min: c,
+            max: c,
+            zeros: !c,
+            ones: c,
+        }
+    }
+
+    /// Facts about a value in an inclusive range.
+    pub fn range(min: u8, max: u8) -> Self {
+        assert!(min <= max, "empty range");
+        Facts {
+            min,
+            max,
+            zeros: 0,
+            ones: 0,
+        }
+        .normalize()
+    }
+
+    /// Facts about a value with known bits. Panics if a bit is known to be both
+    /// 0 and 1.
+    pub fn bits(zeros: u8, ones: u8) -> Self {
+        assert!(zeros & ones == 0, "conflicting known bits");
+        Facts {
+            min: 0,
+            max: 255,
+            zeros,
+            ones,
+        }
+        .normalize()
+    }
+
+    /// Facts about a value which is not zero.
+    pub fn nonzero() -> Self {
+        Facts::range(1, 255)
+    }
+
+    /// The minimum unsigned value.
+    pub fn min(&self) -> u8 {
+        self.min
+    }
+
+    /// The maximum unsigned value.
+    pub fn max(&self) -> u8 {
+        self.max
+    }
+
+    /// Bits which are known to be 0.
+    pub fn known_zeros(&self) -> u8 {
+        self.zeros
+    }
+
+    /// Bits which are known to be 1.
+    pub fn known_ones(&self) -> u8 {
+        self.ones
+    }
+
+    /// Returns the value, if it is known.
+    pub fn as_const(&self) -> Option<u8> {
+        (self.min == self.max).then_some(self.min)
+    }
+
+    /// Returns whether the value is 0 or 1.
+    pub fn is_boolean(&self) -> bool {
+        self.max <= 1
+    }
+
+    /// Returns whether the value can be zero.
+    pub fn may_be_zero(&self) -> bool {
+        self.min == 0
+    }
+
+    /// Returns whether the value is known to be even.
+    pub fn is_even(&self) -> bool {
+        self.zeros & 1 != 0
+    }
+
+    /// Returns whether the value is consistent with these facts.
+    pub fn contains(&self, value: u8) -> bool {
+        self.min <= value && value <= self.max && value & self.zeros == 0 && !value & self.ones == 0
+    }
+
+    /// Computes facts which hold for values in either.
+    pub fn join(&self, other: &Facts) -> Facts {
+        Facts {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+            zeros: self.zeros & other.zeros,
+            ones: self.ones & other.ones,
+        }
+        .normalize()
+    }
+
+    /// Computes facts which hold for values in both, or `None` if no value is
+    /// in both.
+    pub fn meet(&self, other: &Facts) -> Option<Facts> {
+        let facts = Facts {
+            min: self.min.max(other.min),
+            max: self.max.min(other.max),
+            zeros: self.zeros | other.zeros,
+            ones: self.ones | other.ones,
+        };
+        if facts.min > facts.max || facts.zeros & facts.ones != 0 {
+            return None;
+        }
+        let facts = facts.normalize();
+        (facts.min <= facts.max && facts.zeros & facts.ones == 0).then_some(facts)
+    }
+
+    /// Computes facts about the wrapping sum.
+    pub fn add(&self, other: &Facts) -> Facts {
+        let (min, min_overflow) = self.min.overflowing_add(other.min);
+        let (max, max_overflow) = self.max.overflowing_add(other.max);
+        let (min, max) = if min_overflow == max_overflow {
+            (min, max)
+        } else {
+            (0, 255)
+        };
+        // Known bits of a sum, propagating carries which are known.
+        let possible_zero = (!self.zeros).wrapping_add(!other.zeros);
+        let possible_one = self.ones.wrapping_add(other.ones);
+        let carry_zero = !(possible_zero ^ self.zeros ^ other.zeros);
+        let carry_one = possible_one ^ self.ones ^ other.ones;
+        let known =
+            (self.zeros | self.ones) & (other.zeros | other.ones) & (carry_zero | carry_one);
+        Facts {
+            min,
+            max,
+            zeros: !possible_zero & known,
+            ones: possible_one & known,
+        }
+        .normalize()
+    }
+
+    /// Computes facts about the wrapping product.
+    pub fn mul(&self, other: &Facts) -> Facts {
+        if let (Some(a), Some(b)) = (self.as_const(), other.as_const()) {
+            return Facts::constant(a.wrapping_mul(b));
+        }
+        let (min, max) = match (self.max as u16).checked_mul(other.max as u16) {
+            Some(max) if max <= 255 => (self.min * other.min, max as u8),
+            _ => (0, 255),
+        };
+        // The trailing zeros of the operands are trailing zeros of the product.
+        let trailing = (self.zeros.trailing_ones() + other.zeros.trailing_ones()).min(8);
+        let zeros = if trailing == 8 {
+            0xff
+        } else {
+            (1u8 << trailing) - 1
+        };
+        Facts {
+            min,
+            max,
+            zeros,
+            ones: 0,
+        }
+        .normalize()
+    }
+
+    /// Computes facts about the binomial coefficient with this as `n`.
+    pub fn choose(&self, k: u8) -> Facts {
+        if let Some(n) = self.as_const() {
+            Facts::constant(choose(n, k))
+        } else if self.max < k {
+            Facts::constant(0)
+        } else {
+            Facts::TOP
+        }
+    }
+
+    /// Computes facts about the wrapping power.
+    pub fn pow(&self, exp: &Facts) -> Facts {
+        match (self.as_const(), exp.as_const()) {
+            (Some(base), Some(exp)) => Facts::constant(base.wrapping_pow(exp as u32)),
+            (_, Some(0)) => Facts::constant(1),
+            // Odd bases have odd powers and even bases have even powers, unless
+            // the exponent may be zero.
+            _ if self.ones & 1 != 0 => Facts::bits(0, 1),
+            _ if self.is_even() && !exp.may_be_zero() => Facts::bits(1, 0),
+            _ => Facts::TOP,
+        }
+    }
+
+    /// Computes facts about the result of an operation on facts about the
+    /// operands.
+    pub fn of_node(node: &Node, mut operand: impl FnMut(ByteNode) -> Facts) -> Facts {
+        match *node {
+            Node::Copy(..) | Node::Input(_) => Facts::TOP,
+            Node::Const(c) => Facts::constant(c),
+            Node::Add(lhs, rhs) => operand(lhs).add(&operand(rhs)),
+            Node::Mul(lhs, rhs) => operand(lhs).mul(&operand(rhs)),
+            Node::Choose(n, k) => operand(n).choose(k),
+            Node::Pow(base, exp) => operand(base).pow(&operand(exp)),
+            // Control-flow, condition, and effect nodes have no value.
+            _ => Facts::TOP,
+        }
+    }
+
+    /// Tightens the range with the known bits and the known bits with the
+    /// range, until neither changes.
+    fn normalize(mut self) -> Self {
+        loop {
+            let prev = self;
+            // The range is bounded by the known bits.
+            self.min = self.min.max(self.ones);
+            self.max = self.max.min(!self.zeros);
+            // The bits above the highest bit where the bounds differ are
+            // shared by every value in the range.
+            let diff = self.min ^ self.max;
+            let common = if diff == 0 {
+                0xff
+            } else {
+                !(u8::MAX >> diff.leading_zeros())
+            };
+            self.zeros |= !self.min & common;
+            self.ones |= self.min & common;
+            if self.min > self.max || self.zeros & self.ones != 0 || self == prev {
+                return self;
+            }
+        }
+    }
+}
+
+impl Default for Facts {
+    fn default() -> Self {
+        Facts::TOP
+    }
+}
+
+impl Debug for Facts {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}, {}]", self.min, self.max)?;
+        if self.zeros | self.ones != 0 {
+            write!(f, " ")?;
+            for bit in (0..8).rev() {
+                let mask = 1 << bit;
+                let ch = if self.zeros & mask != 0 {
+                    '0'
+                } else if self.ones & mask != 0 {
+                    '1'
+                } else {
+                    '?'
+                };
+                write!(f, "{ch}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Graph {
+    /// Computes facts about a node, where the facts of some leaves are given
+    /// by `leaf` instead of the cached facts. This does not change the cache.
+    pub fn facts_with(
+        &self,
+        id: ByteNode,
+        leaf: &mut impl FnMut(ByteNode) -> Option<Facts>,
+    ) -> Facts {
+        self.facts_with_memo(id, leaf, &mut HashMap::new())
+    }
+
+    fn facts_with_memo(
+        &self,
+        id: ByteNode,
+        leaf: &mut impl FnMut(ByteNode) -> Option<Facts>,
+        memo: &mut HashMap<ByteNode, Facts>,
+    ) -> Facts {
+        if let Some(&facts) = memo.get(&id) {
+            return facts;
+        }
+        let facts = match self[id] {
+            Node::Copy(..) | Node::Input(_) => leaf(id).unwrap_or_else(|| self.facts(id)),
+            Node::Const(c) => Facts::constant(c),
+            Node::Add(lhs, rhs) => self
+                .facts_with_memo(lhs, leaf, memo)
+                .add(&self.facts_with_memo(rhs, leaf, memo)),
+            Node::Mul(lhs, rhs) => self
+                .facts_with_memo(lhs, leaf, memo)
+                .mul(&self.facts_with_memo(rhs, leaf, memo)),
+            Node::Choose(n, k) => self.facts_with_memo(n, leaf, memo).choose(k),
+            Node::Pow(base, exp) => self
+                .facts_with_memo(base, leaf, memo)
+                .pow(&self.facts_with_memo(exp, leaf, memo)),
+            _ => unreachable!("non-byte node in byte expression"),
+        };
+        memo.insert(id, facts);
+        facts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{analysis::Facts, node::choose};
+
+    /// Checks that the facts for an operation contain every result of the
+    /// operation on values in the operand facts.
+    fn check_sound(a: Facts, b: Facts) {
+        let values = |f: Facts| (0..=255u8).filter(move |&v| f.contains(v));
+        let sum = a.add(&b);
+        let product = a.mul(&b);
+        let power = a.pow(&b);
+        for x in values(a) {
+            for k in 0..4 {
+                assert!(a.choose(k).contains(choose(x, k)), "{a:?} choose {k}");
+            }
+            for y in values(b) {
+                assert!(sum.contains(x.wrapping_add(y)), "{a:?} + {b:?} = {sum:?}");
+                assert!(
+                    product.contains(x.wrapping_mul(y)),
+                    "{a:?} * {b:?} = {product:?}"
+                );
+                assert!(
+                    power.contains(x.wrapping_pow(y as u32)),
+                    "{a:?} ** {b:?} = {power:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sound() {
+        let facts = [
+            Facts::TOP,
+            Facts::BOOLEAN,
+            Facts::nonzero(),
+            Facts::constant(0),
+            Facts::constant(1),
+            Facts::constant(255),
+            Facts::constant(6),
+            Facts::range(3, 17),
+            Facts::range(200, 255),
+            Facts::bits(0b1, 0),
+            Facts::bits(0b11, 0b1000_0000),
+            Facts::bits(0, 0b1),
+        ];
+        for &a in &facts {
+            for &b in &facts {
+                check_sound(a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn precise() {
+        assert_eq!(
+            Facts::constant(3).add(&Facts::constant(254)),
+            Facts::constant(1)
+        );
+        assert!(Facts::BOOLEAN.mul(&Facts::BOOLEAN).is_boolean());
+        assert!(Facts::bits(1, 0).mul(&Facts::TOP).is_even());
+        assert_eq!(
+            Facts::BOOLEAN.meet(&Facts::nonzero()),
+            Some(Facts::constant(1)),
+        );
+        assert_eq!(Facts::constant(0).meet(&Facts::nonzero()), None);
+        assert_eq!(Facts::range(2, 3).known_ones(), 0b10);
+        assert_eq!(
+            Facts::BOOLEAN.add(&Facts::constant(255)),
+            Facts::range(0, 255)
+        );
+    }
+}
@@ -100,7 +100,7 @@ impl<T> Arena<T> {
             Self::out_of_bounds();
         }
         unsafe {
-            let chunk = (*self.chunks.get()).get_unchecked(index / Self::CHUNK_SIZE);
+            let chunk = (&*self.chunks.get()).get_unchecked(index / Self::CHUNK_SIZE);
             (&*chunk.add(index % Self::CHUNK_SIZE)).assume_init_ref()
         }
     }
@@ -114,7 +114,7 @@ impl<T> Arena<T> {
             Self::out_of_bounds();
         }
         unsafe {
-            let chunk = (*self.chunks.get()).get_unchecked_mut(index / Self::CHUNK_SIZE);
+            let chunk = (&mut *self.chunks.get()).get_unchecked_mut(index / Self::CHUNK_SIZE);
             (&mut *chunk.add(index % Self::CHUNK_SIZE)).assume_init_mut()
         }
     }
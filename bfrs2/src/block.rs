@@ -1,22 +1,24 @@
 use std::{
     collections::{HashMap, VecDeque},
-    mem,
+    mem, slice,
 };
 
 use crate::{
-    egraph::{Graph, NodeId},
+    egraph::{ByteNode, EffectNode, Graph, InputNode, NodeId},
     node::{BlockId, InputId, Node, Offset},
 };
 
 /// The memory and effects of a basic block.
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Block {
     /// The ID of this block, unique per e-graph.
     pub id: BlockId,
     /// The values modified in this memory.
-    memory: VecDeque<Option<NodeId>>,
-    /// The sequence of effects in this basic block.
-    pub effects: Vec<Effect>,
+    memory: VecDeque<Option<ByteNode>>,
+    /// The last token of the chain of effects in this basic block, which
+    /// starts at the entry token of this block, or `None` when it has no
+    /// effects.
+    effects: Option<EffectNode>,
 
     /// The relative offset of the cell pointer.
     pub offset: Offset,
@@ -29,16 +31,46 @@ pub struct Block {
 }
 
 /// An observable effect.
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Effect {
     /// Printing bytes.
-    Output(Vec<NodeId>),
-    /// Reading a byte from the user. The node is always `Node::Input`.
-    Input(NodeId),
+    Output(Vec<ByteNode>),
+    /// Printing constant bytes, which can be written at once.
+    OutputBytes(Vec<u8>),
+    /// Printing a byte as a decimal number without leading zeros.
+    OutputDecimal(ByteNode),
+    /// Reading a byte from the user.
+    Input(InputNode),
+    /// Reading bytes from the user until one of the delimiters is read.
+    InputUntil {
+        /// The last byte read.
+        input: InputNode,
+        /// The bytes which end the input, in ascending order.
+        delimiters: Vec<u8>,
+    },
+    /// Reading a decimal number from the user. Every byte, which does not end
+    /// the number, is read as a digit, so the value is `value * 10 + b - '0'`
+    /// for each byte `b`, wrapping.
+    InputDecimal {
+        /// The value read.
+        value: InputNode,
+        /// How the number ends.
+        end: DecimalEnd,
+    },
     /// Guarding that a shift can be performed by a certain amount.
     GuardShift(Offset),
 }
 
+/// How a decimal number read from the user ends.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DecimalEnd {
+    /// After a fixed number of digits.
+    Digits(u8),
+    /// At one of these bytes, in ascending order, which is read, but not
+    /// included in the number.
+    Delimiters(Vec<u8>),
+}
+
 /// A builder for a basic block, which avoids constructing intermediate nodes
 /// from sequences of `+` and `-` instructions.
 #[derive(Debug)]
@@ -48,6 +80,9 @@ pub struct BlockBuilder {
     /// Constant addends to each cell. `addends[i]` corresponds with
     /// `block.memory[i]`.
     addends: VecDeque<u8>,
+    /// The effects of the block, which are chained when it is finished, so
+    /// that adjacent outputs are merged without inserting intermediate nodes.
+    effects: Vec<Effect>,
 }
 
 impl Block {
@@ -56,7 +91,7 @@ impl Block {
         Block {
             id,
             memory: VecDeque::new(),
-            effects: Vec::new(),
+            effects: None,
             offset: Offset(0),
             min_offset: Offset(0),
             guarded_left: Offset(0),
@@ -65,13 +100,20 @@ impl Block {
     }
 
     /// Gets the value of the cell at the offset.
-    pub fn get_cell(&self, offset: Offset) -> Option<NodeId> {
+    pub fn get_cell(&self, offset: Offset) -> Option<ByteNode> {
         self.memory
             .get(offset.try_index_from(self.min_offset)?)
             .copied()
             .flatten()
     }
 
+    /// Sets the value of the cell at the offset.
+    pub fn set_cell(&mut self, offset: Offset, value: ByteNode) {
+        self.reserve(offset, offset + 1);
+        let i = offset.index_from(self.min_offset);
+        self.memory[i] = Some(value);
+    }
+
     /// The minimum offset, which has a modified value in this basic block.
     pub fn min_offset(&self) -> Offset {
         self.min_offset
@@ -85,24 +127,37 @@ impl Block {
     /// Concatenates two basic blocks. Applies the operations of `other` to
     /// `self`.
     pub fn concat(&mut self, other: &Self, g: &mut Graph) {
-        self.effects.reserve(other.effects.len());
-        for effect in &other.effects {
+        for effect in other.effects(g) {
             let effect = match effect {
                 Effect::Output(values) => {
-                    let mut effects = mem::take(&mut self.effects);
                     let values = values
                         .iter()
-                        .map(|value| value.move_to_block(other.id, self, g));
-                    if let Some(Effect::Output(values1)) = effects.last_mut() {
-                        values1.extend(values);
-                    } else {
-                        effects.push(Effect::Output(values.collect()));
+                        .map(|value| value.move_to_block(other.id, self, g))
+                        .collect::<Vec<_>>();
+                    match self.last_effect(g) {
+                        Some(Effect::Output(values1)) => {
+                            let values = [values1.as_slice(), &values].concat();
+                            self.pop_effect(g);
+                            Effect::Output(values)
+                        }
+                        _ => Effect::Output(values),
                     }
-                    self.effects = effects;
-                    continue;
                 }
-                &Effect::Input(input) => Effect::Input(input),
-                &Effect::GuardShift(offset) => {
+                Effect::OutputBytes(bytes) => match self.last_effect(g) {
+                    Some(Effect::OutputBytes(bytes1)) => {
+                        let bytes = [bytes1.as_slice(), &bytes].concat();
+                        self.pop_effect(g);
+                        Effect::OutputBytes(bytes)
+                    }
+                    _ => Effect::OutputBytes(bytes),
+                },
+                Effect::OutputDecimal(value) => {
+                    Effect::OutputDecimal(value.move_to_block(other.id, self, g))
+                }
+                Effect::Input(_) | Effect::InputUntil { .. } | Effect::InputDecimal { .. } => {
+                    effect
+                }
+                Effect::GuardShift(offset) => {
                     let offset = offset + self.offset;
                     if offset < self.guarded_left {
                         self.guarded_left = offset;
@@ -114,7 +169,7 @@ impl Block {
                     Effect::GuardShift(offset)
                 }
             };
-            self.effects.push(effect);
+            self.push_effect(effect, g);
         }
 
         let mut other_memory = other.memory.clone();
@@ -138,7 +193,7 @@ impl Block {
 
     /// Reserves slots for cells in the range `min_offset..max_offset` and fills
     /// them with `None`.
-    fn reserve(&mut self, min_offset: Offset, max_offset: Offset) {
+    pub(crate) fn reserve(&mut self, min_offset: Offset, max_offset: Offset) {
         debug_assert!(max_offset >= min_offset);
         if self.memory.is_empty() {
             self.memory.resize(max_offset.index_from(min_offset), None);
@@ -166,35 +221,143 @@ impl Block {
             .iter()
             .map(|cell| cell.map(|cell| cell.clone_in_block(self.id, id, &mut inputs, g)))
             .collect();
-        let effects = self
-            .effects
-            .iter()
-            .map(|effect| effect.clone_in_block(self.id, id, &mut inputs, g))
-            .collect();
-        Block {
+        let mut block = Block {
             id,
             memory,
-            effects,
+            effects: None,
             offset: self.offset,
             min_offset: self.min_offset,
             guarded_left: self.guarded_left,
             guarded_right: self.guarded_right,
+        };
+        for effect in self.effects(g) {
+            let effect = effect.clone_in_block(self.id, id, &mut inputs, g);
+            block.push_effect(effect, g);
         }
+        block
     }
 
     /// Returns an iterator for cells assigned in this block.
-    pub fn iter_memory(&self) -> impl Iterator<Item = (Offset, NodeId)> + '_ {
+    pub fn iter_memory(&self) -> impl Iterator<Item = (Offset, ByteNode)> + '_ {
         (self.min_offset.0..)
             .map(Offset)
             .zip(self.memory.iter())
             .filter_map(|(offset, cell)| cell.map(|cell| (offset, cell)))
     }
 
+    /// Replaces the IDs of the nodes, which this block uses.
+    pub(crate) fn remap(&mut self, f: &mut impl FnMut(NodeId) -> NodeId) {
+        for cell in self.memory.iter_mut().flatten() {
+            *cell = cell.remap(f);
+        }
+        self.effects = self.effects.map(|token| token.remap(f));
+    }
+
+    /// Returns an iterator for the IDs of the nodes, which this block uses
+    /// directly, i.e., the values of cells and the last effect token.
+    pub fn operands(&self) -> impl Iterator<Item = NodeId> + '_ {
+        (self.iter_memory().map(|(_, cell)| cell.id())).chain(self.effects.map(EffectNode::id))
+    }
+
+    /// Gets the last token of the chain of effects in this block, or `None`
+    /// when it has no effects.
+    pub fn effect_token(&self) -> Option<EffectNode> {
+        self.effects
+    }
+
+    /// Returns whether this block has any effects.
+    pub fn has_effects(&self) -> bool {
+        self.effects.is_some()
+    }
+
+    /// Gets the effects of this block in order, by following its chain of
+    /// effect tokens.
+    pub fn effects(&self, g: &Graph) -> Vec<Effect> {
+        let mut effects = Vec::new();
+        let mut token = self.effects;
+        while let Some(Node::Effect(prev, effect)) = token.map(|token| &g[token]) {
+            effects.push(effect.clone());
+            token = Some(*prev);
+        }
+        effects.reverse();
+        effects
+    }
+
+    /// Gets the last effect of this block.
+    pub fn last_effect<'g>(&self, g: &'g Graph) -> Option<&'g Effect> {
+        match g[self.effects?] {
+            Node::Effect(_, ref effect) => Some(effect),
+            _ => None,
+        }
+    }
+
+    /// Appends an effect to the chain of effects of this block.
+    pub fn push_effect(&mut self, effect: Effect, g: &mut Graph) {
+        let token = (self.effects).unwrap_or_else(|| Node::Entry(self.id).insert_ideal(g));
+        self.effects = Some(Node::Effect(token, effect).insert_ideal(g));
+    }
+
+    /// Removes the last effect from the chain of effects of this block and
+    /// returns it.
+    pub fn pop_effect(&mut self, g: &Graph) -> Option<Effect> {
+        let Node::Effect(prev, ref effect) = g[self.effects?] else {
+            return None;
+        };
+        self.effects = matches!(g[prev], Node::Effect(..)).then_some(prev);
+        Some(effect.clone())
+    }
+
+    /// Replaces the effects of this block with a new chain of the effects.
+    pub fn set_effects(&mut self, effects: impl IntoIterator<Item = Effect>, g: &mut Graph) {
+        self.effects = None;
+        for effect in effects {
+            self.push_effect(effect, g);
+        }
+    }
+
+    /// Replaces the values, which the effects of this block output, and
+    /// retains the previous chain of effects in the e-class of the new chain,
+    /// when any value changed.
+    pub fn map_outputs(
+        &mut self,
+        g: &mut Graph,
+        mut f: impl FnMut(ByteNode, &mut Graph) -> ByteNode,
+    ) {
+        let mut effects = self.effects(g);
+        let mut changed = false;
+        let mut map = |value: &mut ByteNode, g: &mut Graph| {
+            let new = f(*value, g);
+            changed |= new != *value;
+            *value = new;
+        };
+        for effect in &mut effects {
+            match effect {
+                Effect::Output(values) => values.iter_mut().for_each(|value| map(value, g)),
+                Effect::OutputDecimal(value) => map(value, g),
+                _ => {}
+            }
+        }
+        if changed {
+            let old = self.effects;
+            self.set_effects(effects, g);
+            if let (Some(old), Some(new)) = (old, self.effects) {
+                old.replace(new, g);
+            }
+        }
+    }
+
+    /// Replaces the effects of this block with the chain of effect nodes, which
+    /// ends at the token. The chain must start at the entry token of this
+    /// block, which [`Block::verify`] checks.
+    pub fn set_effect_token(&mut self, token: EffectNode, g: &Graph) {
+        self.effects = matches!(g[token], Node::Effect(..)).then_some(token);
+    }
+
     /// Iterates mutably over cells assigned in this block.
     pub fn iter_memory_mut(
         &mut self,
         g: &mut Graph,
-        mut each: impl FnMut(Offset, NodeId, &mut Graph) -> Option<NodeId>,
+        mut each: impl FnMut(Offset, ByteNode, &mut Graph) -> Option<ByteNode>,
     ) {
         for (offset, slot) in (self.min_offset.0..)
             .map(Offset)
@@ -226,6 +389,7 @@ impl BlockBuilder {
         BlockBuilder {
             block: Block::new(g.fresh_block_id()),
             addends: VecDeque::new(),
+            effects: Vec::new(),
         }
     }
 
@@ -241,11 +405,11 @@ impl BlockBuilder {
         } else {
             return;
         };
-        block.effects.push(Effect::GuardShift(block.offset));
+        self.effects.push(Effect::GuardShift(block.offset));
     }
 
     /// Gets the value at the cell pointer, forcing construction of its nodes.
-    pub fn get(&mut self, g: &mut Graph) -> NodeId {
+    pub fn get(&mut self, g: &mut Graph) -> ByteNode {
         let (&mut base, &mut addend) = self.get_parts();
         let base = base.unwrap_or_else(|| Node::Copy(self.block.offset, self.block.id).insert(g));
         if addend != 0 {
@@ -256,7 +420,7 @@ impl BlockBuilder {
     }
 
     /// Sets the value at the cell pointer.
-    pub fn set(&mut self, node: NodeId) {
+    pub fn set(&mut self, node: ByteNode) {
         let (base, addend) = self.get_parts();
         *base = Some(node);
         *addend = 0;
@@ -271,23 +435,43 @@ impl BlockBuilder {
     /// Outputs the value at the cell pointer.
     pub fn output(&mut self, g: &mut Graph) {
         let value = self.get(g);
-        if let Some(Effect::Output(values)) = self.block.effects.last_mut() {
+        if let Some(Effect::Output(values)) = self.effects.last_mut() {
             values.push(value);
         } else {
-            self.block.effects.push(Effect::Output(vec![value]));
+            self.effects.push(Effect::Output(vec![value]));
         }
     }
 
     /// Gets a byte from the user and sets the value at the cell pointer to it.
     pub fn input(&mut self, g: &mut Graph) {
         let input = g.fresh_input();
-        self.set(input);
-        self.block.effects.push(Effect::Input(input));
+        self.set(input.byte());
+        self.effects.push(Effect::Input(input));
+    }
+
+    /// Gets bytes from the user until one of the delimiters is read and returns
+    /// the last byte read.
+    pub fn input_until(&mut self, delimiters: Vec<u8>, g: &mut Graph) -> InputNode {
+        let input = g.fresh_input();
+        self.effects.push(Effect::InputUntil { input, delimiters });
+        input
+    }
+
+    /// Outputs a value as a decimal number.
+    pub fn output_decimal(&mut self, value: ByteNode) {
+        self.effects.push(Effect::OutputDecimal(value));
+    }
+
+    /// Gets a decimal number from the user and returns its value.
+    pub fn input_decimal(&mut self, end: DecimalEnd, g: &mut Graph) -> InputNode {
+        let value = g.fresh_input();
+        self.effects.push(Effect::InputDecimal { value, end });
+        value
     }
 
     /// Gets the base node (a `Copy` or `Input`) and constant addend for the
     /// value at the cell pointer.
-    fn get_parts(&mut self) -> (&mut Option<NodeId>, &mut u8) {
+    fn get_parts(&mut self) -> (&mut Option<ByteNode>, &mut u8) {
         let block = &mut self.block;
         let i = block.offset.index_from_signed(block.min_offset);
         if block.memory.is_empty() {
@@ -327,6 +511,7 @@ impl BlockBuilder {
             }
         }
         self.addends.clear();
+        block.set_effects(self.effects.drain(..), g);
         block
     }
 
@@ -334,7 +519,7 @@ impl BlockBuilder {
     pub fn is_empty(&self) -> bool {
         let block = &self.block;
         block.memory.is_empty()
-            && block.effects.is_empty()
+            && self.effects.is_empty()
             && block.offset == Offset(0)
             && block.min_offset == Offset(0)
             && block.guarded_left == Offset(0)
@@ -342,7 +527,7 @@ impl BlockBuilder {
     }
 }
 
-impl NodeId {
+impl ByteNode {
     /// Makes a copy of this node, but with its copies be relative to the given
     /// block.
     pub fn move_to_block(self, block_from: BlockId, block_to: &Block, g: &mut Graph) -> Self {
@@ -364,6 +549,16 @@ impl NodeId {
                 let rhs = rhs.move_to_block(block_from, block_to, g);
                 Node::Mul(lhs, rhs).insert(g)
             }
+            Node::Choose(n, k) => {
+                let n = n.move_to_block(block_from, block_to, g);
+                Node::Choose(n, k).insert(g)
+            }
+            Node::Pow(base, exp) => {
+                let base = base.move_to_block(block_from, block_to, g);
+                let exp = exp.move_to_block(block_from, block_to, g);
+                Node::Pow(base, exp).insert(g)
+            }
+            _ => unreachable!("non-byte node in byte expression"),
         }
     }
 
@@ -373,7 +568,7 @@ impl NodeId {
         self,
         block_from: BlockId,
         block_to: BlockId,
-        inputs: &mut HashMap<InputId, NodeId>,
+        inputs: &mut HashMap<InputId, InputNode>,
         g: &mut Graph,
     ) -> Self {
         match g[self] {
@@ -381,15 +576,10 @@ impl NodeId {
                 Node::Copy(offset, block_to).insert_ideal(g)
             }
             Node::Copy(..) | Node::Const(_) => self,
-            Node::Input(input) => {
-                if let Some(&id) = inputs.get(&input) {
-                    id
-                } else {
-                    let id = g.fresh_input();
-                    inputs.insert(input, id);
-                    id
-                }
-            }
+            Node::Input(input) => inputs
+                .entry(input)
+                .or_insert_with(|| g.fresh_input())
+                .byte(),
             Node::Add(lhs, rhs) => {
                 let lhs = lhs.clone_in_block(block_from, block_to, inputs, g);
                 let rhs = rhs.clone_in_block(block_from, block_to, inputs, g);
@@ -400,18 +590,73 @@ impl NodeId {
                 let rhs = rhs.clone_in_block(block_from, block_to, inputs, g);
                 Node::Mul(lhs, rhs).insert_ideal(g)
             }
+            Node::Choose(n, k) => {
+                let n = n.clone_in_block(block_from, block_to, inputs, g);
+                Node::Choose(n, k).insert_ideal(g)
+            }
+            Node::Pow(base, exp) => {
+                let base = base.clone_in_block(block_from, block_to, inputs, g);
+                let exp = exp.clone_in_block(block_from, block_to, inputs, g);
+                Node::Pow(base, exp).insert_ideal(g)
+            }
+            _ => unreachable!("non-byte node in byte expression"),
         }
     }
 }
 
+impl InputNode {
+    /// Gets the fresh input, which replaces this input in a cloned block.
+    fn clone_fresh(self, inputs: &mut HashMap<InputId, InputNode>, g: &mut Graph) -> Self {
+        let Node::Input(input) = g[self] else {
+            unreachable!();
+        };
+        *inputs.entry(input).or_insert_with(|| g.fresh_input())
+    }
+}
+
 impl Effect {
+    /// Replaces the IDs of the nodes, which this effect uses.
+    pub(crate) fn remap(&mut self, f: &mut impl FnMut(NodeId) -> NodeId) {
+        match self {
+            Effect::Output(values) => values.iter_mut().for_each(|value| *value = value.remap(f)),
+            Effect::OutputDecimal(value) => *value = value.remap(f),
+            Effect::Input(input)
+            | Effect::InputUntil { input, .. }
+            | Effect::InputDecimal { value: input, .. } => *input = input.remap(f),
+            Effect::OutputBytes(_) | Effect::GuardShift(_) => {}
+        }
+    }
+
+    /// Gets the nodes used by this effect.
+    pub fn nodes(&self) -> &[ByteNode] {
+        match self {
+            Effect::Output(values) => values,
+            Effect::OutputDecimal(value) => slice::from_ref(value),
+            Effect::Input(input)
+            | Effect::InputUntil { input, .. }
+            | Effect::InputDecimal { value: input, .. } => slice::from_ref(input.as_byte()),
+            Effect::OutputBytes(_) | Effect::GuardShift(_) => &[],
+        }
+    }
+
+    /// Returns whether this effect outputs only constant bytes.
+    pub fn is_const_output(&self, g: &Graph) -> bool {
+        match self {
+            Effect::Output(values) => values
+                .iter()
+                .all(|&value| matches!(g[value], Node::Const(_))),
+            Effect::OutputBytes(_) => true,
+            _ => false,
+        }
+    }
+
     /// Clones this effect, making its copies be relative to the given block and
     /// generating fresh inputs.
     pub fn clone_in_block(
         &self,
         block_from: BlockId,
         block_to: BlockId,
-        inputs: &mut HashMap<InputId, NodeId>,
+        inputs: &mut HashMap<InputId, InputNode>,
         g: &mut Graph,
     ) -> Self {
         match self {
@@ -422,9 +667,19 @@ impl Effect {
                     .collect();
                 Effect::Output(values)
             }
-            Effect::Input(input) => {
-                Effect::Input(input.clone_in_block(block_from, block_to, inputs, g))
+            Effect::OutputBytes(bytes) => Effect::OutputBytes(bytes.clone()),
+            Effect::OutputDecimal(value) => {
+                Effect::OutputDecimal(value.clone_in_block(block_from, block_to, inputs, g))
             }
+            Effect::Input(input) => Effect::Input(input.clone_fresh(inputs, g)),
+            Effect::InputUntil { input, delimiters } => Effect::InputUntil {
+                input: input.clone_fresh(inputs, g),
+                delimiters: delimiters.clone(),
+            },
+            Effect::InputDecimal { value, end } => Effect::InputDecimal {
+                value: value.clone_fresh(inputs, g),
+                end: end.clone(),
+            },
             &Effect::GuardShift(offset) => Effect::GuardShift(offset),
         }
     }
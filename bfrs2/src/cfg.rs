@@ -1,12 +1,19 @@
 use std::{
+    collections::HashMap,
     fmt::{self, Debug, Formatter},
+    mem,
     ops::{Deref, DerefMut},
+    vec,
 };
 
-use crate::{block::Block, egraph::Graph};
+use crate::{
+    block::Block,
+    egraph::{BoolNode, ByteNode, CtrlNode, Graph, NodeId},
+    node::{BlockId, Node, Offset},
+};
 
 /// The control-flow graph of a program.
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq)]
 pub enum Cfg {
     /// Basic block.
     Block(Block),
@@ -16,10 +23,29 @@ pub enum Cfg {
     Loop(Box<Cfg>),
     /// If the current cell is non-zero.
     If(Box<Cfg>),
+    /// Loop a number of times, which is computed on entry.
+    Repeat {
+        /// The ID, which copies in `count` use to read cells on entry.
+        id: BlockId,
+        /// The number of iterations.
+        count: ByteNode,
+        /// The loop body.
+        body: Box<Cfg>,
+    },
+    /// Read bytes into consecutive cells until the delimiter is read, while
+    /// the current cell is non-zero. This is equivalent to `while p[0] != 0 {
+    /// p[0] += delimiter; p += step; p[0] = input() - delimiter }`, so each
+    /// cell holds the byte read, except for the last, which is zero.
+    InputCells {
+        /// The shift between cells.
+        step: Offset,
+        /// The byte which ends the input.
+        delimiter: u8,
+    },
 }
 
 /// A sequence of control-flow nodes.
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq)]
 pub struct Seq {
     cfgs: Vec<Cfg>,
 }
@@ -30,6 +56,121 @@ impl Cfg {
         Cfg::Seq(Seq::new())
     }
 
+    /// Replaces the IDs of the nodes, which this CFG uses.
+    pub(crate) fn remap(&mut self, f: &mut impl FnMut(NodeId) -> NodeId) {
+        match self {
+            Cfg::Block(block) => block.remap(f),
+            Cfg::Seq(seq) => seq.iter_mut().for_each(|cfg| cfg.remap(f)),
+            Cfg::Loop(body) | Cfg::If(body) => body.remap(f),
+            Cfg::Repeat { count, body, .. } => {
+                *count = count.remap(f);
+                body.remap(f);
+            }
+            Cfg::InputCells { .. } => {}
+        }
+    }
+
+    /// Clones this CFG, giving each block a fresh ID and generating fresh
+    /// inputs.
+    pub fn clone_fresh(&self, g: &mut Graph) -> Self {
+        match self {
+            Cfg::Block(block) => Cfg::Block(block.clone_fresh(g)),
+            Cfg::Seq(seq) => Cfg::Seq(Seq {
+                cfgs: seq.iter().map(|cfg| cfg.clone_fresh(g)).collect(),
+            }),
+            Cfg::Loop(body) => Cfg::Loop(Box::new(body.clone_fresh(g))),
+            Cfg::If(body) => Cfg::If(Box::new(body.clone_fresh(g))),
+            Cfg::Repeat { id, count, body } => {
+                let new_id = g.fresh_block_id();
+                Cfg::Repeat {
+                    id: new_id,
+                    count: count.clone_in_block(*id, new_id, &mut HashMap::new(), g),
+                    body: Box::new(body.clone_fresh(g)),
+                }
+            }
+            &Cfg::InputCells { step, delimiter } => Cfg::InputCells { step, delimiter },
+        }
+    }
+
+    /// Inserts this CFG into the e-graph as control-flow nodes and returns the
+    /// ID of its root. Structurally identical CFGs receive the same ID.
+    pub fn insert(&self, g: &mut Graph) -> CtrlNode {
+        let node = match self {
+            Cfg::Block(block) => Node::Block(Box::new(block.clone())),
+            Cfg::Seq(seq) => Node::Seq(seq.iter().map(|cfg| cfg.insert(g)).collect()),
+            Cfg::Loop(body) => Node::Loop(Cfg::current_non_zero(g), body.insert(g)),
+            Cfg::If(body) => Node::If(Cfg::current_non_zero(g), body.insert(g)),
+            &Cfg::Repeat {
+                id,
+                count,
+                ref body,
+            } => Node::Repeat {
+                id,
+                count,
+                body: body.insert(g),
+            },
+            &Cfg::InputCells { step, delimiter } => Node::InputCells { step, delimiter },
+        };
+        node.insert_ideal(g)
+    }
+
+    /// Rewrites this CFG in place and, when it changed, inserts its previous
+    /// and new versions into the e-graph, so that the previous version is
+    /// retained in the e-class of the new version. Only the rewritten CFG is
+    /// inserted, not its enclosing program. Returns whether it changed.
+    pub fn rewrite(&mut self, g: &mut Graph, f: impl FnOnce(&mut Self, &mut Graph)) -> bool {
+        let old = self.clone();
+        f(self, g);
+        if *self == old {
+            return false;
+        }
+        let old = old.insert(g);
+        let new = self.insert(g);
+        if new != old {
+            old.replace(new, g);
+        }
+        true
+    }
+
+    /// Reconstructs the CFG rooted at a control-flow node.
+    pub fn from_node(id: CtrlNode, g: &Graph) -> Self {
+        match g[id] {
+            Node::Block(ref block) => Cfg::Block((**block).clone()),
+            Node::Seq(ref seq) => Cfg::Seq(Seq {
+                cfgs: seq.iter().map(|&id| Cfg::from_node(id, g)).collect(),
+            }),
+            Node::Loop(cond, body) => {
+                Cfg::assert_current_non_zero(cond, g);
+                Cfg::Loop(Box::new(Cfg::from_node(body, g)))
+            }
+            Node::If(cond, body) => {
+                Cfg::assert_current_non_zero(cond, g);
+                Cfg::If(Box::new(Cfg::from_node(body, g)))
+            }
+            Node::Repeat { id, count, body } => Cfg::Repeat {
+                id,
+                count,
+                body: Box::new(Cfg::from_node(body, g)),
+            },
+            Node::InputCells { step, delimiter } => Cfg::InputCells { step, delimiter },
+            _ => unreachable!("non-control-flow node in CFG"),
+        }
+    }
+
+    /// Inserts the condition of loops and ifs in a CFG, that the current cell
+    /// is non-zero.
+    fn current_non_zero(g: &mut Graph) -> BoolNode {
+        Node::NonZero(Offset(0)).insert_ideal(g)
+    }
+
+    /// Panics if the condition is not that the current cell is non-zero,
+    /// which is the only condition a CFG can represent.
+    fn assert_current_non_zero(cond: BoolNode, g: &Graph) {
+        if g[cond] != Node::NonZero(Offset(0)) {
+            panic!("unsupported condition in CFG: {:?}", g[cond]);
+        }
+    }
+
     /// Flattens a 1-element `Seq` into its element.
     pub fn flatten(&mut self, g: &mut Graph) {
         if let Cfg::Seq(seq) = self {
@@ -48,6 +189,17 @@ impl Debug for Cfg {
             Cfg::Seq(seq) => Debug::fmt(seq, f),
             Cfg::Loop(cfg) => f.debug_tuple("Loop").field(cfg).finish(),
             Cfg::If(cfg) => f.debug_tuple("If").field(cfg).finish(),
+            Cfg::Repeat { id, count, body } => f
+                .debug_struct("Repeat")
+                .field("id", id)
+                .field("count", count)
+                .field("body", body)
+                .finish(),
+            Cfg::InputCells { step, delimiter } => f
+                .debug_struct("InputCells")
+                .field("step", step)
+                .field("delimiter", delimiter)
+                .finish(),
         }
     }
 }
@@ -84,42 +236,25 @@ impl Seq {
     pub fn push(&mut self, cfg: Cfg, g: &mut Graph) {
         match (self.cfgs.last_mut(), cfg) {
             (Some(Cfg::Block(block1)), Cfg::Block(block2)) => block1.concat(&block2, g),
-            (_, Cfg::Seq(seq)) => self.cfgs.extend(seq.cfgs.into_iter()),
+            // Push each element, so a block at either end of the sequence is
+            // concatenated with its neighbor.
+            (_, Cfg::Seq(seq)) => seq.cfgs.into_iter().for_each(|cfg| self.push(cfg, g)),
             (_, cfg) => self.cfgs.push(cfg),
         }
     }
 
-    /// Concanates adjacent blocks and flattens top-level sequences.
+    /// Concanates adjacent blocks and flattens top-level sequences. Blocks,
+    /// which become adjacent by splicing a sequence between them, such as an
+    /// empty sequence left by removing a CFG, are also concatenated.
     pub fn flatten(&mut self, g: &mut Graph) {
-        let mut has_nested_seq = false;
-        let mut flattened_len = self.cfgs.len();
-        if let Some(Cfg::Seq(seq)) = self.cfgs.first() {
-            has_nested_seq = true;
-            flattened_len = flattened_len - 1 + seq.cfgs.len();
-        }
-        self.cfgs.dedup_by(|cfg2, cfg1| match (cfg1, cfg2) {
-            (Cfg::Block(block1), Cfg::Block(block2)) => {
-                block1.concat(block2, g);
-                true
-            }
-            (_, Cfg::Seq(seq)) => {
-                has_nested_seq = true;
-                flattened_len = flattened_len - 1 + seq.cfgs.len();
-                false
-            }
-            _ => false,
-        });
-        if !has_nested_seq {
+        let is_flat = self.cfgs.iter().all(|cfg| !matches!(cfg, Cfg::Seq(_)))
+            && (self.cfgs.windows(2)).all(|pair| !matches!(pair, [Cfg::Block(_), Cfg::Block(_)]));
+        if is_flat {
             return;
         }
-        let mut flattened = Vec::with_capacity(flattened_len);
-        for cfg in self.cfgs.drain(..) {
-            match cfg {
-                Cfg::Seq(inner) => flattened.extend(inner.cfgs.into_iter()),
-                _ => flattened.push(cfg),
-            }
+        for cfg in mem::take(&mut self.cfgs) {
+            self.push(cfg, g);
         }
-        self.cfgs = flattened;
     }
 
     /// Gets this sequence as a slice.
@@ -162,3 +297,12 @@ impl DerefMut for Seq {
         self.as_slice_mut()
     }
 }
+
+impl IntoIterator for Seq {
+    type Item = Cfg;
+    type IntoIter = vec::IntoIter<Cfg>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.cfgs.into_iter()
+    }
+}
@@ -0,0 +1,229 @@
+//! Rendering of e-graphs in the Graphviz DOT format.
+//!
+//! Each e-class with more than one node is drawn as a cluster, with its
+//! canonical node outlined twice. Nodes are coloured by the pass which created
+//! them and edges point from users to operands. Effect tokens are dashed.
+
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Display, Formatter},
+};
+
+use crate::{
+    block::{Block, DecimalEnd, Effect},
+    cfg::Cfg,
+    egraph::{Graph, NodeId, Pass},
+    node::Node,
+    visit::Visitor,
+};
+
+/// A renderer of an e-graph in the DOT format, which draws every node or only
+/// those reachable from roots.
+pub struct Dot<'g> {
+    g: &'g Graph,
+    /// The nodes, from which the drawn nodes are reachable, or `None` to draw
+    /// every node.
+    roots: Option<Vec<NodeId>>,
+}
+
+/// The fill colour for nodes created by each pass in `Pass::ALL`.
+const COLORS: [&str; Pass::ALL.len()] = [
+    "#e0e0e0", "#a6cee3", "#b2df8a", "#fb9a99", "#fdbf6f", "#cab2d6", "#ffff99", "#8dd3c7",
+    "#bebada", "#fb8072", "#80b1d3", "#fccde5", "#ccebc5", "#ffffff",
+];
+
+impl Graph {
+    /// Renders every node of this e-graph in the DOT format.
+    pub fn dot(&self) -> String {
+        Dot::new(self).to_string()
+    }
+}
+
+impl<'g> Dot<'g> {
+    /// Constructs a renderer, which draws every node of the e-graph.
+    pub fn new(g: &'g Graph) -> Self {
+        Dot { g, roots: None }
+    }
+
+    /// Restricts drawing to nodes reachable from the values of the block, in
+    /// addition to any other roots.
+    pub fn reachable_from_block(mut self, block: &Block) -> Self {
+        self.roots
+            .get_or_insert_with(Vec::new)
+            .extend(block.operands());
+        self
+    }
+
+    /// Restricts drawing to nodes reachable from the values of the blocks and
+    /// counts in the CFG, in addition to any other roots.
+    pub fn reachable_from_cfg(mut self, cfg: &Cfg) -> Self {
+        struct Roots<'a>(&'a mut Vec<NodeId>);
+        impl Visitor for Roots<'_> {
+            fn pre(&mut self, cfg: &Cfg, _g: &Graph) -> bool {
+                match cfg {
+                    Cfg::Block(block) => self.0.extend(block.operands()),
+                    Cfg::Repeat { count, .. } => self.0.push(count.id()),
+                    _ => {}
+                }
+                true
+            }
+        }
+        cfg.visit(&mut Roots(self.roots.get_or_insert_with(Vec::new)), self.g);
+        self
+    }
+
+    /// Gets whether to draw each node, indexed by `NodeId::index`. The nodes
+    /// reachable from the roots are closed over operands and e-classes.
+    fn nodes(&self) -> Vec<bool> {
+        let Some(roots) = &self.roots else {
+            return vec![true; self.g.len()];
+        };
+        let mut nodes = vec![false; self.g.len()];
+        let mut stack = roots.clone();
+        while let Some(id) = stack.pop() {
+            if nodes[id.index()] {
+                continue;
+            }
+            nodes[id.index()] = true;
+            stack.extend(self.g[id].operands());
+            if let Some(eclass) = self.g.eclass_of(id) {
+                stack.extend_from_slice(eclass.nodes());
+            }
+        }
+        nodes
+    }
+}
+
+impl Display for Dot<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let g = self.g;
+        let nodes = self.nodes();
+        writeln!(f, "digraph egraph {{")?;
+        writeln!(f, "    node [shape=box, style=filled, fontname=monospace];")?;
+
+        let mut passes = BTreeSet::new();
+        let mut write_node = |f: &mut Formatter<'_>, id: NodeId, indent: &str| {
+            let creator = g.entry(id).creator();
+            passes.insert(pass_index(creator));
+            write!(
+                f,
+                "{indent}n{} [label=\"{}\", fillcolor=\"{}\", tooltip=\"n{} ({})\"",
+                id.index(),
+                Escape(&label(&g[id])),
+                COLORS[pass_index(creator)],
+                id.index(),
+                creator.name(),
+            )?;
+            if g.eclass_of(id).is_some_and(|eclass| eclass.canon() == id) {
+                write!(f, ", peripheries=2, penwidth=2")?;
+            }
+            writeln!(f, "];")
+        };
+        let mut drawn = vec![false; nodes.len()];
+        for id in g.ids().filter(|id| nodes[id.index()]) {
+            if drawn[id.index()] {
+                continue;
+            }
+            let Some(eclass) = g.eclass_of(id) else {
+                write_node(f, id, "    ")?;
+                continue;
+            };
+            let canon = eclass.canon();
+            writeln!(f, "    subgraph cluster_n{} {{", canon.index())?;
+            writeln!(f, "        label=\"e-class of n{}\";", canon.index())?;
+            let mut members = eclass.nodes().to_vec();
+            members.sort_unstable_by_key(NodeId::index);
+            for member in members {
+                if nodes[member.index()] {
+                    write_node(f, member, "        ")?;
+                    drawn[member.index()] = true;
+                }
+            }
+            writeln!(f, "    }}")?;
+        }
+
+        for id in g.ids().filter(|id| nodes[id.index()]) {
+            let node = &g[id];
+            for (i, operand) in node.operands().into_iter().enumerate() {
+                write!(f, "    n{} -> n{}", id.index(), operand.index())?;
+                if i == 0 && matches!(node, Node::Effect(..)) {
+                    write!(f, " [style=dashed]")?;
+                }
+                writeln!(f, ";")?;
+            }
+        }
+
+        writeln!(f, "    subgraph cluster_passes {{")?;
+        writeln!(f, "        label=\"passes\";")?;
+        for pass in passes {
+            writeln!(
+                f,
+                "        pass_{pass} [label=\"{}\", fillcolor=\"{}\"];",
+                Pass::ALL[pass].name(),
+                COLORS[pass],
+            )?;
+        }
+        writeln!(f, "    }}")?;
+        writeln!(f, "}}")
+    }
+}
+
+fn pass_index(pass: Pass) -> usize {
+    Pass::ALL.iter().position(|&p| p == pass).unwrap()
+}
+
+/// A short label for a node, without its operands.
+pub(crate) fn label(node: &Node) -> String {
+    match *node {
+        Node::Copy(offset, block) => format!("p[{}] @b{}", offset.0, block.0),
+        Node::Const(c) => c.to_string(),
+        Node::Input(id) => format!("in{}", id.0),
+        Node::Add(..) => "+".into(),
+        Node::Mul(..) => "*".into(),
+        Node::Choose(_, k) => format!("choose {k}"),
+        Node::Pow(..) => "pow".into(),
+        Node::Block(ref block) => format!("block b{}", block.id.0),
+        Node::Seq(_) => "seq".into(),
+        Node::Loop(..) => "loop".into(),
+        Node::If(..) => "if".into(),
+        Node::Repeat { id, .. } => format!("repeat b{}", id.0),
+        Node::InputCells { step, delimiter } => format!(
+            "input_cells(step: {}, until: '{}')",
+            step.0,
+            delimiter.escape_ascii(),
+        ),
+        Node::NonZero(offset) => format!("p[{}] != 0", offset.0),
+        Node::Entry(block) => format!("entry b{}", block.0),
+        Node::Effect(_, ref effect) => match *effect {
+            Effect::Output(_) => "output".into(),
+            Effect::OutputBytes(ref bytes) => format!("output(\"{}\")", bytes.escape_ascii()),
+            Effect::OutputDecimal(_) => "output_decimal".into(),
+            Effect::Input(_) => "input".into(),
+            Effect::InputUntil { ref delimiters, .. } => {
+                format!("input_until(\"{}\")", delimiters.escape_ascii())
+            }
+            Effect::InputDecimal { ref end, .. } => match end {
+                DecimalEnd::Digits(n) => format!("input_decimal(digits: {n})"),
+                DecimalEnd::Delimiters(delimiters) => {
+                    format!("input_decimal_until(\"{}\")", delimiters.escape_ascii())
+                }
+            },
+            Effect::GuardShift(offset) => format!("guard_shift({})", offset.0),
+        },
+    }
+}
+
+/// Escapes a string for a quoted DOT ID.
+struct Escape<'a>(&'a str);
+
+impl Display for Escape<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for ch in self.0.chars() {
+            match ch {
+                '"' | '\\' => write!(f, "\\{ch}")?,
+                _ => write!(f, "{ch}")?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,32 +1,43 @@
 //! E-graph types.
 
 use std::{
+    cell::Cell,
+    collections::HashMap,
     fmt::{self, Debug, Formatter},
     hash::BuildHasher,
     hint::unreachable_unchecked,
     mem,
     num::NonZero,
     ops::{Deref, Index},
+    slice,
 };
 
 use hashbrown::{DefaultHashBuilder, HashTable};
 
-use crate::node::{BlockId, InputId, Node};
+use crate::{
+    analysis::Facts,
+    cfg::Cfg,
+    node::{BlockId, InputId, Node},
+    verify::VerifyError,
+};
 
 // TODO:
 // - Compare performance of updating nodes in `Eclass::nodes` to point to the
 //   replaced e-class with the current tree of e-classes. The user could still
 //   have an `EclassId` to a replaced e-class, so public APIs would still need
 //   the forwarding loop.
-// - Build debugging infrastructure to visualize nodes in e-classes.
 
-/// An e-graph. Structurally identical nodes receive the same ID. Semantically
-/// equivalent nodes are in the same e-class and considered interchangeable.
-/// Rather than mutating nodes, fresh nodes are inserted and unioned with the
-/// old using [`NodeId::replace`].
+/// An e-graph. Structurally identical nodes receive the same ID. Rather than
+/// mutating nodes, fresh nodes are inserted and recorded as new versions of
+/// the old using [`NodeId::replace`], which places them in the same e-class.
 ///
-/// Note that unlike egg and egglog, this does not use equality saturation and
-/// rewrites are written ad hoc.
+/// A version only replaces the old node at the position in the CFG where the
+/// pass rewrote it, so the nodes of an e-class are not equal in general. Nodes
+/// which are proven equal wherever they are used, as by the rules of
+/// [`Graph::saturate`], are additionally placed in the same equality class
+/// using [`Graph::union`]. Equality saturation and extraction only treat
+/// nodes of an equality class as interchangeable, while passes still rewrite
+/// ad hoc and record versions.
 pub struct Graph {
     /// Deduplicated nodes, identified by `NodeId`.
     nodes: Vec<NodeEntry>, // NodeId -> NodeEntry
@@ -34,6 +45,9 @@ pub struct Graph {
     node_indices: HashTable<u32>, // Node -> NodeId index
     /// E-classes, identified by `EclassId`.
     eclasses: Vec<EclassEntry>, // EclassId -> EclassEntry
+    /// The nodes of each equality class with more than one node, keyed by
+    /// its leader.
+    equalities: HashMap<NodeId, Vec<NodeId>>,
     /// Hasher for `node_ids`.
     hash_builder: DefaultHashBuilder,
     /// The ID of the next basic block.
@@ -42,6 +56,9 @@ pub struct Graph {
     next_input: InputId,
     /// The optimization pass which is currently executing.
     pass: Pass,
+    /// The number of nodes, whose cached facts are up to date. Facts of later
+    /// nodes are recomputed on access, after assumptions change.
+    facts_fresh: Cell<u32>,
 }
 
 /// A node entry in the `Graph`, which knows its e-class and the pass which
@@ -50,7 +67,13 @@ pub struct NodeEntry {
     node: Node,
     hash: u64,
     eclass: Option<EclassId>,
+    /// The leader of the equality class of this node, which is the node itself
+    /// when no other node is proven equal to it.
+    leader: NodeId,
     creator: Pass,
+    /// Facts about the values of this node. For leaves, these are assumptions
+    /// and, for others, they are computed from the facts of the operands.
+    facts: Cell<Facts>,
 }
 
 /// The value-numbered ID of a node in an e-graph.
@@ -64,9 +87,9 @@ pub struct NodeRef<'g> {
     graph: &'g Graph,
 }
 
-/// An equivalence class of nodes, i.e., a set of nodes, which are equivalent
-/// and can be used interchangeably. One node is selected as the canonical
-/// representation of the e-class.
+/// An e-class, i.e., the set of versions of a value, which have replaced each
+/// other. One node is selected as the canonical representation of the e-class,
+/// which is the latest version.
 #[derive(Debug)]
 pub struct Eclass {
     canon: NodeId,
@@ -97,10 +120,73 @@ pub enum Pass {
     QuasiInvariantPeel,
     /// Constant copy propagation.
     CopyConst,
+    /// Unrolling loops with a constant number of iterations
+    Unroll,
+    /// Dead store elimination
+    DeadStore,
+    /// Adjacent loop fusion and redundant loop elimination
+    Fuse,
+    /// Propagation of value ranges and known bits
+    Facts,
+    /// Coalescing constant outputs into byte strings
+    CoalesceOutput,
+    /// Recognizing loops, which read until a delimiter
+    InputLoop,
+    /// Recognizing decimal output and input idioms
+    DecimalIo,
+    /// Reordering independent effects in chains of effect tokens
+    ReorderEffects,
+    /// Equality saturation with declarative rewrite rules
+    EqualitySaturation,
     /// An unknown pass.
     Unknown,
 }
 
+impl Pass {
+    /// All passes.
+    pub const ALL: [Pass; 14] = [
+        Pass::Parse,
+        Pass::AddLoopToMul,
+        Pass::QuasiInvariantPeel,
+        Pass::CopyConst,
+        Pass::Unroll,
+        Pass::DeadStore,
+        Pass::Fuse,
+        Pass::Facts,
+        Pass::CoalesceOutput,
+        Pass::InputLoop,
+        Pass::DecimalIo,
+        Pass::ReorderEffects,
+        Pass::EqualitySaturation,
+        Pass::Unknown,
+    ];
+
+    /// The short name of this pass, as used in pipelines.
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::Parse => "parse",
+            Pass::AddLoopToMul => "closed-form",
+            Pass::QuasiInvariantPeel => "peel",
+            Pass::CopyConst => "copy-const",
+            Pass::Unroll => "unroll",
+            Pass::DeadStore => "dead-store",
+            Pass::Fuse => "fuse",
+            Pass::Facts => "facts",
+            Pass::CoalesceOutput => "coalesce-output",
+            Pass::InputLoop => "input-loop",
+            Pass::DecimalIo => "decimal-io",
+            Pass::ReorderEffects => "reorder-effects",
+            Pass::EqualitySaturation => "saturate",
+            Pass::Unknown => "unknown",
+        }
+    }
+
+    /// Gets the pass with the short name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Pass::ALL.into_iter().find(|pass| pass.name() == name)
+    }
+}
+
 impl Graph {
     /// Constructs a new, empty e-graph.
     #[inline]
@@ -109,10 +195,12 @@ impl Graph {
             nodes: Vec::new(),
             node_indices: HashTable::new(),
             eclasses: Vec::new(),
+            equalities: HashMap::new(),
             hash_builder: DefaultHashBuilder::default(),
             next_block: BlockId(0),
             next_input: InputId(0),
             pass: Pass::Unknown,
+            facts_fresh: Cell::new(0),
         }
     }
 
@@ -121,6 +209,7 @@ impl Graph {
     /// `NodeId`.
     pub fn insert(&mut self, node: Node) -> NodeId {
         self.assert_node(&node);
+        let facts = Facts::of_node(&node, |id| self.facts(id));
         let hash = self.hash_builder.hash_one(&node);
         let entry = self.node_indices.entry(
             hash,
@@ -138,24 +227,34 @@ impl Graph {
             },
         );
         let index = entry.or_insert_with(|| {
+            if self.facts_fresh.get() as usize == self.nodes.len() {
+                self.facts_fresh.set(self.facts_fresh.get() + 1);
+            }
+            let Ok(len) = u32::try_from(self.nodes.len() + 1) else {
+                Self::node_overflow()
+            };
             self.nodes.push(NodeEntry {
                 node,
                 hash,
                 eclass: None,
+                // SAFETY: The index is less than `u32::MAX`.
+                leader: unsafe { NodeId::from_index(len - 1) },
                 creator: self.pass,
+                facts: Cell::new(facts),
             });
-            let Ok(id) = u32::try_from(self.nodes.len()) else {
-                Self::node_overflow()
-            };
-            id - 1
+            len - 1
         });
         // SAFETY: Indexes are only constructed above and are guaranteed to be
         // non-zero.
         unsafe { NodeId::from_index(*index.get()) }
     }
 
-    /// Looks up the ID of this node, if it has already been inserted.
-    pub fn find(&self, node: &Node) -> Option<NodeId> {
+    /// Looks up the ID of this node, if it has already been inserted. Panics
+    /// if the node is not of the requested type.
+    pub fn find<T: TypedId>(&self, node: &Node) -> Option<T> {
+        if !T::accepts(node) {
+            panic!("{node:?} is not a {}", T::KIND);
+        }
         let hash = self.hash_builder.hash_one(node);
         let index = self.node_indices.find(hash, |&index| {
             // SAFETY: Same as `Graph::insert`.
@@ -163,7 +262,7 @@ impl Graph {
             &entry.node == node
         });
         // SAFETY: Same as `Graph::insert`.
-        index.map(|&index| unsafe { NodeId::from_index(index) })
+        index.map(|&index| wrap(unsafe { NodeId::from_index(index) }))
     }
 
     /// Generates a fresh ID for the next basic block.
@@ -174,24 +273,111 @@ impl Graph {
     }
 
     /// Inserts a `Node::Input` with a fresh ID.
-    pub fn fresh_input(&mut self) -> NodeId {
-        let id = self.insert(Node::Input(self.next_input));
+    pub fn fresh_input(&mut self) -> InputNode {
+        let id = Node::Input(self.next_input).insert_ideal(self);
         self.next_input = InputId(self.next_input.0 + 1);
         id
     }
 
+    /// Inserts a `Node::Input` with the given ID and ensures that fresh inputs
+    /// receive greater IDs.
+    pub fn input(&mut self, id: InputId) -> InputNode {
+        self.next_input = InputId(self.next_input.0.max(id.0 + 1));
+        Node::Input(id).insert_ideal(self)
+    }
+
+    /// The IDs, which the next fresh basic block and input receive.
+    pub(crate) fn next_ids(&self) -> (BlockId, InputId) {
+        (self.next_block, self.next_input)
+    }
+
+    /// Sets the IDs, which the next fresh basic block and input receive.
+    pub(crate) fn set_next_ids(&mut self, block: BlockId, input: InputId) {
+        self.next_block = block;
+        self.next_input = input;
+    }
+
+    /// Gets the ID of the node at the 0-based index, if it is in this e-graph.
+    pub(crate) fn node_id(&self, index: usize) -> Option<NodeId> {
+        // SAFETY: Indices of nodes are less than `u32::MAX`.
+        (index < self.nodes.len()).then(|| unsafe { NodeId::from_index(index as u32) })
+    }
+
+    /// Returns an iterator over the IDs of all nodes in this e-graph, in
+    /// insertion order.
+    pub fn ids(&self) -> impl Iterator<Item = NodeId> + '_ {
+        // SAFETY: Indices are in bounds and less than `u32::MAX`.
+        (0..self.nodes.len() as u32).map(|index| unsafe { NodeId::from_index(index) })
+    }
+
     /// Gets a recursive reference to a node in the e-graph.
-    pub fn get(&self, id: NodeId) -> NodeRef<'_> {
-        NodeRef { id, graph: self }
+    pub fn get(&self, id: impl Into<NodeId>) -> NodeRef<'_> {
+        NodeRef {
+            id: id.into(),
+            graph: self,
+        }
     }
 
     /// Gets the entry for a node.
     #[inline]
-    pub fn entry(&self, id: NodeId) -> &NodeEntry {
+    pub fn entry(&self, id: impl Into<NodeId>) -> &NodeEntry {
+        let id = id.into();
         self.assert_node_id(id);
         &self.nodes[id.index()]
     }
 
+    /// Gets the root e-class of a node, or `None` if it is in a singleton
+    /// e-class.
+    pub fn eclass_of(&self, id: impl Into<NodeId>) -> Option<&Eclass> {
+        Some(self.eclass(self.entry(id).eclass?).1)
+    }
+
+    /// Gets the canonical node of the e-class containing this node, i.e., its
+    /// latest version.
+    pub fn canonical<T: TypedId>(&self, id: T) -> T {
+        // E-classes only contain nodes of the same kind, so the canonical node
+        // has the type of the node.
+        self.eclass_of(id).map_or(id, |eclass| wrap(eclass.canon()))
+    }
+
+    /// Gets all versions of this node, i.e., the nodes in its e-class, in
+    /// insertion order.
+    pub fn versions<T: TypedId>(&self, id: T) -> Vec<T> {
+        let mut nodes = match self.eclass_of(id) {
+            Some(eclass) => eclass.nodes().iter().map(|&id| wrap(id)).collect(),
+            None => vec![id],
+        };
+        nodes.sort_unstable_by_key(|&id: &T| id.into().index());
+        nodes
+    }
+
+    /// Gets the leader of the equality class containing this node. Two nodes
+    /// are proven equal exactly when they have the same leader.
+    pub fn leader<T: TypedId>(&self, id: T) -> T {
+        // Equality classes only contain nodes of the same kind, so the leader
+        // has the type of the node.
+        wrap(self.entry(id).leader)
+    }
+
+    /// Gets the nodes, which are proven equal to this node, including itself.
+    pub fn equal_nodes(&self, id: impl Into<NodeId>) -> &[NodeId] {
+        let leader = &self.entry(id).leader;
+        match self.equalities.get(leader) {
+            Some(nodes) => nodes,
+            None => slice::from_ref(leader),
+        }
+    }
+
+    /// Gets the value of a constant node, which is proven equal to this node,
+    /// if there is one.
+    pub fn const_value(&self, id: impl Into<NodeId>) -> Option<u8> {
+        let mut nodes = self.equal_nodes(id).iter();
+        nodes.find_map(|&node| match self[node] {
+            Node::Const(c) => Some(c),
+            _ => None,
+        })
+    }
+
     /// Gets a reference to the root e-class for this ID.
     fn eclass(&self, mut eid: EclassId) -> (EclassId, &Eclass) {
         self.assert_eclass_id(eid);
@@ -227,6 +413,10 @@ impl Graph {
     fn replace(&mut self, origin: NodeId, canon: NodeId) {
         self.assert_node_id(origin);
         self.assert_node_id(canon);
+        let (origin_node, canon_node) = (&self[origin], &self[canon]);
+        if !origin_node.same_kind(canon_node) {
+            panic!("union of nodes of different kinds");
+        }
         let eid1 = self.nodes[origin.index()].eclass;
         let eid2 = self.nodes[canon.index()].eclass;
         match (eid1, eid2) {
@@ -277,6 +467,211 @@ impl Graph {
         }
     }
 
+    /// Records that `a` and `b` are equal wherever they are used, by unifying
+    /// their equality classes. Their e-classes of versions are unchanged.
+    /// Returns whether they were not already proven equal.
+    pub fn union<T: TypedId>(&mut self, a: T, b: T) -> bool {
+        let (a, b) = (a.into(), b.into());
+        let (node_a, node_b) = (&self[a], &self[b]);
+        if !node_a.same_kind(node_b) {
+            panic!("union of nodes of different kinds");
+        }
+        let (leader_a, leader_b) = (self.entry(a).leader, self.entry(b).leader);
+        if leader_a == leader_b {
+            return false;
+        }
+        let mut nodes_a = self.equalities.remove(&leader_a);
+        let mut nodes_b = self.equalities.remove(&leader_b);
+        let nodes_a = nodes_a.get_or_insert_with(|| vec![leader_a]);
+        let nodes_b = nodes_b.get_or_insert_with(|| vec![leader_b]);
+        // Relabel the smaller class, so each node is relabeled at most
+        // logarithmically many times.
+        let (leader, nodes, other) = if nodes_a.len() >= nodes_b.len() {
+            (leader_a, nodes_a, nodes_b)
+        } else {
+            (leader_b, nodes_b, nodes_a)
+        };
+        for &id in other.iter() {
+            self.nodes[id.index()].leader = leader;
+        }
+        nodes.append(other);
+        self.equalities.insert(leader, mem::take(nodes));
+        true
+    }
+
+    /// Gets the e-class for an ID, or the ID of the e-class it was unioned
+    /// into, without following the chain of unions.
+    pub(crate) fn eclass_entry(&self, eid: EclassId) -> Result<&Eclass, EclassId> {
+        self.assert_eclass_id(eid);
+        match &self.eclasses[eid.index()] {
+            EclassEntry::Eclass(eclass) => Ok(eclass),
+            &EclassEntry::Union(eid) => Err(eid),
+        }
+    }
+
+    /// Restores the e-classes of a deserialized e-graph, as returned by
+    /// [`Graph::eclass_entry`], and the e-class of each node. The IDs must be
+    /// in bounds, but the result must still be verified.
+    pub(crate) fn restore_eclasses(
+        &mut self,
+        entries: Vec<Result<Eclass, EclassId>>,
+        node_eclasses: Vec<Option<EclassId>>,
+    ) {
+        assert_eq!(node_eclasses.len(), self.nodes.len());
+        self.eclasses = entries
+            .into_iter()
+            .map(|entry| match entry {
+                Ok(eclass) => EclassEntry::Eclass(eclass),
+                Err(eid) => EclassEntry::Union(eid),
+            })
+            .collect();
+        for (entry, eid) in self.nodes.iter_mut().zip(node_eclasses) {
+            entry.eclass = eid;
+        }
+    }
+
+    /// Restores the equality classes of a deserialized e-graph from the leader
+    /// of each node, or `None` for a node, which leads itself. The IDs must be
+    /// in bounds, but the result must still be verified.
+    pub(crate) fn restore_equalities(&mut self, leaders: Vec<Option<NodeId>>) {
+        assert_eq!(leaders.len(), self.nodes.len());
+        self.equalities.clear();
+        for (index, leader) in leaders.into_iter().enumerate() {
+            // SAFETY: Indices of nodes are less than `u32::MAX`.
+            let id = unsafe { NodeId::from_index(index as u32) };
+            let leader = leader.unwrap_or(id);
+            self.nodes[index].leader = leader;
+            if leader != id {
+                (self.equalities.entry(leader))
+                    .or_insert_with(|| vec![leader])
+                    .push(id);
+            }
+        }
+    }
+
+    /// Removes the nodes, which are not reachable from the CFG, and the
+    /// e-classes, which are left with fewer than two nodes. The remaining nodes
+    /// are renumbered in order and the CFG is remapped to the new IDs. Nodes
+    /// reachable from the CFG keep their canonical nodes, so the CFG can still
+    /// be optimized, but any other `NodeId` or `EclassId` is invalidated.
+    /// Returns the number of removed nodes.
+    ///
+    /// When `keep_versions` is set, the versions of control flow, which passes
+    /// recorded with [`Cfg::rewrite`], are also live, along with the nodes they
+    /// use. Otherwise, all control-flow nodes are removed, since the CFG itself
+    /// is not stored in the e-graph.
+    pub fn compact(&mut self, cfg: &mut Cfg, keep_versions: bool) -> usize {
+        let mut live = vec![false; self.nodes.len()];
+        let mut stack = Vec::new();
+        cfg.remap(&mut |id| {
+            self.assert_node_id(id);
+            stack.push(id);
+            id
+        });
+        if keep_versions {
+            stack.extend(
+                self.ids()
+                    .filter(|&id| self[id].is_ctrl() && self.entry(id).eclass.is_some()),
+            );
+        }
+        while let Some(id) = stack.pop() {
+            if live[id.index()] {
+                continue;
+            }
+            live[id.index()] = true;
+            stack.extend(self.nodes[id.index()].node.operands());
+            stack.push(self.canonical(id));
+        }
+
+        let mut map = vec![None; self.nodes.len()];
+        let mut len = 0;
+        for (index, &live) in live.iter().enumerate() {
+            if live {
+                // SAFETY: The new index is no greater than the old index.
+                map[index] = Some(unsafe { NodeId::from_index(len) });
+                len += 1;
+            }
+        }
+        let mut remap = |id: NodeId| map[id.index()].expect("live node uses removed node");
+
+        // Renumber the e-classes of live nodes, keeping only live members.
+        let mut roots = HashMap::new();
+        let mut eclasses = Vec::new();
+        let mut node_eclasses = vec![None; self.nodes.len()];
+        for (index, entry) in self.nodes.iter().enumerate() {
+            let Some(eid) = entry.eclass.filter(|_| live[index]) else {
+                continue;
+            };
+            let (root, eclass) = self.eclass(eid);
+            node_eclasses[index] = *roots.entry(root.index()).or_insert_with(|| {
+                let nodes = (eclass.nodes.iter())
+                    .filter(|id| live[id.index()])
+                    .map(|&id| remap(id))
+                    .collect::<Vec<_>>();
+                if nodes.len() < 2 {
+                    return None;
+                }
+                eclasses.push(EclassEntry::Eclass(Eclass {
+                    canon: remap(eclass.canon),
+                    nodes,
+                }));
+                Some(EclassId::from_index(eclasses.len() as u32 - 1))
+            });
+        }
+
+        // Keep the live nodes of each equality class, which are led by the old
+        // leader, if it is live, or otherwise by the first live node.
+        let mut leaders = vec![None; self.nodes.len()];
+        let mut equalities = HashMap::new();
+        for (&leader, nodes) in &self.equalities {
+            let mut nodes = (nodes.iter())
+                .filter(|id| live[id.index()])
+                .copied()
+                .collect::<Vec<_>>();
+            nodes.sort_unstable_by_key(NodeId::index);
+            let Some(&first) = nodes.first() else {
+                continue;
+            };
+            let leader = remap(if live[leader.index()] { leader } else { first });
+            for id in &mut nodes {
+                leaders[id.index()] = Some(leader);
+                *id = remap(*id);
+            }
+            if nodes.len() >= 2 {
+                equalities.insert(leader, nodes);
+            }
+        }
+
+        let fresh = self.facts_fresh.get() as usize;
+        let old_len = self.nodes.len();
+        let nodes = mem::take(&mut self.nodes);
+        for (index, mut entry) in nodes.into_iter().enumerate() {
+            if !live[index] {
+                continue;
+            }
+            entry.node.remap(&mut remap);
+            entry.hash = self.hash_builder.hash_one(&entry.node);
+            entry.eclass = node_eclasses[index];
+            // SAFETY: The new index is no greater than the old index.
+            let id = unsafe { NodeId::from_index(self.nodes.len() as u32) };
+            entry.leader = leaders[index].unwrap_or(id);
+            self.nodes.push(entry);
+        }
+        self.equalities = equalities;
+        self.facts_fresh
+            .set(live[..fresh].iter().filter(|&&live| live).count() as u32);
+        self.eclasses = eclasses;
+        self.node_indices.clear();
+        for (index, entry) in self.nodes.iter().enumerate() {
+            self.node_indices
+                .insert_unique(entry.hash, index as u32, |&index| {
+                    self.nodes[index as usize].hash
+                });
+        }
+        cfg.remap(&mut remap);
+        old_len - self.nodes.len()
+    }
+
     /// Returns the number of nodes in this e-graph.
     #[inline]
     pub fn len(&self) -> usize {
@@ -302,18 +697,129 @@ impl Graph {
         self.pass = pass;
     }
 
+    /// The optimization pass which is currently executing.
+    #[inline]
+    pub fn pass(&self) -> Pass {
+        self.pass
+    }
+
+    /// Gets facts about the values of a node.
+    pub fn facts(&self, id: impl Into<NodeId>) -> Facts {
+        let id = id.into();
+        self.assert_node_id(id);
+        self.sweep_facts(id);
+        self.nodes[id.index()].facts.get()
+    }
+
+    /// Records a proven assumption about the values of a copy or input, which
+    /// refines the facts of nodes that use it. Since a copy reads a cell on
+    /// entry to its block, the assumption must hold wherever the block
+    /// executes.
+    pub fn assume(&mut self, id: impl Into<NodeId>, facts: Facts) {
+        let id = id.into();
+        self.assert_node_id(id);
+        let entry = &self.nodes[id.index()];
+        assert!(
+            matches!(entry.node, Node::Copy(..) | Node::Input(_)),
+            "assumption on non-leaf node",
+        );
+        if let Some(facts) = entry.facts.get().meet(&facts) {
+            entry.facts.set(facts);
+            let fresh = self.facts_fresh.get().min(id.index() as u32 + 1);
+            self.facts_fresh.set(fresh);
+        }
+    }
+
+    /// Recomputes outdated facts of nodes up to and including this node.
+    /// Operands have lower indices than their users, so facts are computed in
+    /// order.
+    fn sweep_facts(&self, id: NodeId) {
+        let end = id.index() as u32 + 1;
+        let fresh = self.facts_fresh.get();
+        if fresh >= end {
+            return;
+        }
+        for entry in &self.nodes[fresh as usize..end as usize] {
+            if let Node::Add(..) | Node::Mul(..) = entry.node {
+                let facts = Facts::of_node(&entry.node, |id| self.nodes[id.index()].facts.get());
+                entry.facts.set(facts);
+            }
+        }
+        self.facts_fresh.set(end);
+    }
+
+    /// Checks that every chain of e-class unions terminates, that every node is
+    /// in the e-class it refers to, and that every node is led by a node of the
+    /// same kind, which leads itself.
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        for index in 0..self.eclasses.len() {
+            let start = EclassId(NonZero::new(index as u32 + 1).unwrap());
+            let mut eid = start;
+            for _ in 0..=self.eclasses.len() {
+                match self.eclasses.get(eid.index()) {
+                    Some(EclassEntry::Eclass(_)) => break,
+                    Some(&EclassEntry::Union(eid2)) => eid = eid2,
+                    None => return Err(VerifyError::UnionCycle(start)),
+                }
+            }
+            let Some(EclassEntry::Eclass(eclass)) = self.eclasses.get(eid.index()) else {
+                return Err(VerifyError::UnionCycle(start));
+            };
+            if !eclass.nodes.contains(&eclass.canon) {
+                return Err(VerifyError::NotInEclass(eclass.canon));
+            }
+        }
+        for id in self.ids() {
+            if let Some(eid) = self.entry(id).eclass {
+                if !self.eclass(eid).1.nodes.contains(&id) {
+                    return Err(VerifyError::NotInEclass(id));
+                }
+            }
+            let leader = self.entry(id).leader;
+            let (node, leader_node) = (&self[id], &self[leader]);
+            if self.entry(leader).leader != leader || !node.same_kind(leader_node) {
+                return Err(VerifyError::NotEqual(id));
+            }
+        }
+        Ok(())
+    }
+
     fn assert_node(&self, node: &Node) {
         match *node {
-            Node::Copy(..) | Node::Const(_) | Node::Input(_) => {}
-            Node::Add(lhs, rhs) | Node::Mul(lhs, rhs) => {
+            Node::Copy(..)
+            | Node::Const(_)
+            | Node::Input(_)
+            | Node::InputCells { .. }
+            | Node::NonZero(_) => {}
+            Node::Add(lhs, rhs) | Node::Mul(lhs, rhs) | Node::Pow(lhs, rhs) => {
                 self.assert_node_id(lhs);
                 self.assert_node_id(rhs);
             }
+            Node::Choose(n, _) => self.assert_node_id(n),
+            Node::Loop(cond, body) | Node::If(cond, body) => {
+                self.assert_node_id(cond);
+                self.assert_node_id(body);
+            }
+            Node::Repeat { count, body, .. } => {
+                self.assert_node_id(count);
+                self.assert_node_id(body);
+            }
+            Node::Seq(ref seq) => seq.iter().for_each(|&id| self.assert_node_id(id)),
+            Node::Block(ref block) => block.operands().for_each(|id| self.assert_node_id(id)),
+            Node::Entry(_) => {}
+            Node::Effect(token, ref effect) => {
+                self.assert_node_id(token);
+                effect
+                    .nodes()
+                    .iter()
+                    .for_each(|&id| self.assert_node_id(id));
+            }
         }
     }
 
     #[inline]
-    fn assert_node_id(&self, id: NodeId) {
+    fn assert_node_id(&self, id: impl Into<NodeId>) {
+        let id = id.into();
         if id.index() >= self.nodes.len() {
             Self::bad_node_id();
         }
@@ -422,6 +928,214 @@ impl NodeId {
     }
 }
 
+/// A typed ID for the nodes of some variants. Conversions from `NodeId` are
+/// checked, so a typed ID always refers to a node of its variants.
+pub trait TypedId: sealed::Wrap + Copy + Into<NodeId> {
+    /// A description of the variants, for diagnostics.
+    const KIND: &'static str;
+
+    /// Returns whether the node is one of the variants of this type.
+    fn accepts(node: &Node) -> bool;
+
+    /// Converts a node ID to this type, if its node is one of the variants.
+    fn new(id: NodeId, g: &Graph) -> Option<Self> {
+        Self::accepts(&g[id]).then(|| wrap(id))
+    }
+}
+
+mod sealed {
+    use super::NodeId;
+
+    /// A witness that a node ID has been checked, which only this crate can
+    /// construct.
+    pub struct Checked(pub(crate) ());
+
+    pub trait Wrap {
+        /// Wraps a node ID, which has been checked.
+        fn wrap(id: NodeId, checked: Checked) -> Self;
+    }
+}
+
+/// Wraps a node ID in a typed ID. The node must be one of the variants of the
+/// type.
+#[inline]
+pub(crate) fn wrap<T: TypedId>(id: NodeId) -> T {
+    T::wrap(id, sealed::Checked(()))
+}
+
+/// The ID of a byte computation node, i.e., `Copy`, `Const`, `Input`, `Add`,
+/// `Mul`, `Choose`, or `Pow`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct ByteNode(NodeId);
+
+/// The ID of a `Node::Input`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct InputNode(ByteNode);
+
+/// The ID of a control-flow node, i.e., `Block`, `Seq`, `Loop`, `If`,
+/// `Repeat`, or `InputCells`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CtrlNode(NodeId);
+
+/// The ID of a condition of a loop or if, i.e., `NonZero`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BoolNode(NodeId);
+
+/// The ID of an effect token, i.e., `Entry` or `Effect`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EffectNode(NodeId);
+
+impl sealed::Wrap for NodeId {
+    fn wrap(id: NodeId, _checked: sealed::Checked) -> Self {
+        id
+    }
+}
+
+impl TypedId for NodeId {
+    const KIND: &'static str = "node";
+
+    fn accepts(_node: &Node) -> bool {
+        true
+    }
+}
+
+macro_rules! typed_id {
+    ($Id:ident, $kind:literal, |$node:ident| $accepts:expr, |$id:ident| $wrap:expr) => {
+        impl $Id {
+            /// Gets the untyped ID.
+            #[inline]
+            pub fn id(self) -> NodeId {
+                self.into()
+            }
+
+            /// Returns the 0-based index of this ID.
+            #[inline]
+            pub fn index(self) -> usize {
+                self.id().index()
+            }
+
+            /// Unifies `self` and `canon` into the same e-class and makes
+            /// `canon` the canonical node.
+            pub fn replace(self, canon: Self, g: &mut Graph) {
+                g.replace(self.id(), canon.id());
+            }
+
+            /// Replaces this ID with a node of the same kind.
+            pub(crate) fn remap(self, f: &mut impl FnMut(NodeId) -> NodeId) -> Self {
+                wrap(f(self.id()))
+            }
+        }
+
+        impl sealed::Wrap for $Id {
+            #[inline]
+            fn wrap($id: NodeId, _checked: sealed::Checked) -> Self {
+                $wrap
+            }
+        }
+
+        impl TypedId for $Id {
+            const KIND: &'static str = $kind;
+
+            #[inline]
+            fn accepts($node: &Node) -> bool {
+                $accepts
+            }
+        }
+
+        impl Index<$Id> for Graph {
+            type Output = Node;
+
+            #[inline]
+            fn index(&self, id: $Id) -> &Self::Output {
+                &self[id.id()]
+            }
+        }
+
+        impl Debug for $Id {
+            fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                f.debug_tuple(stringify!($Id)).field(&self.index()).finish()
+            }
+        }
+    };
+}
+
+typed_id!(ByteNode, "byte computation", |node| node.is_byte(), |id| {
+    ByteNode(id)
+});
+typed_id!(
+    InputNode,
+    "input",
+    |node| matches!(node, Node::Input(_)),
+    |id| InputNode(ByteNode(id))
+);
+typed_id!(CtrlNode, "control-flow node", |node| node.is_ctrl(), |id| {
+    CtrlNode(id)
+});
+typed_id!(BoolNode, "condition", |node| node.is_bool(), |id| {
+    BoolNode(id)
+});
+typed_id!(EffectNode, "effect token", |node| node.is_effect(), |id| {
+    EffectNode(id)
+});
+
+impl From<ByteNode> for NodeId {
+    #[inline]
+    fn from(id: ByteNode) -> Self {
+        id.0
+    }
+}
+
+impl From<InputNode> for NodeId {
+    #[inline]
+    fn from(id: InputNode) -> Self {
+        id.0 .0
+    }
+}
+
+impl From<InputNode> for ByteNode {
+    #[inline]
+    fn from(id: InputNode) -> Self {
+        id.0
+    }
+}
+
+impl From<CtrlNode> for NodeId {
+    #[inline]
+    fn from(id: CtrlNode) -> Self {
+        id.0
+    }
+}
+
+impl From<BoolNode> for NodeId {
+    #[inline]
+    fn from(id: BoolNode) -> Self {
+        id.0
+    }
+}
+
+impl From<EffectNode> for NodeId {
+    #[inline]
+    fn from(id: EffectNode) -> Self {
+        id.0
+    }
+}
+
+impl InputNode {
+    /// Gets the ID as a byte computation.
+    #[inline]
+    pub fn byte(self) -> ByteNode {
+        self.0
+    }
+
+    /// Gets a reference to the ID as a byte computation.
+    #[inline]
+    pub fn as_byte(&self) -> &ByteNode {
+        &self.0
+    }
+}
+
 impl<'g> NodeRef<'g> {
     /// Gets the ID of this node.
     pub fn id(&self) -> NodeId {
@@ -439,9 +1153,9 @@ impl<'g> NodeRef<'g> {
     }
 
     /// Gets a recursive reference to a node in the e-graph.
-    pub fn get(&self, id: NodeId) -> NodeRef<'g> {
+    pub fn get(&self, id: impl Into<NodeId>) -> NodeRef<'g> {
         NodeRef {
-            id,
+            id: id.into(),
             graph: self.graph,
         }
     }
@@ -456,6 +1170,11 @@ impl Deref for NodeRef<'_> {
 }
 
 impl Eclass {
+    /// Constructs an e-class from its canonical node and all of its nodes.
+    pub(crate) fn new(canon: NodeId, nodes: Vec<NodeId>) -> Self {
+        Eclass { canon, nodes }
+    }
+
     /// The canonical node which represents this e-class.
     #[inline]
     pub fn canon(&self) -> NodeId {
@@ -470,9 +1189,15 @@ impl Eclass {
 }
 
 impl EclassId {
+    /// Constructs an e-class ID from a 0-based index.
+    #[inline]
+    pub(crate) fn from_index(index: u32) -> Self {
+        EclassId(NonZero::new(index + 1).expect("e-class index overflow"))
+    }
+
     /// Returns the 0-based index of this ID.
     #[inline]
-    fn index(self) -> usize {
+    pub(crate) fn index(self) -> usize {
         self.0.get() as usize - 1
     }
 }
@@ -497,7 +1222,9 @@ impl Debug for NodeEntry {
         f.debug_struct("NodeEntry")
             .field("node", &self.node)
             .field("eclass", &self.eclass)
+            .field("leader", &self.leader)
             .field("creator", &self.creator)
+            .field("facts", &self.facts.get())
             .finish()
     }
 }
@@ -0,0 +1,295 @@
+//! Cost-based extraction of the best node from each equality class.
+
+use std::collections::HashMap;
+
+use crate::{
+    cfg::Cfg,
+    egraph::{self, ByteNode, Graph, NodeId, TypedId},
+    node::Node,
+};
+
+/// A model for the cost of evaluating nodes.
+pub trait CostModel {
+    /// The cost of the operation of this node, excluding the cost of its
+    /// operands.
+    fn cost(&self, node: &Node) -> u64;
+}
+
+/// A cost model, which counts each arithmetic operation as 1.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OpCount;
+
+/// A cost model with configurable costs for each kind of node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WeightedCost {
+    /// The cost of reading a cell.
+    pub copy: u64,
+    /// The cost of a constant.
+    pub constant: u64,
+    /// The cost of referencing an input.
+    pub input: u64,
+    /// The cost of an addition.
+    pub add: u64,
+    /// The cost of a multiplication.
+    pub mul: u64,
+}
+
+/// The cheapest node of each equality class, according to a cost model.
+#[derive(Clone, Debug)]
+pub struct Extraction {
+    /// The equality class of each node at the time of extraction, identified
+    /// by its leader. Indexed by node index.
+    classes: Vec<NodeId>,
+    /// The cheapest node and its total cost for each equality class.
+    best: HashMap<NodeId, (NodeId, u64)>,
+}
+
+impl CostModel for OpCount {
+    fn cost(&self, node: &Node) -> u64 {
+        match node {
+            Node::Copy(..) | Node::Const(_) | Node::Input(_) => 0,
+            Node::Add(..) | Node::Mul(..) | Node::Choose(..) | Node::Pow(..) => 1,
+            _ => 0,
+        }
+    }
+}
+
+impl CostModel for WeightedCost {
+    fn cost(&self, node: &Node) -> u64 {
+        match node {
+            Node::Copy(..) => self.copy,
+            Node::Const(_) => self.constant,
+            Node::Input(_) => self.input,
+            Node::Add(..) => self.add,
+            // Binomial coefficients and powers are computed with several
+            // multiplications, but are costed as one.
+            Node::Mul(..) | Node::Choose(..) | Node::Pow(..) => self.mul,
+            // Control-flow, condition, and effect nodes are not extracted.
+            _ => 0,
+        }
+    }
+}
+
+impl Default for WeightedCost {
+    /// Costs, where a multiplication is several times more expensive than an
+    /// addition.
+    fn default() -> Self {
+        WeightedCost {
+            copy: 1,
+            constant: 0,
+            input: 1,
+            add: 1,
+            mul: 4,
+        }
+    }
+}
+
+impl Graph {
+    /// Selects the cheapest node in each equality class. The cost of a node is
+    /// its own cost plus the costs of the cheapest nodes of its operands, i.e.,
+    /// shared subterms are counted for each use. Ties are broken in favor of
+    /// the leader, then the earliest inserted node. Versions of a node in its
+    /// e-class are not considered, since they are not equal to it.
+    pub fn extract(&self, model: &impl CostModel) -> Extraction {
+        let classes = self.ids().map(|id| self.leader(id)).collect::<Vec<_>>();
+        let mut best = HashMap::<NodeId, (NodeId, u64)>::new();
+        let class_cost = |best: &HashMap<NodeId, (NodeId, u64)>, id: ByteNode| {
+            best.get(&classes[id.index()]).map(|&(_, cost)| cost)
+        };
+        loop {
+            let mut changed = false;
+            for id in self.ids() {
+                let node = &self[id];
+                let operands = match *node {
+                    Node::Copy(..) | Node::Const(_) | Node::Input(_) => Some(0),
+                    Node::Add(lhs, rhs) | Node::Mul(lhs, rhs) | Node::Pow(lhs, rhs) => {
+                        class_cost(&best, lhs)
+                            .zip(class_cost(&best, rhs))
+                            .map(|(lhs, rhs)| lhs.saturating_add(rhs))
+                    }
+                    Node::Choose(n, _) => class_cost(&best, n),
+                    _ => None,
+                };
+                let Some(operands) = operands else {
+                    continue;
+                };
+                let cost = model.cost(node).saturating_add(operands);
+                let class = classes[id.index()];
+                let better = match best.get(&class) {
+                    None => true,
+                    Some(&(best_id, best_cost)) => {
+                        cost < best_cost || cost == best_cost && id == class && best_id != class
+                    }
+                };
+                if better {
+                    best.insert(class, (id, cost));
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        Extraction { classes, best }
+    }
+}
+
+impl Extraction {
+    /// Gets the cheapest node, which is equal to this node. Nodes inserted
+    /// after extraction are their own best.
+    pub fn best<T: TypedId>(&self, id: T) -> T {
+        // The best node is in the same equality class, so it has the same
+        // type.
+        self.classes
+            .get(id.into().index())
+            .and_then(|class| self.best.get(class))
+            .map_or(id, |&(best, _)| egraph::wrap(best))
+    }
+
+    /// Gets the total cost of the cheapest node, which is equal to this node,
+    /// or `None` if it was inserted after extraction.
+    pub fn cost(&self, id: impl Into<NodeId>) -> Option<u64> {
+        let class = self.classes.get(id.into().index())?;
+        self.best.get(class).map(|&(_, cost)| cost)
+    }
+
+    /// Inserts the cheapest term, which is equal to this node, where every
+    /// operand is recursively the cheapest of its equality class. The term is
+    /// unioned with the node, without changing its e-class of versions.
+    pub fn build(&self, id: ByteNode, g: &mut Graph) -> ByteNode {
+        self.build_memo(id, g, &mut HashMap::new())
+    }
+
+    fn build_memo(
+        &self,
+        id: ByteNode,
+        g: &mut Graph,
+        memo: &mut HashMap<ByteNode, ByteNode>,
+    ) -> ByteNode {
+        let best = self.best(id);
+        if let Some(&term) = memo.get(&best) {
+            return term;
+        }
+        let term = match g[best] {
+            Node::Copy(..) | Node::Const(_) | Node::Input(_) => best,
+            Node::Add(lhs, rhs) => {
+                let lhs = self.build_memo(lhs, g, memo);
+                let rhs = self.build_memo(rhs, g, memo);
+                Node::Add(lhs, rhs).insert_ideal(g)
+            }
+            Node::Mul(lhs, rhs) => {
+                let lhs = self.build_memo(lhs, g, memo);
+                let rhs = self.build_memo(rhs, g, memo);
+                Node::Mul(lhs, rhs).insert_ideal(g)
+            }
+            Node::Choose(n, k) => Node::Choose(self.build_memo(n, g, memo), k).insert_ideal(g),
+            Node::Pow(base, exp) => {
+                let base = self.build_memo(base, g, memo);
+                let exp = self.build_memo(exp, g, memo);
+                Node::Pow(base, exp).insert_ideal(g)
+            }
+            _ => unreachable!("non-byte node in byte expression"),
+        };
+        g.union(id, term);
+        memo.insert(best, term);
+        term
+    }
+}
+
+impl Cfg {
+    /// Replaces every value in this CFG with the cheapest equivalent term, so
+    /// that consumers of the CFG see the extracted terms.
+    pub fn extract(&mut self, ex: &Extraction, g: &mut Graph) {
+        let mut memo = HashMap::new();
+        self.extract_memo(ex, g, &mut memo);
+    }
+
+    fn extract_memo(
+        &mut self,
+        ex: &Extraction,
+        g: &mut Graph,
+        memo: &mut HashMap<ByteNode, ByteNode>,
+    ) {
+        match self {
+            Cfg::Block(block) => {
+                block.iter_memory_mut(g, |_, cell, g| Some(ex.build_memo(cell, g, memo)));
+                block.map_outputs(g, |value, g| ex.build_memo(value, g, memo));
+            }
+            Cfg::Seq(seq) => {
+                for cfg in seq.as_slice_mut() {
+                    cfg.extract_memo(ex, g, memo);
+                }
+            }
+            Cfg::Loop(body) | Cfg::If(body) => body.extract_memo(ex, g, memo),
+            Cfg::Repeat { count, body, .. } => {
+                *count = ex.build_memo(*count, g, memo);
+                body.extract_memo(ex, g, memo);
+            }
+            Cfg::InputCells { .. } => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        egraph::{ByteNode, Graph},
+        extract::{OpCount, WeightedCost},
+        node::{BlockId, Node, Offset},
+        rewrite::{arithmetic_rules, Limits},
+    };
+
+    #[test]
+    fn cheapest_by_model() {
+        let mut g = Graph::new();
+        let x = Node::Copy(Offset(0), BlockId(0)).insert_ideal(&mut g);
+        let two = Node::Const(2).insert_ideal(&mut g);
+        let add: ByteNode = Node::Add(x, x).insert_ideal(&mut g);
+        let mul = Node::Mul(x, two).insert_ideal(&mut g);
+        g.union(add, mul);
+
+        let ex = g.extract(&WeightedCost::default());
+        assert_eq!(ex.best(mul), add);
+        assert_eq!(ex.cost(mul), Some(3));
+        let ex = g.extract(&WeightedCost {
+            add: 10,
+            ..WeightedCost::default()
+        });
+        assert_eq!(ex.best(add), mul);
+    }
+
+    #[test]
+    fn build_nested() {
+        let mut g = Graph::new();
+        let x = Node::Copy(Offset(0), BlockId(0)).insert_ideal(&mut g);
+        let y = Node::Copy(Offset(1), BlockId(0)).insert_ideal(&mut g);
+        let zero = Node::Const(0).insert_ideal(&mut g);
+        let x_0 = Node::Add(x, zero).insert_ideal(&mut g);
+        g.union(x, x_0);
+        let mul = Node::Mul(x_0, y).insert_ideal(&mut g);
+        assert_eq!(g.leader(x), g.leader(x_0));
+
+        let ex = g.extract(&OpCount);
+        let term = ex.build(mul, &mut g);
+        assert_eq!(g[term], Node::Mul(x, y));
+        assert_eq!(g.leader(term), g.leader(mul));
+    }
+
+    #[test]
+    fn extract_after_pass() {
+        let mut g = Graph::new();
+        let mut cfg = g.parse(b"[->++<]").unwrap();
+        cfg.opt_closed_form_add(&mut g);
+        let pretty = cfg.pretty(&g);
+        assert!(pretty.contains("p[1] = c0 * 2 + c1"));
+
+        // The old versions of the cells are not equal to their new values, so
+        // extraction only chooses between terms equal to the stored values.
+        g.saturate(&arithmetic_rules(), Limits::default());
+        let ex = g.extract(&WeightedCost::default());
+        let expected = pretty.replace("c0 * 2 + c1", "c0 + c0 + c1");
+        assert_eq!(cfg.pretty_extracted(&g, &ex), expected);
+        cfg.extract(&ex, &mut g);
+        assert_eq!(cfg.pretty(&g), expected);
+    }
+}
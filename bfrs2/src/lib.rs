@@ -1,11 +1,30 @@
 #[deny(missing_docs)]
+pub mod analysis;
+#[deny(missing_docs)]
 pub mod arena;
 pub mod block;
 pub mod cfg;
 #[deny(missing_docs)]
+pub mod dot;
+#[deny(missing_docs)]
 pub mod egraph;
+#[deny(missing_docs)]
+pub mod extract;
 pub mod node;
 pub mod optimize;
 pub mod parse;
-mod pretty;
+#[deny(missing_docs)]
+pub mod parse_ir;
+#[deny(missing_docs)]
+pub mod poly;
+#[deny(missing_docs)]
+pub mod pretty;
 mod properties;
+#[deny(missing_docs)]
+pub mod rewrite;
+#[deny(missing_docs)]
+pub mod serialize;
+#[deny(missing_docs)]
+pub mod verify;
+#[deny(missing_docs)]
+pub mod visit;
@@ -1,6 +1,10 @@
 use std::{env::args_os, error::Error, fs, process::exit};
 
-use bfrs2::{egraph::Graph, optimize::unsound_outline_guards};
+use bfrs2::{
+    egraph::Graph,
+    optimize::{unsound_outline_guards, Pipeline},
+    pretty::{PrettyOptions, Syntax},
+};
 
 fn main() {
     if let Err(err) = do_main() {
@@ -10,18 +14,51 @@ fn main() {
 }
 
 fn do_main() -> Result<(), Box<dyn Error>> {
-    let args = args_os();
-    if args.len() != 2 {
-        eprintln!("Usage: bfrs-minimal PROGRAM");
-        exit(2);
+    let mut pipeline = Pipeline::default();
+    let mut history = false;
+    let mut options = PrettyOptions::default();
+    let mut filename = None;
+    let mut args = args_os().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--passes" {
+            let passes = args.next().ok_or("missing pipeline after --passes")?;
+            let passes = passes.to_str().ok_or("pipeline is not UTF-8")?;
+            pipeline = Pipeline::parse(passes)?;
+        } else if arg == "--history" {
+            history = true;
+        } else if arg == "--bfrs1" {
+            options.syntax = Syntax::Bfrs1;
+        } else if arg == "--no-guards" {
+            options.guards = false;
+        } else if arg == "--ids" {
+            options.block_ids = true;
+            options.node_ids = true;
+        } else if arg == "--color" {
+            options.color = true;
+        } else if filename.is_none() {
+            filename = Some(arg);
+        } else {
+            filename = None;
+            break;
+        }
     }
-    let filename = args.skip(1).next().unwrap();
+    let Some(filename) = filename else {
+        eprintln!(
+            "Usage: bfrs-minimal [--passes PIPELINE] [--history] [--bfrs1] [--no-guards] [--ids] \
+             [--color] PROGRAM"
+        );
+        exit(2);
+    };
     let src = fs::read(&filename)?;
     let mut g = Graph::new();
     let mut cfg = g.parse(&src)?;
     unsound_outline_guards(true);
-    cfg.opt_closed_form_add(&mut g);
-    cfg.opt_peel(&mut g);
-    print!("{}", cfg.pretty(&g));
+    if history {
+        let history = pipeline.run_with_history(&mut cfg, &mut g);
+        print!("{}", history.report(&g));
+    } else {
+        pipeline.run(&mut cfg, &mut g);
+        print!("{}", cfg.pretty_with(&g, &options));
+    }
     Ok(())
 }
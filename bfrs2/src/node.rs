@@ -1,11 +1,15 @@
-use std::{
-    mem,
-    ops::{Add, AddAssign},
-};
+use std::ops::{Add, AddAssign};
 
-use crate::egraph::{Graph, NodeId};
+use crate::{
+    block::{Block, Effect},
+    egraph::{self, BoolNode, ByteNode, CtrlNode, EffectNode, Graph, NodeId, TypedId},
+    poly::Poly,
+};
 
-/// A node for a byte computation.
+/// A node in the e-graph, which is either a byte computation, a control-flow
+/// node, a condition, or an effect. Control-flow nodes mirror the variants of `Cfg`, so that
+/// passes, which rewrite a loop, can retain its earlier versions in the same
+/// e-class. Effects are chained by tokens, which enforce their order.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Node {
     /// Copy the byte from the cell at the offset.
@@ -15,9 +19,53 @@ pub enum Node {
     /// A byte read from the user.
     Input(InputId),
     /// Addition of two bytes.
-    Add(NodeId, NodeId),
+    Add(ByteNode, ByteNode),
     /// Multiplication of two bytes.
-    Mul(NodeId, NodeId),
+    Mul(ByteNode, ByteNode),
+    /// The binomial coefficient `n choose k` (mod 256), where `n` is taken as
+    /// an integer in `0..=255`. This is not a polynomial over bytes, so it is
+    /// an atom in the polynomial normal form.
+    Choose(ByteNode, u8),
+    /// Exponentiation of a byte by a byte, where the exponent is taken as an
+    /// integer in `0..=255`.
+    Pow(ByteNode, ByteNode),
+
+    /// A basic block.
+    Block(Box<Block>),
+    /// A sequence of control-flow nodes.
+    Seq(Vec<CtrlNode>),
+    /// Loop while the condition holds.
+    Loop(BoolNode, CtrlNode),
+    /// If the condition holds.
+    If(BoolNode, CtrlNode),
+    /// Loop a number of times, which is computed on entry.
+    Repeat {
+        /// The ID, which copies in `count` use to read cells on entry.
+        id: BlockId,
+        /// The number of iterations.
+        count: ByteNode,
+        /// The loop body.
+        body: CtrlNode,
+    },
+    /// Read bytes into consecutive cells until the delimiter is read, while
+    /// the current cell is non-zero.
+    InputCells {
+        /// The shift between cells.
+        step: Offset,
+        /// The byte which ends the input.
+        delimiter: u8,
+    },
+
+    /// Whether the cell at the offset from the cell pointer is non-zero, where
+    /// the condition is evaluated.
+    NonZero(Offset),
+
+    /// The effect token on entry to a basic block, which begins its chain of
+    /// effects.
+    Entry(BlockId),
+    /// An observable effect, which is ordered after the effects of the token.
+    /// The node itself is the token for later effects.
+    Effect(EffectNode, Effect),
 }
 
 /// An ID for a basic block, unique per e-graph.
@@ -29,116 +77,195 @@ pub struct BlockId(pub u32);
 pub struct InputId(pub u32);
 
 /// A relative offset to the cell pointer.
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Offset(pub i64);
 
 impl Node {
-    /// Inserts this node into the e-graph and transforms it to its ideal
-    /// representation. Any structurally equivalent nodes are deduplicated and
-    /// receive the same ID.
-    pub fn insert(self, g: &mut Graph) -> NodeId {
+    /// Inserts this byte node into the e-graph and transforms it to its ideal
+    /// representation, the polynomial normal form. Any structurally
+    /// equivalent nodes are deduplicated and receive the same ID. Panics if
+    /// this is not a byte computation.
+    pub fn insert(self, g: &mut Graph) -> ByteNode {
         match self {
-            Node::Add(mut lhs, mut rhs) => {
-                if let Node::Add(b, c) = g[rhs] {
-                    if let Node::Add(..) = g[lhs] {
-                        return Node::Add(Node::Add(lhs, b).insert(g), c).insert(g);
-                    }
-                    mem::swap(&mut lhs, &mut rhs);
-                }
-                let (tail, head) = match g[lhs] {
-                    Node::Add(a, b) => (Some(a), b),
-                    _ => (None, lhs),
-                };
-                let (res, idealize) = match (&g[head], &g[rhs]) {
-                    (&Node::Const(a), &Node::Const(b)) => {
-                        (Node::Const(a.wrapping_add(b)).insert_ideal(g), true)
-                    }
-                    (_, Node::Const(0)) => (head, false),
-                    (Node::Const(0), _) => (rhs, true),
-                    (Node::Const(_), _) => {
-                        if let Some(tail) = tail {
-                            return Node::Add(Node::Add(tail, rhs).insert(g), head).insert(g);
-                        } else {
-                            return Node::Add(rhs, head).insert_ideal(g);
-                        }
-                    }
-                    _ if head == rhs => (
-                        Node::Mul(head, Node::Const(2).insert_ideal(g)).insert(g),
-                        true,
-                    ),
-                    (&Node::Mul(a, b), _) if a == rhs => {
-                        let n = Node::Add(b, Node::Const(1).insert_ideal(g)).insert(g);
-                        (Node::Mul(a, n).insert(g), true)
-                    }
-                    (_, &Node::Mul(b, c)) if b == head => {
-                        let n = Node::Add(c, Node::Const(1).insert_ideal(g)).insert(g);
-                        (Node::Mul(b, n).insert(g), true)
-                    }
-                    _ => return Node::Add(lhs, rhs).insert_ideal(g),
-                };
-                if let Some(tail) = tail {
-                    if res == head {
-                        lhs
-                    } else if idealize {
-                        Node::Add(tail, res).insert(g)
+            Node::Add(lhs, rhs) => Poly::of(lhs, g).add(&Poly::of(rhs, g), g).insert(g),
+            Node::Mul(lhs, rhs) => match Poly::of(lhs, g).mul(&Poly::of(rhs, g), g) {
+                Some(product) => product.insert(g),
+                None => {
+                    // Too large to expand, so keep it as an opaque product with
+                    // ordered operands.
+                    let (lhs, rhs) = if lhs.index() <= rhs.index() {
+                        (lhs, rhs)
                     } else {
-                        Node::Add(tail, res).insert_ideal(g)
-                    }
-                } else {
-                    res
+                        (rhs, lhs)
+                    };
+                    Node::Mul(lhs, rhs).insert_ideal(g)
                 }
-            }
-            Node::Mul(mut lhs, mut rhs) => {
-                if let Node::Mul(b, c) = g[rhs] {
-                    if let Node::Mul(..) = g[lhs] {
-                        return Node::Mul(Node::Mul(lhs, b).insert(g), c).insert(g);
-                    }
-                    mem::swap(&mut lhs, &mut rhs);
-                }
-                let (tail, head) = match g[lhs] {
-                    Node::Mul(a, b) => (Some(a), b),
-                    _ => (None, lhs),
-                };
-                let (res, idealize) = match (&g[head], &g[rhs]) {
-                    (&Node::Const(a), &Node::Const(b)) => {
-                        (Node::Const(a.wrapping_mul(b)).insert_ideal(g), true)
-                    }
-                    (_, Node::Const(1)) => (head, false),
-                    (Node::Const(1), _) => (rhs, true),
-                    (_, Node::Const(0)) | (Node::Const(0), _) => {
-                        return Node::Const(0).insert_ideal(g)
-                    }
-                    (Node::Const(_), _) => {
-                        if let Some(tail) = tail {
-                            return Node::Mul(Node::Mul(tail, rhs).insert(g), head).insert(g);
-                        } else {
-                            return Node::Mul(rhs, head).insert_ideal(g);
-                        }
-                    }
-                    _ => return Node::Mul(lhs, rhs).insert_ideal(g),
-                };
-                if let Some(tail) = tail {
-                    if res == head {
-                        lhs
-                    } else if idealize {
-                        Node::Mul(tail, res).insert(g)
-                    } else {
-                        Node::Mul(tail, res).insert_ideal(g)
-                    }
-                } else {
-                    res
+            },
+            Node::Choose(n, k) => match (g[n].clone(), k) {
+                (_, 0) => Node::Const(1).insert_ideal(g),
+                (_, 1) => n,
+                (Node::Const(n), k) => Node::Const(choose(n, k)).insert_ideal(g),
+                _ => self.insert_ideal(g),
+            },
+            Node::Pow(base, exp) => match (g[base].clone(), g[exp].clone()) {
+                (_, Node::Const(0)) | (Node::Const(1), _) => Node::Const(1).insert_ideal(g),
+                (_, Node::Const(1)) => base,
+                (Node::Const(base), Node::Const(exp)) => {
+                    Node::Const(base.wrapping_pow(exp as u32)).insert_ideal(g)
                 }
-            }
+                _ => self.insert_ideal(g),
+            },
             _ => self.insert_ideal(g),
         }
     }
 
+    /// Returns whether this is a byte computation.
+    pub fn is_byte(&self) -> bool {
+        matches!(
+            self,
+            Node::Copy(..)
+                | Node::Const(_)
+                | Node::Input(_)
+                | Node::Add(..)
+                | Node::Mul(..)
+                | Node::Choose(..)
+                | Node::Pow(..)
+        )
+    }
+
+    /// Returns whether this is a control-flow node, rather than a byte
+    /// computation.
+    pub fn is_ctrl(&self) -> bool {
+        matches!(
+            self,
+            Node::Block(_)
+                | Node::Seq(_)
+                | Node::Loop(..)
+                | Node::If(..)
+                | Node::Repeat { .. }
+                | Node::InputCells { .. }
+        )
+    }
+
+    /// Returns whether this is a condition of a loop or if.
+    pub fn is_bool(&self) -> bool {
+        matches!(self, Node::NonZero(_))
+    }
+
+    /// Returns whether this is an effect token.
+    pub fn is_effect(&self) -> bool {
+        matches!(self, Node::Entry(_) | Node::Effect(..))
+    }
+
+    /// Returns whether both nodes are of the same kind, i.e., byte
+    /// computations, control-flow nodes, conditions, or effect tokens.
+    pub fn same_kind(&self, other: &Node) -> bool {
+        self.is_byte() == other.is_byte()
+            && self.is_ctrl() == other.is_ctrl()
+            && self.is_bool() == other.is_bool()
+    }
+
+    /// Gets the IDs of the nodes, which this node uses, in order.
+    pub fn operands(&self) -> Vec<NodeId> {
+        match *self {
+            Node::Copy(..)
+            | Node::Const(_)
+            | Node::Input(_)
+            | Node::InputCells { .. }
+            | Node::NonZero(_)
+            | Node::Entry(_) => Vec::new(),
+            Node::Add(lhs, rhs) | Node::Mul(lhs, rhs) | Node::Pow(lhs, rhs) => {
+                vec![lhs.id(), rhs.id()]
+            }
+            Node::Choose(n, _) => vec![n.id()],
+            Node::Loop(cond, body) | Node::If(cond, body) => vec![cond.id(), body.id()],
+            Node::Repeat { count, body, .. } => vec![count.id(), body.id()],
+            Node::Seq(ref seq) => seq.iter().map(|cfg| cfg.id()).collect(),
+            Node::Block(ref block) => block.operands().collect(),
+            Node::Effect(token, ref effect) => {
+                let mut operands = vec![token.id()];
+                operands.extend(effect.nodes().iter().map(|value| value.id()));
+                operands
+            }
+        }
+    }
+
+    /// Replaces the IDs of the nodes, which this node uses. The replacements
+    /// must be nodes of the same kinds.
+    pub(crate) fn remap(&mut self, f: &mut impl FnMut(NodeId) -> NodeId) {
+        match self {
+            Node::Copy(..)
+            | Node::Const(_)
+            | Node::Input(_)
+            | Node::InputCells { .. }
+            | Node::NonZero(_)
+            | Node::Entry(_) => {}
+            Node::Add(lhs, rhs) | Node::Mul(lhs, rhs) | Node::Pow(lhs, rhs) => {
+                *lhs = lhs.remap(f);
+                *rhs = rhs.remap(f);
+            }
+            Node::Choose(n, _) => *n = n.remap(f),
+            Node::Loop(cond, body) | Node::If(cond, body) => {
+                *cond = cond.remap(f);
+                *body = body.remap(f);
+            }
+            Node::Repeat { count, body, .. } => {
+                *count = count.remap(f);
+                *body = body.remap(f);
+            }
+            Node::Seq(seq) => seq.iter_mut().for_each(|cfg| *cfg = cfg.remap(f)),
+            Node::Block(block) => block.remap(f),
+            Node::Effect(token, effect) => {
+                *token = token.remap(f);
+                effect.remap(f);
+            }
+        }
+    }
+
     /// Inserts this node into the e-graph, without idealizing. The node must
     /// already be idealized. Any structurally equivalent nodes are deduplicated
-    /// and receive the same ID.
-    pub fn insert_ideal(self, g: &mut Graph) -> NodeId {
-        g.insert(self)
+    /// and receive the same ID. Panics if the node is not of the requested
+    /// type.
+    pub fn insert_ideal<T: TypedId>(self, g: &mut Graph) -> T {
+        if !T::accepts(&self) {
+            panic!("{self:?} is not a {}", T::KIND);
+        }
+        egraph::wrap(g.insert(self))
+    }
+}
+
+/// Computes the binomial coefficient `n choose k` (mod 256) without division,
+/// by cancelling the factors of the denominator against those of the
+/// numerator.
+pub fn choose(n: u8, k: u8) -> u8 {
+    if k > n {
+        return 0;
+    }
+    let mut numerator = (0..k as u32).map(|i| n as u32 - i).collect::<Vec<_>>();
+    for d in 2..=k as u32 {
+        // Each prime power in `d` divides the product of the numerator, since
+        // `k!` divides any product of `k` consecutive integers.
+        let mut d = d;
+        for factor in &mut numerator {
+            let g = gcd(*factor, d);
+            *factor /= g;
+            d /= g;
+            if d == 1 {
+                break;
+            }
+        }
+        debug_assert_eq!(d, 1);
+    }
+    numerator
+        .into_iter()
+        .fold(1u8, |product, factor| product.wrapping_mul(factor as u8))
+}
+
+fn gcd(mut a: u32, mut b: u32) -> u32 {
+    while b != 0 {
+        (a, b) = (b, a % b);
     }
+    a
 }
 
 impl Offset {
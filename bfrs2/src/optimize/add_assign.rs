@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     mem,
     sync::atomic::{AtomicBool, Ordering},
 };
@@ -6,45 +7,72 @@ use std::{
 use crate::{
     block::Block,
     cfg::Cfg,
-    egraph::Graph,
-    node::{Node, Offset},
+    egraph::{ByteNode, Graph},
+    node::{choose, Node, Offset},
+    poly::Poly,
+    visit::Rewriter,
 };
 
 static UNSOUND_OUTLINE_GUARDS: AtomicBool = AtomicBool::new(false);
 
 impl Cfg {
     /// Converts loops, which have no net shift and add an odd constant to the
-    /// current cell, to their closed form.
+    /// current cell, to their closed form. Other cells may be incremented by a
+    /// polynomial in the current cell (e.g., triangular sums) or multiplied by
+    /// a loop-invariant value. When the body has effects or stores without a
+    /// closed form, the loop is converted to a counted loop.
     pub fn opt_closed_form_add(&mut self, g: &mut Graph) {
-        match self {
-            Cfg::Block(_) => {}
-            Cfg::Seq(seq) => {
-                seq.iter_mut().for_each(|cfg| cfg.opt_closed_form_add(g));
-                self.flatten(g);
-            }
-            Cfg::Loop(cfg) => {
-                cfg.opt_closed_form_add(g);
-                if let Cfg::Block(block) = cfg.as_mut() {
-                    if let Some(factor) = block.closed_form_iter_factor(g) {
-                        if let Some(has_guards) = block.is_pure() {
-                            if block.opt_closed_form_add(factor, g) {
-                                let Cfg::Loop(body) = mem::replace(self, Cfg::empty()) else {
-                                    unreachable!();
-                                };
-                                if has_guards && !UNSOUND_OUTLINE_GUARDS.load(Ordering::Acquire) {
-                                    *self = Cfg::If(body);
-                                } else {
-                                    *self = *body;
-                                }
-                            }
-                        }
-                    }
+        self.visit_mut(&mut ClosedFormAdd, g);
+    }
+
+    /// Converts this loop to its closed form or to a counted loop, when the
+    /// number of iterations is known.
+    fn closed_form_loop(&mut self, g: &mut Graph) {
+        let Cfg::Loop(cfg) = self else {
+            unreachable!();
+        };
+        let Cfg::Block(block) = cfg.as_mut() else {
+            return;
+        };
+        let Some(factor) = block.closed_form_iter_factor(g) else {
+            return;
+        };
+        if let Some(has_guards) = block.is_pure(g) {
+            if block.opt_closed_form_add(factor, g) {
+                let Cfg::Loop(body) = mem::replace(self, Cfg::empty()) else {
+                    unreachable!();
+                };
+                if has_guards && !UNSOUND_OUTLINE_GUARDS.load(Ordering::Acquire) {
+                    *self = Cfg::If(body);
+                } else {
+                    *self = *body;
                 }
-            }
-            Cfg::If(cfg_then) => {
-                cfg_then.opt_closed_form_add(g);
+                return;
             }
         }
+        // The body has effects or stores without a closed form, but the number
+        // of iterations is still known.
+        let id = g.fresh_block_id();
+        let count = Node::Mul(
+            Node::Copy(Offset(0), id).insert_ideal(g),
+            Node::Const(factor).insert_ideal(g),
+        )
+        .insert(g);
+        let Cfg::Loop(body) = mem::replace(self, Cfg::empty()) else {
+            unreachable!();
+        };
+        *self = Cfg::Repeat { id, count, body };
+    }
+}
+
+/// Converts loops to their closed form, innermost first.
+struct ClosedFormAdd;
+
+impl Rewriter for ClosedFormAdd {
+    fn post(&mut self, cfg: &mut Cfg, g: &mut Graph) {
+        if let Cfg::Loop(_) = cfg {
+            cfg.rewrite(g, Cfg::closed_form_loop);
+        }
     }
 }
 
@@ -59,17 +87,22 @@ impl Block {
     /// execute as the body of a loop. The number of iterations is the factor
     /// multiplied by the current cell. This can be calculated when the block
     /// has no net shift and an odd constant is added to the current cell.
-    fn closed_form_iter_factor(&self, g: &Graph) -> Option<u8> {
+    pub(super) fn closed_form_iter_factor(&self, g: &Graph) -> Option<u8> {
         if self.offset == Offset(0) {
-            if let Some(current) = self.get_cell(Offset(0)) {
-                if let Node::Add(lhs, rhs) = g[current] {
-                    if g[lhs] == Node::Copy(Offset(0), self.id) {
-                        if let Node::Const(addend) = g[rhs] {
-                            if let Some(factor) = mod_inverse(addend.wrapping_neg()) {
-                                return Some(factor);
-                            }
-                        }
-                    }
+            self.counter_factor(Offset(0), g)
+        } else {
+            None
+        }
+    }
+
+    /// Calculates the factor of the number of iterations a loop would execute,
+    /// when this block adds an odd constant to the loop counter at the offset.
+    pub(super) fn counter_factor(&self, offset: Offset, g: &Graph) -> Option<u8> {
+        let current = self.get_cell(offset)?;
+        if let Node::Add(lhs, rhs) = g[current] {
+            if g[lhs] == Node::Copy(offset, self.id) {
+                if let Node::Const(addend) = g[rhs] {
+                    return mod_inverse(addend.wrapping_neg());
                 }
             }
         }
@@ -78,12 +111,18 @@ impl Block {
 
     /// Converts a loop body, which has no net shift and adds an odd constant to
     /// the current cell, to its closed form. The block should be in a loop.
+    /// Every other cell must be loop-invariant or follow a recurrence with a
+    /// closed form.
     fn opt_closed_form_add(&mut self, factor: u8, g: &mut Graph) -> bool {
-        if !self
-            .iter_memory()
-            .all(|(offset, cell)| offset == Offset(0) || g.get(cell).is_add_assign(offset, self))
-        {
-            return false;
+        let step = mod_inverse(factor).unwrap().wrapping_neg();
+        let mut recurrences = HashMap::new();
+        for (offset, cell) in self.iter_memory() {
+            if offset != Offset(0) {
+                let Some(recurrence) = self.recurrence(offset, cell, step, g) else {
+                    return false;
+                };
+                recurrences.insert(offset, recurrence);
+            }
         }
         let block_id = self.id;
         let iters = Node::Mul(
@@ -92,20 +131,121 @@ impl Block {
         )
         .insert(g);
         self.iter_memory_mut(g, |offset, cell, a| {
-            if offset == Offset(0) {
-                Some(Node::Const(0).insert_ideal(a))
-            } else {
-                match a[cell] {
-                    Node::Add(lhs, rhs) => {
-                        debug_assert_eq!(a[lhs], Node::Copy(offset, block_id));
-                        Some(Node::Add(lhs, Node::Mul(rhs, iters).insert(a)).insert(a))
+            match recurrences.remove(&offset) {
+                None => Some(Node::Const(0).insert_ideal(a)),
+                Some(Recurrence::Invariant) => Some(cell),
+                Some(Recurrence::Sum(coeffs)) => {
+                    // Σ_{k<n} C(k, m) = C(n, m + 1)
+                    let mut value = Node::Copy(offset, block_id).insert_ideal(a);
+                    for (m, coeff) in coeffs.iter().enumerate() {
+                        let coeff = coeff.insert(a);
+                        let choose = Node::Choose(iters, m as u8 + 1).insert(a);
+                        let term = Node::Mul(coeff, choose).insert(a);
+                        value = Node::Add(value, term).insert(a);
                     }
-                    _ => Some(cell),
+                    Some(value)
+                }
+                Some(Recurrence::Geometric(ratio)) => {
+                    let copy = Node::Copy(offset, block_id).insert_ideal(a);
+                    let ratio = ratio.insert(a);
+                    let pow = Node::Pow(ratio, iters).insert(a);
+                    Some(Node::Mul(copy, pow).insert(a))
                 }
             }
         });
         true
     }
+
+    /// Finds the recurrence for the value stored to a cell in a loop body,
+    /// where the current cell is the loop counter and changes by `step` each
+    /// iteration.
+    fn recurrence(
+        &self,
+        offset: Offset,
+        cell: ByteNode,
+        step: u8,
+        g: &Graph,
+    ) -> Option<Recurrence> {
+        let is_invariant = |poly: &Poly| {
+            !poly
+                .atoms()
+                .any(|atom| g.get(atom).reads_from(self, self.id))
+        };
+        let value = Poly::of(cell, g);
+        let powers = match g.find(&Node::Copy(offset, self.id)) {
+            Some(copy) => value.powers_of(copy, g),
+            None => vec![value],
+        };
+        match powers.as_slice() {
+            [] => Some(Recurrence::Invariant),
+            [value] => is_invariant(value).then_some(Recurrence::Invariant),
+            [addend, one] if one.as_const() == Some(1) => {
+                let counter = g.find(&Node::Copy(Offset(0), self.id));
+                let coeffs = match counter {
+                    Some(counter) => addend.powers_of(counter, g),
+                    None => vec![addend.clone()],
+                };
+                if coeffs.len() > SUM_OF_POWERS.len() || !coeffs.iter().all(is_invariant) {
+                    return None;
+                }
+                Some(Recurrence::Sum(sum_in_binomial_basis(
+                    &coeffs,
+                    counter.map(Poly::atom),
+                    step,
+                    g,
+                )?))
+            }
+            [zero, ratio] if zero.as_const() == Some(0) && is_invariant(ratio) => {
+                Some(Recurrence::Geometric(ratio.clone()))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// How a cell changes in each iteration of a loop, when the current cell is
+/// the loop counter.
+enum Recurrence {
+    /// The cell is stored a loop-invariant value.
+    Invariant,
+    /// A polynomial in the counter is added to the cell. The polynomial is the
+    /// sum of `coeffs[m] * C(k, m)`, where `k` is the iteration.
+    Sum(Vec<Poly>),
+    /// The cell is multiplied by a loop-invariant ratio.
+    Geometric(Poly),
+}
+
+/// The sums of powers as binomial coefficients, where `Σ_{k<n} k^i` is the sum
+/// of `SUM_OF_POWERS[i][m] * C(n, m + 1)`. The coefficients are the Stirling
+/// numbers of the second kind multiplied by `m!`.
+const SUM_OF_POWERS: [[u8; 4]; 4] = [[1, 0, 0, 0], [0, 1, 0, 0], [0, 1, 2, 0], [0, 1, 6, 6]];
+
+/// Rewrites a polynomial `f(c) = Σ_j coeffs[j] * c^j` in the counter at the
+/// iteration `k`, i.e., with `c = counter + step * k`, and sums it over the
+/// iterations. Returns the coefficient of each `C(n, m + 1)`, where `n` is the
+/// number of iterations, or `None` if the polynomials get too large.
+fn sum_in_binomial_basis(
+    coeffs: &[Poly],
+    counter: Option<Poly>,
+    step: u8,
+    g: &Graph,
+) -> Option<Vec<Poly>> {
+    let mut sums = vec![Poly::constant(0); coeffs.len()];
+    for (j, coeff) in coeffs.iter().enumerate() {
+        // (counter + step*k)^j = Σ_i C(j, i) * counter^(j-i) * step^i * k^i
+        for (i, sum_of_power) in SUM_OF_POWERS.iter().enumerate().take(j + 1) {
+            let scale = choose(j as u8, i as u8).wrapping_mul(step.wrapping_pow(i as u32));
+            let mut term = coeff.mul(&Poly::constant(scale), g)?;
+            for _ in i..j {
+                term = term.mul(counter.as_ref()?, g)?;
+            }
+            for (sum, &scale) in sums.iter_mut().zip(&sum_of_power[..=i]) {
+                let term = term.mul(&Poly::constant(scale), g)?;
+                *sum = sum.add(&term, g);
+            }
+        }
+    }
+    Some(sums)
 }
 
 /// Computes the multiplicative inverse of a number (mod 256).
@@ -144,3 +284,39 @@ fn mod_inverse(value: u8) -> Option<u8> {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{egraph::Graph, node::choose, poly::Poly};
+
+    use super::sum_in_binomial_basis;
+
+    #[test]
+    fn sum_of_powers() {
+        let g = Graph::new();
+        let coeffs = [3u8, 5, 7, 11];
+        for counter in [0u8, 1, 2, 100, 255] {
+            for step in [1u8, 3, 255] {
+                let polys = coeffs.map(Poly::constant);
+                let sums = sum_in_binomial_basis(&polys, Some(Poly::constant(counter)), step, &g)
+                    .unwrap()
+                    .iter()
+                    .map(|sum| sum.as_const().unwrap())
+                    .collect::<Vec<_>>();
+                let mut expected = 0u8;
+                for n in 0..=255u8 {
+                    let closed_form = sums.iter().enumerate().fold(0u8, |acc, (m, &sum)| {
+                        acc.wrapping_add(sum.wrapping_mul(choose(n, m as u8 + 1)))
+                    });
+                    assert_eq!(closed_form, expected, "n={n} c={counter} step={step}");
+                    let c = counter.wrapping_add(step.wrapping_mul(n));
+                    let f = coeffs
+                        .iter()
+                        .rev()
+                        .fold(0u8, |acc, &coeff| acc.wrapping_mul(c).wrapping_add(coeff));
+                    expected = expected.wrapping_add(f);
+                }
+            }
+        }
+    }
+}
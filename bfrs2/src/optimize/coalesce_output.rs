@@ -0,0 +1,57 @@
+use crate::{
+    block::{Block, Effect},
+    cfg::Cfg,
+    egraph::Graph,
+    node::Node,
+    visit::Rewriter,
+};
+
+impl Cfg {
+    /// Coalesces outputs of constant bytes into byte strings, so that they can
+    /// be written at once. Adjacent strings are merged, including those in
+    /// blocks, which become adjacent in a sequence.
+    pub fn opt_coalesce_output(&mut self, g: &mut Graph) {
+        self.visit_mut(&mut CoalesceOutput, g);
+    }
+}
+
+/// Coalesces the outputs of each block.
+struct CoalesceOutput;
+
+impl Rewriter for CoalesceOutput {
+    fn post(&mut self, cfg: &mut Cfg, g: &mut Graph) {
+        if let Cfg::Block(block) = cfg {
+            block.coalesce_output(g);
+        }
+    }
+}
+
+impl Block {
+    /// Splits outputs into runs of constant and non-constant bytes and merges
+    /// adjacent runs of the same kind.
+    fn coalesce_output(&mut self, g: &mut Graph) {
+        let mut effects = Vec::new();
+        for effect in self.effects(g) {
+            match effect {
+                Effect::Output(values) => {
+                    for value in values {
+                        match (&g[value], effects.last_mut()) {
+                            (&Node::Const(b), Some(Effect::OutputBytes(bytes))) => bytes.push(b),
+                            (&Node::Const(b), _) => effects.push(Effect::OutputBytes(vec![b])),
+                            (_, Some(Effect::Output(values))) => values.push(value),
+                            (_, _) => effects.push(Effect::Output(vec![value])),
+                        }
+                    }
+                }
+                Effect::OutputBytes(bytes) => match effects.last_mut() {
+                    Some(Effect::OutputBytes(bytes1)) => bytes1.extend_from_slice(&bytes),
+                    _ => effects.push(Effect::OutputBytes(bytes)),
+                },
+                _ => effects.push(effect),
+            }
+        }
+        if effects != self.effects(g) {
+            self.set_effects(effects, g);
+        }
+    }
+}
@@ -1,35 +1,53 @@
 use crate::{
-    block::{Block, Effect},
+    block::Block,
     cfg::Cfg,
-    egraph::{Graph, NodeId},
+    egraph::{ByteNode, Graph},
     node::{BlockId, Node},
+    visit::Rewriter,
 };
 
 impl Cfg {
     /// Replaces copies with their definition in the preceding block, if the
     /// definition is a constant.
     pub fn opt_copy_const(&mut self, pred: Option<&Block>, g: &mut Graph) {
-        match self {
+        let mut pass = CopyConst {
+            pred: pred.cloned(),
+        };
+        self.visit_mut(&mut pass, g);
+    }
+}
+
+/// The state of constant copy propagation: the block which precedes the
+/// current CFG node, if it is a block.
+struct CopyConst {
+    pred: Option<Block>,
+}
+
+impl Rewriter for CopyConst {
+    fn pre(&mut self, cfg: &mut Cfg, g: &mut Graph) -> bool {
+        match cfg {
             Cfg::Block(block) => {
-                if let Some(pred) = pred {
+                if let Some(pred) = &self.pred {
                     block.copy_const(pred, g);
                 }
             }
-            Cfg::Seq(seq) => {
-                let mut pred = pred;
-                for cfg in seq.as_slice_mut() {
-                    cfg.opt_copy_const(pred, g);
-                    if let Cfg::Block(block) = cfg {
-                        pred = Some(block);
-                    } else {
-                        pred = None;
-                    }
+            Cfg::Loop(_) => self.pred = None,
+            Cfg::Repeat { id, count, .. } => {
+                if let Some(pred) = &self.pred {
+                    *count = count.copy_const(*id, pred, g);
                 }
-                self.flatten(g);
+                self.pred = None;
             }
-            Cfg::Loop(cfg) => cfg.opt_copy_const(None, g),
-            Cfg::If(cfg_then) => cfg_then.opt_copy_const(pred, g),
+            Cfg::Seq(_) | Cfg::If(_) | Cfg::InputCells { .. } => {}
         }
+        true
+    }
+
+    fn post(&mut self, cfg: &mut Cfg, _g: &mut Graph) {
+        self.pred = match cfg {
+            Cfg::Block(block) => Some(block.clone()),
+            _ => None,
+        };
     }
 }
 
@@ -39,17 +57,11 @@ impl Block {
     pub fn copy_const(&mut self, pred: &Block, g: &mut Graph) {
         let curr = self.id;
         self.iter_memory_mut(g, |_, cell, a| Some(cell.copy_const(curr, pred, a)));
-        for effect in &mut self.effects {
-            if let Effect::Output(values) = effect {
-                for value in values {
-                    *value = value.copy_const(curr, pred, g);
-                }
-            }
-        }
+        self.map_outputs(g, |value, g| value.copy_const(curr, pred, g));
     }
 }
 
-impl NodeId {
+impl ByteNode {
     /// Copes the node, with copies replaced with their definition in the
     /// preceding block, if the definition is a constant.
     fn copy_const(self, curr: BlockId, pred: &Block, g: &mut Graph) -> Self {
@@ -69,6 +81,13 @@ impl NodeId {
                 let rhs = rhs.copy_const(curr, pred, g);
                 Node::Mul(lhs, rhs).insert(g)
             }
+            Node::Choose(n, k) => Node::Choose(n.copy_const(curr, pred, g), k).insert(g),
+            Node::Pow(base, exp) => {
+                let base = base.copy_const(curr, pred, g);
+                let exp = exp.copy_const(curr, pred, g);
+                Node::Pow(base, exp).insert(g)
+            }
+            _ => unreachable!("non-byte node in byte expression"),
         }
     }
 }
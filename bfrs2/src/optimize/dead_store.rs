@@ -0,0 +1,247 @@
+use std::collections::{BTreeSet, HashSet};
+
+use crate::{
+    block::{Block, Effect},
+    cfg::Cfg,
+    egraph::{ByteNode, Graph},
+    node::{BlockId, Node, Offset},
+};
+
+/// The cells, which may be read before they are overwritten, relative to the
+/// cell pointer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Live {
+    /// Every cell may be read, e.g., when the cell pointer is not statically
+    /// known.
+    All,
+    /// Only these cells may be read.
+    Cells(BTreeSet<Offset>),
+}
+
+impl Cfg {
+    /// Removes stores to cells, which are overwritten on every path before
+    /// they are read. No cells are live when the program exits.
+    pub fn opt_dead_store(&mut self, g: &mut Graph) {
+        self.eliminate_dead_stores(Live::none(), g);
+    }
+
+    /// Removes dead stores, given the cells which are live after this CFG, and
+    /// returns the cells which are live before it.
+    fn eliminate_dead_stores(&mut self, live_out: Live, g: &mut Graph) -> Live {
+        match self {
+            Cfg::Block(block) => block.eliminate_dead_stores(live_out, g),
+            Cfg::Seq(seq) => {
+                let mut live = live_out;
+                for cfg in seq.iter_mut().rev() {
+                    live = cfg.eliminate_dead_stores(live, g);
+                }
+                self.flatten(g);
+                live
+            }
+            Cfg::Loop(_) | Cfg::If(_) | Cfg::Repeat { .. } => {
+                let (live_body, live_in) = self.loop_liveness(live_out, g);
+                let (Cfg::Loop(body) | Cfg::If(body) | Cfg::Repeat { body, .. }) = self else {
+                    unreachable!();
+                };
+                body.eliminate_dead_stores(live_body, g);
+                live_in
+            }
+            Cfg::InputCells { .. } => Live::All,
+        }
+    }
+
+    /// Computes the cells which are live before this CFG, given the cells
+    /// which are live after it, without changing it.
+    fn live_in(&self, live_out: Live, g: &Graph) -> Live {
+        match self {
+            Cfg::Block(block) => block.live_in(live_out, g),
+            Cfg::Seq(seq) => seq
+                .iter()
+                .rev()
+                .fold(live_out, |live, cfg| cfg.live_in(live, g)),
+            Cfg::Loop(_) | Cfg::If(_) | Cfg::Repeat { .. } => self.loop_liveness(live_out, g).1,
+            Cfg::InputCells { .. } => Live::All,
+        }
+    }
+
+    /// Computes liveness for a loop or if, given the cells which are live
+    /// after it. Returns the cells which are live after its body and the cells
+    /// which are live before it.
+    fn loop_liveness(&self, live_out: Live, g: &Graph) -> (Live, Live) {
+        let (Cfg::Loop(body) | Cfg::If(body) | Cfg::Repeat { body, .. }) = self else {
+            unreachable!();
+        };
+        if body.shift() != Some(Offset(0)) {
+            return (Live::All, Live::All);
+        }
+        match self {
+            Cfg::Loop(_) => {
+                // The condition is checked before every iteration and the body
+                // may execute any number of times.
+                let mut live = live_out;
+                live.insert(Offset(0));
+                loop {
+                    let next = live.union(body.live_in(live.clone(), g));
+                    if next == live {
+                        return (live.clone(), live);
+                    }
+                    live = next;
+                }
+            }
+            Cfg::If(_) => {
+                let mut live_in = live_out.union(body.live_in(live_out.clone(), g));
+                live_in.insert(Offset(0));
+                (live_out, live_in)
+            }
+            Cfg::Repeat { id, count, .. } => {
+                let mut live = live_out;
+                loop {
+                    let next = live.union(body.live_in(live.clone(), g));
+                    if next == live {
+                        break;
+                    }
+                    live = next;
+                }
+                let mut live_in = live.clone();
+                let mut visited = HashSet::new();
+                live_in.insert_reads(*count, *id, &mut visited, g);
+                (live, live_in)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Computes the net shift of the cell pointer, if it is statically known.
+    pub(super) fn shift(&self) -> Option<Offset> {
+        match self {
+            Cfg::Block(block) => Some(block.offset),
+            Cfg::Seq(seq) => {
+                let mut shift = Offset(0);
+                for cfg in seq.iter() {
+                    shift += cfg.shift()?;
+                }
+                Some(shift)
+            }
+            Cfg::Loop(body) | Cfg::If(body) | Cfg::Repeat { body, .. } => {
+                (body.shift()? == Offset(0)).then_some(Offset(0))
+            }
+            Cfg::InputCells { .. } => None,
+        }
+    }
+}
+
+impl Block {
+    /// Removes dead stores, given the cells which are live after this block,
+    /// and returns the cells which are live before it.
+    fn eliminate_dead_stores(&mut self, live_out: Live, g: &mut Graph) -> Live {
+        let live_out = live_out.shift(self.offset);
+        if let Live::Cells(cells) = &live_out {
+            self.iter_memory_mut(g, |offset, cell, _| cells.contains(&offset).then_some(cell));
+        }
+        self.live_in_shifted(live_out, g)
+    }
+
+    /// Computes the cells which are live before this block, given the cells
+    /// which are live after it.
+    fn live_in(&self, live_out: Live, g: &Graph) -> Live {
+        self.live_in_shifted(live_out.shift(self.offset), g)
+    }
+
+    /// Computes the cells which are live before this block, given the cells
+    /// which are live after it, relative to the cell pointer on entry.
+    fn live_in_shifted(&self, live_out: Live, g: &Graph) -> Live {
+        let mut live = live_out;
+        let mut visited = HashSet::new();
+        let kept = self
+            .iter_memory()
+            .filter(|&(offset, _)| live.contains(offset))
+            .collect::<Vec<_>>();
+        for (offset, _) in self.iter_memory() {
+            live.remove(offset);
+        }
+        for (_, cell) in kept {
+            live.insert_reads(cell, self.id, &mut visited, g);
+        }
+        for effect in self.effects(g) {
+            match effect {
+                Effect::Output(values) => {
+                    for value in values {
+                        live.insert_reads(value, self.id, &mut visited, g);
+                    }
+                }
+                Effect::OutputDecimal(value) => {
+                    live.insert_reads(value, self.id, &mut visited, g);
+                }
+                _ => {}
+            }
+        }
+        live
+    }
+}
+
+impl Live {
+    /// No cells are live.
+    fn none() -> Self {
+        Live::Cells(BTreeSet::new())
+    }
+
+    fn contains(&self, offset: Offset) -> bool {
+        match self {
+            Live::All => true,
+            Live::Cells(cells) => cells.contains(&offset),
+        }
+    }
+
+    fn insert(&mut self, offset: Offset) {
+        if let Live::Cells(cells) = self {
+            cells.insert(offset);
+        }
+    }
+
+    fn remove(&mut self, offset: Offset) {
+        if let Live::Cells(cells) = self {
+            cells.remove(&offset);
+        }
+    }
+
+    fn union(&self, other: Live) -> Live {
+        match (self, other) {
+            (Live::Cells(cells), Live::Cells(mut other)) => {
+                other.extend(cells.iter().copied());
+                Live::Cells(other)
+            }
+            _ => Live::All,
+        }
+    }
+
+    /// Makes cells relative to the cell pointer before a shift.
+    fn shift(self, shift: Offset) -> Live {
+        match self {
+            Live::All => Live::All,
+            Live::Cells(cells) => Live::Cells(cells.into_iter().map(|o| o + shift).collect()),
+        }
+    }
+
+    /// Inserts the cells read by the value, which copy from the block.
+    fn insert_reads(
+        &mut self,
+        value: ByteNode,
+        block_id: BlockId,
+        visited: &mut HashSet<ByteNode>,
+        g: &Graph,
+    ) {
+        if !visited.insert(value) {
+            return;
+        }
+        match g[value] {
+            Node::Copy(offset, block) if block == block_id => self.insert(offset),
+            Node::Copy(..) | Node::Const(_) | Node::Input(_) => {}
+            Node::Add(lhs, rhs) | Node::Mul(lhs, rhs) | Node::Pow(lhs, rhs) => {
+                self.insert_reads(lhs, block_id, visited, g);
+                self.insert_reads(rhs, block_id, visited, g);
+            }
+            Node::Choose(n, _) => self.insert_reads(n, block_id, visited, g),
+            _ => unreachable!("non-byte node in byte expression"),
+        }
+    }
+}
@@ -0,0 +1,679 @@
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+};
+
+use crate::{
+    block::{Block, BlockBuilder, DecimalEnd, Effect},
+    cfg::{Cfg, Seq},
+    egraph::{ByteNode, Graph},
+    node::{BlockId, Node, Offset},
+    optimize::interpret::{Event, Exec, Tape},
+};
+
+/// The maximum number of CFG nodes executed when interpreting a decimal
+/// output for one value.
+const OUTPUT_FUEL: usize = 1 << 16;
+
+/// The offsets relative to the cell pointer, which are tried as the cell
+/// printed as a decimal number.
+const OUTPUT_CELLS: [i64; 9] = [0, -1, 1, -2, 2, -3, 3, -4, 4];
+
+impl Cfg {
+    /// Recognizes idioms, which print a cell as a decimal number or read a
+    /// decimal number into a cell, and replaces them with
+    /// `Effect::OutputDecimal` and `Effect::InputDecimal`. Idioms are verified
+    /// by interpreting them for every value, so they may use scratch cells,
+    /// whose values are known. The CFG is a whole program, so every cell is
+    /// zero on entry, and cells stay known through loops, which do not store
+    /// to them.
+    ///
+    /// Closed forms of loops with guards are conditional, so they split
+    /// idioms. They are inlined when their body leaves memory unchanged for a
+    /// zero current cell and its guards are already performed, on entry or
+    /// by the next block before any I/O.
+    pub fn opt_decimal_io(&mut self, g: &mut Graph) {
+        self.decimal_io(Tape::zeroed(), g);
+    }
+
+    /// Recognizes decimal I/O, given the known cells on entry, and returns the
+    /// known cells on exit.
+    fn decimal_io(&mut self, mut tape: Tape, g: &mut Graph) -> Tape {
+        match self {
+            Cfg::Block(block) => block.input_digits(&tape, g),
+            Cfg::Seq(seq) => {
+                let cfgs = inline_closed_forms(mem::replace(seq, Seq::new()), &tape, g);
+                let mut recognized = Vec::with_capacity(cfgs.len());
+                let mut i = 0;
+                while i < cfgs.len() {
+                    let mut cfg = match output_decimal(&cfgs[i..], &tape, g) {
+                        Some((len, block)) => {
+                            i += len;
+                            Cfg::Block(block)
+                        }
+                        None => {
+                            i += 1;
+                            cfgs[i - 1].clone()
+                        }
+                    };
+                    tape = cfg.decimal_io(tape, g);
+                    recognized.push(cfg);
+                }
+                *seq = Seq::from_iter(recognized, g);
+                self.flatten(g);
+                return tape;
+            }
+            Cfg::Loop(_) | Cfg::If(_) if tape.get(Offset(0)) == Some(0) => return tape,
+            Cfg::Loop(body) => match body.input_decimal_until(&tape, g) {
+                Some(cfg) => *self = cfg,
+                None => {
+                    let entry = body.entry_tape(&tape, g);
+                    body.decimal_io(entry, g);
+                }
+            },
+            Cfg::If(body) => {
+                body.decimal_io(tape.clone(), g);
+            }
+            Cfg::Repeat { body, .. } => {
+                let entry = body.entry_tape(&tape, g);
+                body.decimal_io(entry, g);
+            }
+            Cfg::InputCells { .. } => {}
+        }
+        self.exit_tape(&tape, g)
+    }
+
+    /// Gets the known cells on exit, given the known cells on entry.
+    fn exit_tape(&self, tape: &Tape, g: &Graph) -> Tape {
+        let mut exec = Exec::new(tape, g);
+        if exec.run(self).is_some() {
+            return exec.exit_tape();
+        }
+        match self {
+            Cfg::Block(block) => {
+                let mut tape = tape.clone();
+                tape.apply_block(block, g);
+                tape
+            }
+            Cfg::Seq(seq) => (seq.iter()).fold(tape.clone(), |tape, cfg| cfg.exit_tape(&tape, g)),
+            Cfg::Loop(body) => {
+                let mut tape = body.entry_tape(tape, g);
+                tape.cells.insert(Offset(0), Some(0));
+                tape
+            }
+            Cfg::If(body) => {
+                if body.stores(g).is_none() {
+                    return Tape::default();
+                }
+                let mut skipped = tape.clone();
+                skipped.cells.insert(Offset(0), Some(0));
+                skipped.join(&body.exit_tape(tape, g))
+            }
+            Cfg::Repeat { body, .. } => body.entry_tape(tape, g),
+            Cfg::InputCells { .. } => Tape {
+                cells: [(Offset(0), Some(0))].into_iter().collect(),
+                ..Tape::default()
+            },
+        }
+    }
+
+    /// Gets the known cells on entry to each iteration of a loop with this
+    /// body, given the known cells on entry to the loop. Cells, which the body
+    /// does not store to, keep their value.
+    fn entry_tape(&self, tape: &Tape, g: &Graph) -> Tape {
+        let Some(stores) = self.stores(g) else {
+            return Tape::default();
+        };
+        let mut entry = tape.clone();
+        entry.forget(stores);
+        entry
+    }
+
+    /// Gets the cells, which may be stored to, relative to the cell pointer on
+    /// entry, when the CFG has no net shift.
+    fn stores(&self, g: &Graph) -> Option<HashSet<Offset>> {
+        let (shift, stores) = self.footprint(g)?;
+        (shift == Offset(0)).then_some(stores)
+    }
+
+    /// Gets the net shift and the cells, which may be stored to, relative to
+    /// the cell pointer on entry, when the shift is known.
+    fn footprint(&self, g: &Graph) -> Option<(Offset, HashSet<Offset>)> {
+        match self {
+            Cfg::Block(block) => Some((
+                block.offset,
+                block.iter_memory().map(|(offset, _)| offset).collect(),
+            )),
+            Cfg::Seq(seq) => {
+                let mut shift = Offset(0);
+                let mut stores = HashSet::new();
+                for cfg in seq.iter() {
+                    let (shift1, stores1) = cfg.footprint(g)?;
+                    stores.extend(stores1.into_iter().map(|offset| offset + shift));
+                    shift += shift1;
+                }
+                Some((shift, stores))
+            }
+            Cfg::Loop(body) | Cfg::If(body) | Cfg::Repeat { body, .. } => {
+                Some((Offset(0), body.stores(g)?))
+            }
+            Cfg::InputCells { .. } => None,
+        }
+    }
+
+    /// Recognizes a loop body, which reads one digit per iteration into an
+    /// accumulator, until one of a set of delimiters is read. The accumulator
+    /// must be zero on entry and the body must not depend on other cells,
+    /// which are not known on entry.
+    fn input_decimal_until(&self, tape: &Tape, g: &mut Graph) -> Option<Cfg> {
+        let mut runs = Vec::with_capacity(256);
+        for input in 0..=255 {
+            let mut exec = Exec::new(tape, g).with_input(input);
+            exec.run(self)?;
+            if exec.ptr != Offset(0) || exec.input_node.is_none() || !exec.outputs.is_empty() {
+                return None;
+            }
+            runs.push(exec);
+        }
+        let is_delimiter = |exec: &Exec<'_>| exec.read(Offset(0)) == Some(0);
+        let delimiters = (0..=255)
+            .filter(|&input| is_delimiter(&runs[input as usize]))
+            .collect::<Vec<u8>>();
+        if delimiters.is_empty() || delimiters.len() == 256 {
+            return None;
+        }
+        let mut accumulators = runs
+            .iter()
+            .flat_map(|exec| exec.cells.keys().copied())
+            .filter(|&offset| tape.get(offset) == Some(0))
+            .collect::<Vec<_>>();
+        accumulators.sort();
+        accumulators.dedup();
+        accumulators.retain(|&offset| {
+            runs.iter().enumerate().all(|(input, exec)| {
+                is_delimiter(exec) || exec.read(offset) == Some((input as u8).wrapping_sub(b'0'))
+            })
+        });
+        // The replacement only performs the guards of a delimiter, which every
+        // run performs last, so it omits the guards of digits.
+        let exit_run = &runs[delimiters[0] as usize];
+        let guarded = exit_run.guarded;
+        let events = exit_run.events.clone();
+        drop(runs);
+        let is_guarded = |offset: Offset| guarded.0 <= offset && offset <= guarded.1;
+        let (acc, exit) = accumulators
+            .into_iter()
+            .filter(|&acc| is_guarded(acc))
+            .find_map(|acc| Some((acc, verify_input_decimal(self, tape, acc, &delimiters, g)?)))?;
+        if !exit.iter().all(|&(offset, _)| is_guarded(offset)) {
+            return None;
+        }
+
+        let mut b = BlockBuilder::new(g);
+        let mut current = 0;
+        let mut value = None;
+        for event in events {
+            match event {
+                Event::Guard(offset) => {
+                    b.shift(offset.0 - current);
+                    current = offset.0;
+                }
+                Event::Input => {
+                    let end = DecimalEnd::Delimiters(delimiters.clone());
+                    value = Some(b.input_decimal(end, g));
+                }
+            }
+        }
+        let value = value?.byte();
+        for (offset, cell) in exit {
+            b.shift(offset.0 - current);
+            current = offset.0;
+            b.set(Node::Const(cell).insert(g));
+        }
+        b.shift(acc.0 - current);
+        b.set(value);
+        b.shift(-acc.0);
+        Some(Cfg::If(Box::new(Cfg::Block(b.finish(g)))))
+    }
+}
+
+/// Verifies that a loop body reads a decimal number into the accumulator for
+/// every value of the accumulator and every byte read. Returns the values of
+/// the other cells stored on exit, which must be constants.
+///
+/// Since the loop is replaced by the stores on exit, every other cell stored
+/// by a digit must either be known on entry and restored, or be unknown on
+/// entry and stored with the same constant on exit.
+fn verify_input_decimal(
+    body: &Cfg,
+    tape: &Tape,
+    acc: Offset,
+    delimiters: &[u8],
+    g: &Graph,
+) -> Option<Vec<(Offset, u8)>> {
+    let mut exit = None::<Vec<(Offset, u8)>>;
+    // The cells, which are unknown on entry, and the values stored to them by
+    // digits.
+    let mut digit_stores = HashSet::new();
+    for value in 0..=255u8 {
+        let mut entry = tape.clone();
+        entry.cells.insert(acc, Some(value));
+        for input in 0..=255u8 {
+            let mut exec = Exec::new(&entry, g).with_input(input);
+            exec.run(body)?;
+            if exec.ptr != Offset(0) || exec.input_node.is_none() || !exec.outputs.is_empty() {
+                return None;
+            }
+            let is_delimiter = delimiters.contains(&input);
+            match exec.read(Offset(0)) {
+                Some(0) if is_delimiter => {}
+                Some(1..) if !is_delimiter => {}
+                _ => return None,
+            }
+            if !is_delimiter {
+                // The next iteration must start from the same cells, except
+                // for the accumulator.
+                let expected = value
+                    .wrapping_mul(10)
+                    .wrapping_add(input.wrapping_sub(b'0'));
+                if exec.read(acc) != Some(expected) {
+                    return None;
+                }
+                for (&offset, &cell) in &exec.cells {
+                    match tape.get(offset) {
+                        _ if offset == acc => {}
+                        Some(known) if known == cell => {}
+                        Some(_) => return None,
+                        None => {
+                            digit_stores.insert((offset, cell));
+                        }
+                    }
+                }
+                continue;
+            }
+            if exec.read(acc) != Some(value) {
+                return None;
+            }
+            let mut cells = exec
+                .cells
+                .iter()
+                .filter(|&(&offset, _)| offset != acc)
+                .map(|(&offset, &cell)| (offset, cell))
+                .collect::<Vec<_>>();
+            cells.sort();
+            match &exit {
+                None => exit = Some(cells),
+                Some(exit) if *exit == cells => {}
+                Some(_) => return None,
+            }
+        }
+    }
+    let exit = exit?;
+    (digit_stores.iter())
+        .all(|store| exit.contains(store))
+        .then_some(exit)
+}
+
+/// Inlines the closed forms in a sequence, which can run unconditionally,
+/// given the known cells on entry, and concatenates the blocks around them.
+fn inline_closed_forms(seq: Seq, tape: &Tape, g: &mut Graph) -> Vec<Cfg> {
+    let mut cfgs = seq.into_iter().collect::<Vec<_>>();
+    let mut tape = tape.clone();
+    for i in 0..cfgs.len() {
+        let exit = cfgs[i].exit_tape(&tape, g);
+        if let Cfg::If(body) = &cfgs[i] {
+            if let Cfg::Block(block) = body.as_ref() {
+                if block.runs_unconditionally(&tape, cfgs.get(i + 1), g) {
+                    let inlined = Cfg::Block(block.clone());
+                    cfgs[i] = inlined;
+                }
+            }
+        }
+        tape = exit;
+    }
+    Seq::from_iter(cfgs, g).into_iter().collect()
+}
+
+/// Recognizes a prefix of a sequence, which prints a cell as a decimal number,
+/// given the known cells on entry. Returns the length of the prefix and an
+/// equivalent block.
+fn output_decimal(cfgs: &[Cfg], tape: &Tape, g: &mut Graph) -> Option<(usize, Block)> {
+    if !cfgs.iter().any(|cfg| has_output(cfg, g)) {
+        return None;
+    }
+    OUTPUT_CELLS
+        .iter()
+        .find_map(|&cell| output_decimal_at(cfgs, Offset(cell), tape, g))
+}
+
+/// The state after printing a number for one value.
+struct Printed {
+    /// The length of the prefix, which printed it.
+    len: usize,
+    /// The cells stored to, relative to the cell pointer on entry.
+    cells: HashMap<Offset, u8>,
+    /// The cell pointer, relative to its position on entry.
+    ptr: Offset,
+    /// The range of guarded cells, relative to the cell pointer on entry.
+    guarded: (Offset, Offset),
+}
+
+/// Recognizes a prefix of a sequence, which prints the cell at the offset as a
+/// decimal number.
+fn output_decimal_at(
+    cfgs: &[Cfg],
+    cell: Offset,
+    tape: &Tape,
+    g: &mut Graph,
+) -> Option<(usize, Block)> {
+    // The lengths of the prefixes, which print the number, for every value
+    // tried so far, and the states after each.
+    let mut lens = (1..=cfgs.len()).collect::<Vec<_>>();
+    let mut execs = Vec::with_capacity(256);
+    // Try a few values first, to reject most prefixes early.
+    let values = [123, 0, 7].into_iter().chain(1..=255).collect::<Vec<u8>>();
+    let mut tried = [false; 256];
+    for value in values {
+        if mem::replace(&mut tried[value as usize], true) {
+            continue;
+        }
+        let mut entry = tape.clone();
+        entry.cells.insert(cell, Some(value));
+        let decimal = value.to_string().into_bytes();
+        let mut exec = Exec::new(&entry, g).with_fuel(OUTPUT_FUEL);
+        let mut printed = Vec::new();
+        for (i, cfg) in cfgs.iter().enumerate() {
+            if exec.run(cfg).is_none() || !decimal.starts_with(&exec.outputs) {
+                break;
+            }
+            if exec.outputs == decimal && lens.contains(&(i + 1)) {
+                printed.push(Printed {
+                    len: i + 1,
+                    cells: exec.cells.clone(),
+                    ptr: exec.ptr,
+                    guarded: exec.guarded,
+                });
+            }
+        }
+        lens.retain(|&len| printed.iter().any(|p| p.len == len));
+        if lens.is_empty() {
+            return None;
+        }
+        execs.push((value, printed));
+    }
+
+    // Use the longest prefix, where every cell is stored a constant or the
+    // number plus a constant.
+    'len: for &len in lens.iter().rev() {
+        let states = execs
+            .iter()
+            .map(|(value, printed)| (*value, printed.iter().find(|p| p.len == len).unwrap()))
+            .collect::<Vec<_>>();
+        let (_, first) = states[0];
+        if states.iter().any(|(_, state)| state.ptr != first.ptr) {
+            continue;
+        }
+        // Only guard the cells, which are guarded for every value, so no
+        // guard is added on a path. As in reorder-effects, the output may move
+        // before guards.
+        let guarded = states.iter().fold(first.guarded, |guarded, (_, state)| {
+            (
+                guarded.0.max(state.guarded.0),
+                guarded.1.min(state.guarded.1),
+            )
+        });
+        let mut offsets = states
+            .iter()
+            .flat_map(|(_, state)| state.cells.keys().copied())
+            .collect::<Vec<_>>();
+        offsets.sort();
+        offsets.dedup();
+        let mut stores = Vec::with_capacity(offsets.len());
+        for offset in offsets {
+            // Cells, which are not stored for some value, keep their value on
+            // entry.
+            let values = states
+                .iter()
+                .map(|&(value, state)| {
+                    let stored = match state.cells.get(&offset) {
+                        Some(&stored) => stored,
+                        None if offset == cell => value,
+                        None => tape.get(offset)?,
+                    };
+                    Some((value, stored))
+                })
+                .collect::<Option<Vec<_>>>();
+            let Some(values) = values else {
+                continue 'len;
+            };
+            let (value0, stored0) = values[0];
+            let addend = stored0.wrapping_sub(value0);
+            if values.iter().all(|&(_, stored)| stored == stored0) {
+                if offset != cell && tape.get(offset) == Some(stored0) {
+                    continue;
+                }
+                stores.push((offset, Stored::Const(stored0)));
+            } else if values
+                .iter()
+                .all(|&(value, stored)| stored == value.wrapping_add(addend))
+            {
+                stores.push((offset, Stored::Number(addend)));
+            } else {
+                continue 'len;
+            }
+        }
+        stores.sort();
+        let is_guarded = |offset: Offset| guarded.0 <= offset && offset <= guarded.1;
+        if !is_guarded(cell) || stores.iter().any(|&(offset, _)| !is_guarded(offset)) {
+            continue;
+        }
+
+        let mut b = BlockBuilder::new(g);
+        b.shift(cell.0);
+        let value = b.get(g);
+        b.output_decimal(value);
+        let mut current = cell.0;
+        for end in [guarded.0, guarded.1] {
+            if end < tape.guarded.0 || end > tape.guarded.1 {
+                b.shift(end.0 - current);
+                current = end.0;
+            }
+        }
+        for (offset, store) in stores {
+            if offset == cell && store == Stored::Number(0) {
+                continue;
+            }
+            b.shift(offset.0 - current);
+            current = offset.0;
+            b.set(match store {
+                Stored::Const(c) => Node::Const(c).insert(g),
+                Stored::Number(0) => value,
+                Stored::Number(addend) => {
+                    let addend = Node::Const(addend).insert(g);
+                    Node::Add(value, addend).insert(g)
+                }
+            });
+        }
+        b.shift(first.ptr.0 - current);
+        return Some((len, b.finish(g)));
+    }
+    None
+}
+
+/// The value of a cell after printing a number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Stored {
+    Const(u8),
+    /// The number plus a constant.
+    Number(u8),
+}
+
+/// Reports whether the CFG prints anything.
+fn has_output(cfg: &Cfg, g: &Graph) -> bool {
+    match cfg {
+        Cfg::Block(block) => block.effects(g).iter().any(|effect| {
+            matches!(
+                effect,
+                Effect::Output(_) | Effect::OutputBytes(_) | Effect::OutputDecimal(_)
+            )
+        }),
+        Cfg::Seq(seq) => seq.iter().any(|cfg| has_output(cfg, g)),
+        Cfg::Loop(body) | Cfg::If(body) | Cfg::Repeat { body, .. } => has_output(body, g),
+        Cfg::InputCells { .. } => false,
+    }
+}
+
+impl Block {
+    /// Reports whether this body of an `if` can run unconditionally, given the
+    /// known cells on entry and the next CFG. It must leave memory unchanged,
+    /// when the current cell is zero, and only perform guards, which are
+    /// already performed on entry or by the next block before any I/O. Guard
+    /// failures are only observable through I/O, so such guards can move
+    /// before the `if`.
+    fn runs_unconditionally(&self, tape: &Tape, next: Option<&Cfg>, g: &mut Graph) -> bool {
+        if self.offset != Offset(0) {
+            return false;
+        }
+        let mut guarded = tape.guarded;
+        if let Some(Cfg::Block(next)) = next {
+            for effect in next.effects(g) {
+                let Effect::GuardShift(offset) = effect else {
+                    break;
+                };
+                guarded.0 = guarded.0.min(offset);
+                guarded.1 = guarded.1.max(offset);
+            }
+        }
+        let guards_performed = self.effects(g).iter().all(|effect| match *effect {
+            Effect::GuardShift(offset) => guarded.0 <= offset && offset <= guarded.1,
+            _ => false,
+        });
+        if !guards_performed {
+            return false;
+        }
+        let zero = Tape {
+            cells: [(Offset(0), Some(0))].into_iter().collect(),
+            ..Tape::default()
+        };
+        let stores = self.iter_memory().collect::<Vec<_>>();
+        stores.into_iter().all(|(offset, cell)| {
+            let cell = cell.with_known_copies(self.id, &zero, g);
+            let copy = Node::Copy(offset, self.id).insert(g);
+            let copy = copy.with_known_copies(self.id, &zero, g);
+            g.canonical(cell) == g.canonical(copy)
+        })
+    }
+
+    /// Recognizes a fixed number of inputs, which are combined into a decimal
+    /// number in a single cell, given the known cells on entry.
+    fn input_digits(&mut self, tape: &Tape, g: &mut Graph) {
+        let effects = self.effects(g);
+        let inputs = effects
+            .iter()
+            .enumerate()
+            .filter_map(|(i, effect)| match *effect {
+                Effect::Input(input) => Some((i, input)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let (Some(&(first, _)), Some(&(last, _))) = (inputs.first(), inputs.last()) else {
+            return;
+        };
+        if inputs.len() > u8::MAX as usize
+            || effects[first..last]
+                .iter()
+                .any(|effect| !matches!(effect, Effect::Input(_) | Effect::GuardShift(_)))
+            || effects.iter().any(|effect| match *effect {
+                Effect::Output(ref values) => values.iter().any(|&v| reads_input(v, g)),
+                Effect::OutputDecimal(v) => reads_input(v, g),
+                _ => false,
+            })
+        {
+            return;
+        }
+        let neg_zero = Node::Const(b'0'.wrapping_neg()).insert(g);
+        let ten = Node::Const(10).insert(g);
+        let mut expected = Node::Const(0).insert(g);
+        for &(_, input) in &inputs {
+            let digit = Node::Add(input.byte(), neg_zero).insert(g);
+            let shifted = Node::Mul(expected, ten).insert(g);
+            expected = Node::Add(shifted, digit).insert(g);
+        }
+        let expected = g.canonical(expected);
+
+        let mut target = None;
+        for (offset, cell) in self.iter_memory() {
+            if !reads_input(cell, g) {
+                continue;
+            }
+            if target.is_some() {
+                return;
+            }
+            target = Some((offset, cell));
+        }
+        let Some((offset, cell)) = target else {
+            return;
+        };
+        let known = cell.with_known_copies(self.id, tape, g);
+        if g.canonical(known) != expected {
+            return;
+        }
+        let value = g.fresh_input();
+        let end = DecimalEnd::Digits(inputs.len() as u8);
+        let effects = (effects.into_iter().enumerate()).filter_map(|(i, effect)| match effect {
+            Effect::Input(_) if i == last => Some(Effect::InputDecimal {
+                value,
+                end: end.clone(),
+            }),
+            Effect::Input(_) => None,
+            effect => Some(effect),
+        });
+        self.set_effects(effects.collect::<Vec<_>>(), g);
+        self.iter_memory_mut(g, |o, cell, _| {
+            Some(if o == offset { value.byte() } else { cell })
+        });
+    }
+}
+
+/// Reports whether the value depends on an input.
+fn reads_input(value: ByteNode, g: &Graph) -> bool {
+    match g[value] {
+        Node::Input(_) => true,
+        Node::Copy(..) | Node::Const(_) => false,
+        Node::Add(lhs, rhs) | Node::Mul(lhs, rhs) | Node::Pow(lhs, rhs) => {
+            reads_input(lhs, g) || reads_input(rhs, g)
+        }
+        Node::Choose(n, _) => reads_input(n, g),
+        _ => unreachable!("non-byte node in byte expression"),
+    }
+}
+
+impl ByteNode {
+    /// Copies the node, with copies of known cells replaced with constants.
+    fn with_known_copies(self, block: BlockId, tape: &Tape, g: &mut Graph) -> ByteNode {
+        match g[self] {
+            Node::Copy(offset, b) if b == block => match tape.get(offset) {
+                Some(c) => Node::Const(c).insert(g),
+                None => self,
+            },
+            Node::Copy(..) | Node::Const(_) | Node::Input(_) => self,
+            Node::Add(lhs, rhs) => {
+                let lhs = lhs.with_known_copies(block, tape, g);
+                let rhs = rhs.with_known_copies(block, tape, g);
+                Node::Add(lhs, rhs).insert(g)
+            }
+            Node::Mul(lhs, rhs) => {
+                let lhs = lhs.with_known_copies(block, tape, g);
+                let rhs = rhs.with_known_copies(block, tape, g);
+                Node::Mul(lhs, rhs).insert(g)
+            }
+            Node::Choose(n, k) => Node::Choose(n.with_known_copies(block, tape, g), k).insert(g),
+            Node::Pow(base, exp) => {
+                let base = base.with_known_copies(block, tape, g);
+                let exp = exp.with_known_copies(block, tape, g);
+                Node::Pow(base, exp).insert(g)
+            }
+            _ => unreachable!("non-byte node in byte expression"),
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use std::{collections::HashMap, mem};
+
+use crate::{
+    analysis::Facts,
+    block::{Block, Effect},
+    cfg::Cfg,
+    egraph::{ByteNode, Graph},
+    node::{Node, Offset},
+    poly::Poly,
+};
+
+/// Facts about cells relative to the cell pointer. Cells which are not present
+/// can have any value.
+type State = HashMap<Offset, Facts>;
+
+impl Cfg {
+    /// Propagates facts about cell values forwards and records them as
+    /// assumptions on the copies of each block. Loops, which provably run at
+    /// most once, are converted to ifs, and values are simplified using the
+    /// facts, e.g., by reducing products of booleans.
+    pub fn opt_facts(&mut self, g: &mut Graph) {
+        self.propagate_facts(State::new(), g);
+    }
+
+    /// Propagates facts through this CFG, given facts on entry, and returns
+    /// facts on exit.
+    fn propagate_facts(&mut self, state: State, g: &mut Graph) -> State {
+        match self {
+            Cfg::Block(block) => block.propagate_facts(state, g),
+            Cfg::Seq(seq) => {
+                let mut state = state;
+                for cfg in seq.iter_mut() {
+                    state = cfg.propagate_facts(state, g);
+                }
+                self.flatten(g);
+                state
+            }
+            Cfg::Loop(_) | Cfg::If(_) | Cfg::InputCells { .. } if !may_enter(&state) => {
+                // The body never runs. The parent sequence concatenates the
+                // blocks around it, when it is flattened.
+                *self = Cfg::empty();
+                state
+            }
+            Cfg::If(_) if state.get(&Offset(0)).is_some_and(|f| !f.may_be_zero()) => {
+                // The body always runs.
+                let Cfg::If(body) = mem::replace(self, Cfg::empty()) else {
+                    unreachable!();
+                };
+                *self = *body;
+                self.propagate_facts(state, g)
+            }
+            Cfg::Loop(body) => {
+                if body.runs_at_most_once(&state, g) {
+                    let Cfg::Loop(body) = mem::replace(self, Cfg::empty()) else {
+                        unreachable!();
+                    };
+                    *self = Cfg::If(body);
+                    return self.propagate_facts(state, g);
+                }
+                let mut body_state = body.invariant_facts(&state);
+                body_state.insert(Offset(0), Facts::nonzero());
+                body.propagate_facts(body_state, g);
+                let mut exit = body.invariant_facts(&state);
+                exit.insert(Offset(0), Facts::constant(0));
+                exit
+            }
+            Cfg::If(body) => {
+                let mut skip_state = state.clone();
+                skip_state.insert(Offset(0), Facts::constant(0));
+                let mut body_state = state;
+                let current = body_state.get(&Offset(0)).unwrap_or(&Facts::TOP);
+                body_state.insert(Offset(0), current.meet(&Facts::nonzero()).unwrap());
+                let body_exit = body.propagate_facts(body_state, g);
+                if body.shift() != Some(Offset(0)) {
+                    return State::new();
+                }
+                join(&skip_state, &body_exit)
+            }
+            &mut Cfg::Repeat {
+                id,
+                count,
+                ref mut body,
+            } => {
+                for (&offset, &facts) in &state {
+                    if let Some(copy) = g.find::<ByteNode>(&Node::Copy(offset, id)) {
+                        g.assume(copy, facts);
+                    }
+                }
+                let count_facts = g.facts(count);
+                if count_facts.as_const() == Some(0) {
+                    *self = Cfg::empty();
+                    return state;
+                }
+                if count_facts.is_boolean() && g[count] == Node::Copy(Offset(0), id) {
+                    let Cfg::Repeat { body, .. } = mem::replace(self, Cfg::empty()) else {
+                        unreachable!();
+                    };
+                    *self = Cfg::If(body);
+                    return self.propagate_facts(state, g);
+                }
+                let body_state = body.invariant_facts(&state);
+                body.propagate_facts(body_state, g);
+                body.invariant_facts(&state)
+            }
+            // The cell pointer ends at the last cell, which is zero.
+            Cfg::InputCells { .. } => State::from([(Offset(0), Facts::constant(0))]),
+        }
+    }
+
+    /// Returns whether this loop body leaves the current cell zero after the
+    /// first iteration, given the facts on entry to the loop.
+    fn runs_at_most_once(&self, state: &State, g: &Graph) -> bool {
+        match self {
+            Cfg::Block(block) if block.offset == Offset(0) => {
+                let Some(current) = block.get_cell(Offset(0)) else {
+                    return false;
+                };
+                let entry = state.get(&Offset(0)).unwrap_or(&Facts::TOP);
+                let Some(entry) = entry.meet(&Facts::nonzero()) else {
+                    return false;
+                };
+                let exit = g.facts_with(current, &mut |id| match g[id] {
+                    Node::Copy(Offset(0), b) if b == block.id => Some(entry),
+                    Node::Copy(offset, b) if b == block.id => state.get(&offset).copied(),
+                    _ => None,
+                });
+                exit.as_const() == Some(0)
+            }
+            Cfg::Seq(seq) if self.shift() == Some(Offset(0)) => match seq.last() {
+                Some(Cfg::Block(block)) => block
+                    .get_cell(block.offset)
+                    .is_some_and(|cell| g.facts(cell).as_const() == Some(0)),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Keeps the facts for cells, which are never stored to in this loop body,
+    /// so hold on every iteration.
+    fn invariant_facts(&self, state: &State) -> State {
+        let mut stores = HashMap::new();
+        if self.count_stores(Offset(0), &mut stores) != Some(Offset(0)) {
+            return State::new();
+        }
+        state
+            .iter()
+            .filter(|(offset, _)| !stores.contains_key(offset))
+            .map(|(&offset, &facts)| (offset, facts))
+            .collect()
+    }
+}
+
+impl Block {
+    /// Records facts on entry as assumptions on the copies in this block,
+    /// simplifies its values, and returns facts on exit.
+    fn propagate_facts(&mut self, state: State, g: &mut Graph) -> State {
+        for (&offset, &facts) in &state {
+            if let Some(copy) = g.find::<ByteNode>(&Node::Copy(offset, self.id)) {
+                g.assume(copy, facts);
+            }
+        }
+        self.iter_memory_mut(g, |_, cell, g| Some(cell.simplify_with_facts(g)));
+        for effect in self.effects(g) {
+            if let Effect::InputUntil { input, delimiters } = effect {
+                let facts = delimiters.iter().map(|&b| Facts::constant(b));
+                if let Some(facts) = facts.reduce(|a, b| a.join(&b)) {
+                    g.assume(input, facts);
+                }
+            }
+        }
+        self.map_outputs(g, |value, g| value.simplify_with_facts(g));
+        let mut exit = State::new();
+        for (offset, facts) in state {
+            if self.get_cell(offset).is_none() {
+                exit.insert(Offset(offset.0 - self.offset.0), facts);
+            }
+        }
+        for (offset, cell) in self.iter_memory() {
+            exit.insert(Offset(offset.0 - self.offset.0), g.facts(cell));
+        }
+        exit.retain(|_, facts| *facts != Facts::TOP);
+        exit
+    }
+}
+
+impl ByteNode {
+    /// Simplifies this value using facts, by folding it to a constant when its
+    /// value is known, substituting atoms with known values, and reducing
+    /// powers of booleans.
+    fn simplify_with_facts(self, g: &mut Graph) -> ByteNode {
+        if let Some(c) = g.facts(self).as_const() {
+            return Node::Const(c).insert(g);
+        }
+        if let Node::Mul(..) | Node::Add(..) = g[self] {
+            if let Some(poly) = Poly::of(self, g).simplify_with_facts(g) {
+                return poly.insert(g);
+            }
+        }
+        self
+    }
+}
+
+/// Returns whether the current cell may be non-zero.
+fn may_enter(state: &State) -> bool {
+    state
+        .get(&Offset(0))
+        .is_none_or(|facts| facts.meet(&Facts::nonzero()).is_some())
+}
+
+/// Computes facts which hold for either state.
+fn join(a: &State, b: &State) -> State {
+    a.iter()
+        .filter_map(|(offset, facts)| Some((*offset, facts.join(b.get(offset)?))))
+        .filter(|(_, facts)| *facts != Facts::TOP)
+        .collect()
+}
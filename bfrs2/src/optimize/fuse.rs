@@ -0,0 +1,97 @@
+use std::{collections::HashMap, mem};
+
+use crate::{
+    cfg::{Cfg, Seq},
+    egraph::Graph,
+    node::{Node, Offset},
+};
+
+impl Cfg {
+    /// Removes loops and ifs, which immediately follow a CFG that leaves the
+    /// current cell zero, because they never run, and fuses adjacent ifs on
+    /// the same cell, when the first does not change the current cell.
+    ///
+    /// A loop only exits once its counter is zero, so of two adjacent
+    /// closed-form loops on the same counter, the second is removed rather
+    /// than fused, which leaves the first as one block. Only ifs, whose body
+    /// preserves the current cell, are fused.
+    pub fn opt_fuse(&mut self, g: &mut Graph) {
+        match self {
+            Cfg::Block(_) | Cfg::InputCells { .. } => {}
+            Cfg::Seq(seq) => {
+                let mut fused = Vec::<Cfg>::with_capacity(seq.len());
+                for mut cfg in mem::replace(seq, Seq::new()) {
+                    cfg.opt_fuse(g);
+                    if let Some(prev) = fused.last_mut() {
+                        if prev.exits_zero(g) && cfg.is_skipped_on_zero(g) {
+                            continue;
+                        }
+                        if let (Cfg::If(prev_then), Cfg::If(cfg_then)) = (&mut *prev, &mut cfg) {
+                            if prev_then.preserves_current() {
+                                let then = mem::replace(&mut **prev_then, Cfg::empty());
+                                let cfg_then = mem::replace(&mut **cfg_then, Cfg::empty());
+                                **prev_then = Seq::from_iter([then, cfg_then], g).into_cfg();
+                                continue;
+                            }
+                        }
+                    }
+                    fused.push(cfg);
+                }
+                *seq = Seq::from_iter(fused, g);
+                self.flatten(g);
+            }
+            Cfg::Loop(body) | Cfg::If(body) | Cfg::Repeat { body, .. } => body.opt_fuse(g),
+        }
+    }
+
+    /// Returns whether the current cell is zero after this CFG.
+    fn exits_zero(&self, g: &Graph) -> bool {
+        match self {
+            Cfg::Block(block) => block
+                .get_cell(block.offset)
+                .is_some_and(|cell| g[cell] == Node::Const(0)),
+            Cfg::Seq(seq) => seq.last().is_some_and(|cfg| cfg.exits_zero(g)),
+            Cfg::Loop(_) | Cfg::InputCells { .. } => true,
+            // When the body is skipped, the current cell is already zero.
+            Cfg::If(body) => body.exits_zero(g),
+            // The count was derived from the counter in the body, so the
+            // counter reaches zero after the last iteration.
+            Cfg::Repeat { body, .. } => self
+                .current_count_factor(g)
+                .is_some_and(|factor| body.iter_factor(g) == Some(factor)),
+        }
+    }
+
+    /// Returns whether this CFG has no effect when the current cell is zero.
+    fn is_skipped_on_zero(&self, g: &Graph) -> bool {
+        match self {
+            Cfg::Block(_) | Cfg::Seq(_) => false,
+            Cfg::Loop(_) | Cfg::If(_) | Cfg::InputCells { .. } => true,
+            Cfg::Repeat { .. } => self.current_count_factor(g).is_some(),
+        }
+    }
+
+    /// Returns the factor, when this is a counted loop, which iterates a
+    /// multiple of the current cell on entry.
+    pub(super) fn current_count_factor(&self, g: &Graph) -> Option<u8> {
+        let &Cfg::Repeat { id, count, .. } = self else {
+            return None;
+        };
+        match g[count] {
+            Node::Copy(Offset(0), block) if block == id => Some(1),
+            Node::Mul(lhs, rhs) if g[lhs] == Node::Copy(Offset(0), id) => match g[rhs] {
+                Node::Const(factor) => Some(factor),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Returns whether this CFG has no net shift and never stores to the
+    /// current cell.
+    fn preserves_current(&self) -> bool {
+        let mut stores = HashMap::new();
+        self.count_stores(Offset(0), &mut stores) == Some(Offset(0))
+            && !stores.contains_key(&Offset(0))
+    }
+}
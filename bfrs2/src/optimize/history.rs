@@ -0,0 +1,301 @@
+use std::{collections::HashSet, fmt::Write};
+
+use crate::{
+    cfg::Cfg,
+    dot,
+    egraph::{Graph, NodeId, Pass},
+    optimize::Pipeline,
+};
+
+/// The history of a pipeline run: the printed CFG before the first pass and
+/// after each pass, with the e-class unions each pass performed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct History {
+    /// The printed CFG before any pass.
+    pub initial: String,
+    /// The passes which were run, in order.
+    pub steps: Vec<Step>,
+}
+
+/// A pass in the history of a pipeline run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Step {
+    /// The pass.
+    pub pass: Pass,
+    /// Whether the pass changed the CFG.
+    pub changed: bool,
+    /// The printed CFG after the pass.
+    pub pretty: String,
+    /// The e-class unions performed by the pass.
+    pub unions: Vec<Union>,
+}
+
+/// A union of e-classes, which made `into` the canonical node of `from`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Union {
+    /// The canonical node of the e-class before the pass, or a node inserted
+    /// by the pass.
+    pub from: NodeId,
+    /// The canonical node after the pass.
+    pub into: NodeId,
+}
+
+impl Pipeline {
+    /// Runs the pipeline on the CFG and records the printed CFG and the e-class
+    /// unions after each pass.
+    pub fn run_with_history(&self, cfg: &mut Cfg, g: &mut Graph) -> History {
+        let mut history = History {
+            initial: cfg.pretty(g),
+            steps: Vec::new(),
+        };
+        self.run_with(cfg, g, &mut |pass, cfg, g| {
+            let canons = g.ids().map(|id| g.canonical(id)).collect::<Vec<_>>();
+            let changed = pass.run(cfg, g);
+            let mut unions = Vec::new();
+            let mut seen = HashSet::new();
+            for id in g.ids() {
+                let from = canons.get(id.index()).copied().unwrap_or(id);
+                let union = Union {
+                    from,
+                    into: g.canonical(id),
+                };
+                if union.from != union.into && seen.insert(union) {
+                    unions.push(union);
+                }
+            }
+            history.steps.push(Step {
+                pass,
+                changed,
+                pretty: cfg.pretty(g),
+                unions,
+            });
+            changed
+        });
+        history
+    }
+}
+
+impl History {
+    /// Formats a report of the history with a unified diff of the printed CFG
+    /// for each pass, which changed it, and the unions it performed.
+    pub fn report(&self, g: &Graph) -> String {
+        let mut s = String::new();
+        let (mut before, mut before_name) = (&self.initial, "initial");
+        for (i, step) in self.steps.iter().enumerate() {
+            let name = step.pass.name();
+            if !step.changed && step.unions.is_empty() {
+                writeln!(s, "pass {}: {name} (unchanged)", i + 1).unwrap();
+                continue;
+            }
+            writeln!(s, "pass {}: {name}", i + 1).unwrap();
+            unified_diff(&mut s, before, &step.pretty, before_name, name);
+            if !step.unions.is_empty() {
+                writeln!(s, "unions:").unwrap();
+                for union in &step.unions {
+                    writeln!(
+                        s,
+                        "  n{} ({}) into n{} ({})",
+                        union.from.index(),
+                        describe(union.from, g),
+                        union.into.index(),
+                        describe(union.into, g),
+                    )
+                    .unwrap();
+                }
+            }
+            (before, before_name) = (&step.pretty, name);
+        }
+        s
+    }
+}
+
+/// Describes a node as an expression, if it is a byte computation, or by its
+/// kind otherwise.
+fn describe(id: NodeId, g: &Graph) -> String {
+    let node = &g[id];
+    if !node.is_byte() {
+        dot::label(node)
+    } else {
+        g.get(id).to_string()
+    }
+}
+
+/// An edit in a line diff.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Edit {
+    /// A line in both, at the indices in `a` and `b`.
+    Keep(usize, usize),
+    /// A line only in `a`.
+    Delete(usize),
+    /// A line only in `b`.
+    Insert(usize),
+}
+
+/// Computes a minimal line diff with Myers' algorithm.
+fn diff_lines(a: &[&str], b: &[&str]) -> Vec<Edit> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = n + m;
+    let offset = max + 1;
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    let mut trace = Vec::new();
+    'search: for d in 0..=max {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let i = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+                v[i + 1]
+            } else {
+                v[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let i = (k + offset) as usize;
+        let prev_k = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = v[(prev_k + offset) as usize];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            edits.push(Edit::Keep(x as usize, y as usize));
+        }
+        if d > 0 {
+            if x == prev_x {
+                edits.push(Edit::Insert(prev_y as usize));
+            } else {
+                edits.push(Edit::Delete(prev_x as usize));
+            }
+        }
+        (x, y) = (prev_x, prev_y);
+    }
+    edits.reverse();
+    edits
+}
+
+/// Writes a unified diff of two texts with three lines of context.
+fn unified_diff(s: &mut String, a: &str, b: &str, a_name: &str, b_name: &str) {
+    const CONTEXT: usize = 3;
+    let a = a.lines().collect::<Vec<_>>();
+    let b = b.lines().collect::<Vec<_>>();
+    let edits = diff_lines(&a, &b);
+    let changes = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| !matches!(edit, Edit::Keep(..)))
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    if changes.is_empty() {
+        return;
+    }
+    writeln!(s, "--- {a_name}").unwrap();
+    writeln!(s, "+++ {b_name}").unwrap();
+    let mut i = 0;
+    while i < changes.len() {
+        // Extend the hunk while the next change is within its context.
+        let mut j = i;
+        while j + 1 < changes.len() && changes[j + 1] - changes[j] <= 2 * CONTEXT {
+            j += 1;
+        }
+        let start = changes[i].saturating_sub(CONTEXT);
+        let end = (changes[j] + CONTEXT + 1).min(edits.len());
+        let hunk = &edits[start..end];
+        // The position of the hunk in each text is where its first line is, or
+        // would be inserted.
+        let (mut a_start, mut b_start) = (a.len(), b.len());
+        for edit in hunk {
+            match *edit {
+                Edit::Keep(x, y) => {
+                    a_start = a_start.min(x);
+                    b_start = b_start.min(y);
+                }
+                Edit::Delete(x) => a_start = a_start.min(x),
+                Edit::Insert(y) => b_start = b_start.min(y),
+            }
+        }
+        let a_len = hunk
+            .iter()
+            .filter(|e| !matches!(e, Edit::Insert(_)))
+            .count();
+        let b_len = hunk
+            .iter()
+            .filter(|e| !matches!(e, Edit::Delete(_)))
+            .count();
+        if a_len == 0 {
+            a_start = edits[..start]
+                .iter()
+                .filter(|e| !matches!(e, Edit::Insert(_)))
+                .count();
+        }
+        if b_len == 0 {
+            b_start = edits[..start]
+                .iter()
+                .filter(|e| !matches!(e, Edit::Delete(_)))
+                .count();
+        }
+        writeln!(
+            s,
+            "@@ -{},{a_len} +{},{b_len} @@",
+            a_start + (a_len != 0) as usize,
+            b_start + (b_len != 0) as usize,
+        )
+        .unwrap();
+        for edit in hunk {
+            match *edit {
+                Edit::Keep(x, _) => writeln!(s, " {}", a[x]),
+                Edit::Delete(x) => writeln!(s, "-{}", a[x]),
+                Edit::Insert(y) => writeln!(s, "+{}", b[y]),
+            }
+            .unwrap();
+        }
+        i = j + 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::optimize::history::unified_diff;
+
+    #[test]
+    fn diff() {
+        let mut s = String::new();
+        unified_diff(&mut s, "a\nb\nc\nd\n", "a\nc\nd\ne\n", "old", "new");
+        assert_eq!(s, "--- old\n+++ new\n@@ -1,4 +1,4 @@\n a\n-b\n c\n d\n+e\n",);
+        let mut s = String::new();
+        unified_diff(&mut s, "", "x\n", "old", "new");
+        assert_eq!(s, "--- old\n+++ new\n@@ -0,0 +1,1 @@\n+x\n");
+        let mut s = String::new();
+        let a = (0..20).map(|i| format!("{i}\n")).collect::<String>();
+        let b = (0..20)
+            .map(|i| match i {
+                2 => "two\n".into(),
+                17 => "seventeen\n".into(),
+                _ => format!("{i}\n"),
+            })
+            .collect::<String>();
+        unified_diff(&mut s, &a, &b, "old", "new");
+        assert_eq!(
+            s,
+            "--- old\n+++ new\n\
+             @@ -1,6 +1,6 @@\n 0\n 1\n-2\n+two\n 3\n 4\n 5\n\
+             @@ -15,6 +15,6 @@\n 14\n 15\n 16\n-17\n+seventeen\n 18\n 19\n",
+        );
+    }
+}
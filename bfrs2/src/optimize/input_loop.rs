@@ -0,0 +1,172 @@
+use crate::{
+    block::{BlockBuilder, Effect},
+    cfg::Cfg,
+    egraph::Graph,
+    node::{Node, Offset},
+    optimize::interpret::{Event, Exec, Tape},
+    visit::Rewriter,
+};
+
+impl Cfg {
+    /// Recognizes loops around a single input, which read until a delimiter,
+    /// and replaces them with higher-level effects. Loops, which read into
+    /// consecutive cells, become `Cfg::InputCells`, and loops, which read into
+    /// the same cell, become `Effect::InputUntil`.
+    pub fn opt_input_loop(&mut self, g: &mut Graph) {
+        self.visit_mut(&mut InputLoop, g);
+    }
+
+    /// Recognizes a loop body, which stores a byte read at the next cell and
+    /// restores the current cell to the delimiter.
+    fn input_cells(&self, g: &mut Graph) -> Option<Cfg> {
+        let Cfg::Block(block) = self else {
+            return None;
+        };
+        let step = block.offset;
+        if step == Offset(0)
+            || block.guarded_left < step.min(Offset(0))
+            || block.guarded_right > step.max(Offset(0))
+        {
+            return None;
+        }
+        let mut input = None;
+        for effect in block.effects(g) {
+            match effect {
+                Effect::GuardShift(_) => {}
+                Effect::Input(node) if input.is_none() => input = Some(node),
+                _ => return None,
+            }
+        }
+        let input = input?.byte();
+        let cell = block.get_cell(step)?;
+        // Find the delimiter from the constant subtracted from the input.
+        let delimiter = if cell == input {
+            0
+        } else {
+            (1..=255u8).find(|&d| {
+                let neg = Node::Const(d.wrapping_neg()).insert(g);
+                let expected = Node::Add(input, neg).insert(g);
+                g.canonical(expected) == g.canonical(cell)
+            })?
+        };
+        let restored = match block.get_cell(Offset(0)) {
+            None => delimiter == 0,
+            Some(current) => {
+                let copy = Node::Copy(Offset(0), block.id).insert(g);
+                let d = Node::Const(delimiter).insert(g);
+                let expected = Node::Add(copy, d).insert(g);
+                delimiter != 0 && g.canonical(expected) == g.canonical(current)
+            }
+        };
+        let stores = block.iter_memory().count();
+        if !restored || stores != 1 + (delimiter != 0) as usize {
+            return None;
+        }
+        Some(Cfg::InputCells { step, delimiter })
+    }
+
+    /// Recognizes a loop body with no net shift, which reads exactly one byte
+    /// and otherwise only depends on cells it has itself stored. The body is
+    /// interpreted for each byte to find the bytes which exit the loop.
+    fn input_until(&self, g: &mut Graph) -> Option<Cfg> {
+        let entry = Tape::default();
+        let mut runs = Vec::with_capacity(256);
+        for input in 0..=255 {
+            let mut exec = Exec::new(&entry, g).with_input(input);
+            exec.run(self)?;
+            if exec.ptr != Offset(0) || exec.input_node.is_none() || !exec.outputs.is_empty() {
+                return None;
+            }
+            runs.push(exec);
+        }
+        let first = &runs[0];
+        if runs.iter().any(|exec| {
+            exec.events != first.events
+                || exec.cells.len() != first.cells.len()
+                || exec
+                    .cells
+                    .keys()
+                    .any(|offset| !first.cells.contains_key(offset))
+        }) {
+            return None;
+        }
+        let delimiters = (0..=255)
+            .filter(|&input| runs[input as usize].cells.get(&Offset(0)) == Some(&0))
+            .collect::<Vec<u8>>();
+        if delimiters.is_empty() {
+            return None;
+        }
+        let mut cells = first.cells.keys().copied().collect::<Vec<_>>();
+        cells.sort();
+        // Each cell must be a constant or the byte read plus a constant, when
+        // the loop exits.
+        let mut values = Vec::with_capacity(cells.len());
+        for &offset in &cells {
+            let value = |input: u8| runs[input as usize].cells[&offset];
+            let d0 = delimiters[0];
+            let value = if delimiters.iter().all(|&d| value(d) == value(d0)) {
+                Value::Const(value(d0))
+            } else {
+                let addend = value(d0).wrapping_sub(d0);
+                if !delimiters
+                    .iter()
+                    .all(|&d| value(d) == d.wrapping_add(addend))
+                {
+                    return None;
+                }
+                Value::Input(addend)
+            };
+            values.push((offset, value));
+        }
+
+        let events = first.events.clone();
+        let mut b = BlockBuilder::new(g);
+        let mut current = 0;
+        let mut input = None;
+        for event in events {
+            match event {
+                Event::Guard(offset) => {
+                    b.shift(offset.0 - current);
+                    current = offset.0;
+                }
+                Event::Input => input = Some(b.input_until(delimiters.clone(), g)),
+            }
+        }
+        let input = input?.byte();
+        for (offset, value) in values {
+            b.shift(offset.0 - current);
+            current = offset.0;
+            let value = match value {
+                Value::Const(c) => Node::Const(c).insert(g),
+                Value::Input(0) => input,
+                Value::Input(addend) => {
+                    let addend = Node::Const(addend).insert(g);
+                    Node::Add(input, addend).insert(g)
+                }
+            };
+            b.set(value);
+        }
+        b.shift(-current);
+        Some(Cfg::If(Box::new(Cfg::Block(b.finish(g)))))
+    }
+}
+
+/// Recognizes input loops, innermost first.
+struct InputLoop;
+
+impl Rewriter for InputLoop {
+    fn post(&mut self, cfg: &mut Cfg, g: &mut Graph) {
+        if let Cfg::Loop(body) = cfg {
+            if let Some(new) = body.input_cells(g).or_else(|| body.input_until(g)) {
+                *cfg = new;
+            }
+        }
+    }
+}
+
+/// The value of a cell when an input loop exits.
+enum Value {
+    Const(u8),
+    /// The delimiter read plus a constant.
+    Input(u8),
+}
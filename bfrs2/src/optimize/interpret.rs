@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+
+use crate::{
+    block::{Block, Effect},
+    cfg::Cfg,
+    egraph::{ByteNode, Graph, InputNode},
+    node::{choose, BlockId, Node, Offset},
+};
+
+/// Cell values relative to the cell pointer, which may be partially known.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(super) struct Tape {
+    /// Cells with a known or explicitly unknown value.
+    pub cells: HashMap<Offset, Option<u8>>,
+    /// The value of all other cells, if known.
+    pub default: Option<u8>,
+    /// The range of cells, which are known to be guarded.
+    pub guarded: (Offset, Offset),
+}
+
+/// A concrete execution of a CFG, which fails as soon as it depends on a value
+/// that is not known.
+pub(super) struct Exec<'a> {
+    g: &'a Graph,
+    /// The cells on entry.
+    entry: &'a Tape,
+    /// The byte returned by the first input, if any input may be read.
+    input: Option<u8>,
+    /// The input node, once it has been read.
+    pub input_node: Option<InputNode>,
+    /// The cells stored to, relative to the cell pointer on entry.
+    pub cells: HashMap<Offset, u8>,
+    /// The bytes output.
+    pub outputs: Vec<u8>,
+    /// The cell pointer, relative to its position on entry.
+    pub ptr: Offset,
+    /// The range of guarded cells, relative to the cell pointer on entry.
+    pub guarded: (Offset, Offset),
+    /// The guards and input in the order they were performed.
+    pub events: Vec<Event>,
+    /// The remaining number of CFG nodes, which may be executed.
+    fuel: usize,
+}
+
+/// A guard or input performed by an execution.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Event {
+    /// A guard, which extends the guarded range to this cell.
+    Guard(Offset),
+    /// The input.
+    Input,
+}
+
+impl Tape {
+    /// Constructs a tape, where every cell is zero, as on entry to a program.
+    pub fn zeroed() -> Self {
+        Tape {
+            cells: HashMap::new(),
+            default: Some(0),
+            guarded: (Offset(0), Offset(0)),
+        }
+    }
+
+    /// Gets the value of a cell, if known.
+    pub fn get(&self, offset: Offset) -> Option<u8> {
+        self.cells.get(&offset).copied().unwrap_or(self.default)
+    }
+
+    /// Updates this tape for the effect of a block on the cells, which are
+    /// unknown, when they cannot be evaluated.
+    pub fn apply_block(&mut self, block: &Block, g: &Graph) {
+        let exec = Exec::new(self, g);
+        let stores = block
+            .iter_memory()
+            .map(|(offset, cell)| (offset, exec.eval(cell, block.id)))
+            .collect::<Vec<_>>();
+        self.cells.extend(stores);
+        self.guarded.0 = self.guarded.0.min(block.guarded_left);
+        self.guarded.1 = self.guarded.1.max(block.guarded_right);
+        self.shift(block.offset);
+    }
+
+    /// Forgets the values of the cells.
+    pub fn forget(&mut self, offsets: impl IntoIterator<Item = Offset>) {
+        self.cells
+            .extend(offsets.into_iter().map(|offset| (offset, None)));
+    }
+
+    /// Joins the cells known on two paths, which reach the same cell pointer.
+    /// Cells are known when they have the same value on both paths.
+    pub fn join(&self, other: &Tape) -> Tape {
+        let cells = (self.cells.keys().chain(other.cells.keys()))
+            .map(|&offset| {
+                let value = self.get(offset);
+                (offset, value.filter(|_| value == other.get(offset)))
+            })
+            .collect();
+        Tape {
+            cells,
+            default: self.default.filter(|_| self.default == other.default),
+            guarded: (
+                self.guarded.0.max(other.guarded.0),
+                self.guarded.1.min(other.guarded.1),
+            ),
+        }
+    }
+
+    /// Moves the cell pointer.
+    pub fn shift(&mut self, offset: Offset) {
+        if offset != Offset(0) {
+            self.cells = self
+                .cells
+                .drain()
+                .map(|(o, value)| (Offset(o.0 - offset.0), value))
+                .collect();
+            self.guarded = (
+                Offset(self.guarded.0 .0 - offset.0),
+                Offset(self.guarded.1 .0 - offset.0),
+            );
+        }
+    }
+}
+
+impl<'a> Exec<'a> {
+    /// Constructs an execution from the cells on entry, which reads no input.
+    pub fn new(entry: &'a Tape, g: &'a Graph) -> Self {
+        Exec {
+            g,
+            entry,
+            input: None,
+            input_node: None,
+            cells: HashMap::new(),
+            outputs: Vec::new(),
+            ptr: Offset(0),
+            guarded: entry.guarded,
+            events: Vec::new(),
+            fuel: 4096,
+        }
+    }
+
+    /// Sets the byte returned by the first input.
+    pub fn with_input(mut self, input: u8) -> Self {
+        self.input = Some(input);
+        self
+    }
+
+    /// Sets the maximum number of CFG nodes to execute.
+    pub fn with_fuel(mut self, fuel: usize) -> Self {
+        self.fuel = fuel;
+        self
+    }
+
+    /// Executes the CFG. Returns `None` when it reads a cell, which is not
+    /// known, performs an input other than the first, or runs out of fuel.
+    pub fn run(&mut self, cfg: &Cfg) -> Option<()> {
+        self.fuel = self.fuel.checked_sub(1)?;
+        match cfg {
+            Cfg::Block(block) => self.run_block(block)?,
+            Cfg::Seq(seq) => {
+                for cfg in seq.iter() {
+                    self.run(cfg)?;
+                }
+            }
+            Cfg::Loop(body) => {
+                while self.read(self.ptr)? != 0 {
+                    self.run(body)?;
+                }
+            }
+            Cfg::If(body) => {
+                if self.read(self.ptr)? != 0 {
+                    self.run(body)?;
+                }
+            }
+            &Cfg::Repeat {
+                id,
+                count,
+                ref body,
+            } => {
+                for _ in 0..self.eval(count, id)? {
+                    self.run(body)?;
+                }
+            }
+            Cfg::InputCells { .. } => return None,
+        }
+        Some(())
+    }
+
+    fn run_block(&mut self, block: &Block) -> Option<()> {
+        for effect in block.effects(self.g) {
+            match effect {
+                Effect::Output(values) => {
+                    for value in values {
+                        let value = self.eval(value, block.id)?;
+                        self.outputs.push(value);
+                    }
+                }
+                Effect::OutputBytes(bytes) => self.outputs.extend_from_slice(&bytes),
+                Effect::OutputDecimal(value) => {
+                    let value = self.eval(value, block.id)?;
+                    self.outputs.extend_from_slice(value.to_string().as_bytes());
+                }
+                Effect::Input(node) if self.input.is_some() && self.input_node.is_none() => {
+                    self.input_node = Some(node);
+                    self.events.push(Event::Input);
+                }
+                Effect::GuardShift(offset) => {
+                    let offset = self.ptr + offset;
+                    if offset < self.guarded.0 {
+                        self.guarded.0 = offset;
+                    } else if offset > self.guarded.1 {
+                        self.guarded.1 = offset;
+                    } else {
+                        continue;
+                    }
+                    self.events.push(Event::Guard(offset));
+                }
+                _ => return None,
+            }
+        }
+        let stores = block
+            .iter_memory()
+            .map(|(offset, cell)| Some((self.ptr + offset, self.eval(cell, block.id)?)))
+            .collect::<Option<Vec<_>>>()?;
+        self.cells.extend(stores);
+        self.ptr += block.offset;
+        Some(())
+    }
+
+    /// Evaluates a node, where copies from the block read the cells relative
+    /// to the cell pointer.
+    pub fn eval(&self, id: ByteNode, block: BlockId) -> Option<u8> {
+        Some(match self.g[id] {
+            Node::Copy(offset, b) if b == block => self.read(self.ptr + offset)?,
+            Node::Copy(..) => return None,
+            Node::Const(c) => c,
+            Node::Input(_) if Some(id) == self.input_node.map(InputNode::byte) => self.input?,
+            Node::Input(_) => return None,
+            Node::Add(lhs, rhs) => self.eval(lhs, block)?.wrapping_add(self.eval(rhs, block)?),
+            Node::Mul(lhs, rhs) => self.eval(lhs, block)?.wrapping_mul(self.eval(rhs, block)?),
+            Node::Choose(n, k) => choose(self.eval(n, block)?, k),
+            Node::Pow(base, exp) => {
+                let exp = self.eval(exp, block)?;
+                self.eval(base, block)?.wrapping_pow(exp as u32)
+            }
+            _ => unreachable!("non-byte node in byte expression"),
+        })
+    }
+
+    /// Reads a cell relative to the cell pointer on entry.
+    pub fn read(&self, offset: Offset) -> Option<u8> {
+        match self.cells.get(&offset) {
+            Some(&value) => Some(value),
+            None => self.entry.get(offset),
+        }
+    }
+
+    /// Gets the cells on exit, relative to the cell pointer on exit.
+    pub fn exit_tape(&self) -> Tape {
+        let mut tape = self.entry.clone();
+        tape.cells.extend(
+            self.cells
+                .iter()
+                .map(|(&offset, &value)| (offset, Some(value))),
+        );
+        tape.guarded = self.guarded;
+        tape.shift(self.ptr);
+        tape
+    }
+}
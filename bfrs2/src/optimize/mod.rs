@@ -1,5 +1,18 @@
 mod add_assign;
+mod coalesce_output;
 mod copy_const;
+mod dead_store;
+mod decimal_io;
+mod facts;
+mod fuse;
+mod history;
+mod input_loop;
+mod interpret;
 mod peel;
+mod pipeline;
+mod reorder_effects;
+mod unroll;
 
 pub use add_assign::unsound_outline_guards;
+pub use history::{History, Step, Union};
+pub use pipeline::{Pipeline, PipelineError};
@@ -1,47 +1,168 @@
-use std::mem;
+use std::{collections::HashMap, mem};
 
 use crate::{
     block::Block,
     cfg::{Cfg, Seq},
-    egraph::Graph,
-    node::Offset,
+    egraph::{ByteNode, Graph},
+    node::{Node, Offset},
+    visit::Rewriter,
 };
 
-// TODO:
-// - Investigate peeling looped sequences.
-
 impl Cfg {
     pub fn opt_peel(&mut self, g: &mut Graph) {
+        self.visit_mut(&mut Peel, g);
+    }
+
+    /// Constructs the body for the iterations after the first of this loop
+    /// body, without the stores which would not change after another
+    /// iteration. Returns `None` when there are no such stores.
+    fn peeled_tail(&self, g: &mut Graph) -> Option<Cfg> {
         match self {
-            Cfg::Block(_) => {}
+            Cfg::Block(block) if block.offset == Offset(0) && block.has_invariant_stores(g) => {
+                let mut tail = block.clone_fresh(g);
+                tail.remove_invariant_stores(block, g);
+                tail.copy_const(block, g);
+                Some(Cfg::Block(tail))
+            }
             Cfg::Seq(seq) => {
-                seq.iter_mut().for_each(|cfg| cfg.opt_peel(g));
-                self.flatten(g);
+                let invariant = seq.invariant_stores(g)?;
+                let mut tail = self.clone_fresh(g);
+                let Cfg::Seq(tail_seq) = &mut tail else {
+                    unreachable!();
+                };
+                for (i, offsets) in invariant {
+                    let Cfg::Block(block) = &mut tail_seq[i] else {
+                        unreachable!();
+                    };
+                    block.iter_memory_mut(g, |offset, cell, _| {
+                        (!offsets.contains(&offset)).then_some(cell)
+                    });
+                }
+                Some(tail)
             }
-            Cfg::Loop(cfg) => {
-                if let Cfg::Block(block) = cfg.as_ref() {
-                    if block.offset == Offset(0) && block.has_invariant_stores(g) {
-                        let mut tail = block.clone_fresh(g);
-                        tail.remove_invariant_stores(block, g);
-                        tail.copy_const(block, g);
-                        let mut tail = Cfg::Loop(Box::new(Cfg::Block(tail)));
-                        tail.opt_closed_form_add(g);
-                        tail.opt_peel(g);
+            _ => None,
+        }
+    }
 
-                        let Cfg::Loop(peeled) = mem::replace(self, Cfg::empty()) else {
-                            unreachable!();
-                        };
-                        let body = Seq::from_iter([*peeled, tail], g).into_cfg();
-                        *self = Cfg::If(Box::new(body));
-                        return;
-                    }
+    /// Peels the first iteration of this loop, with `tail` as the body of the
+    /// loop for the remaining iterations.
+    fn peel(&mut self, tail: Cfg, g: &mut Graph) {
+        let mut tail = Cfg::Loop(Box::new(tail));
+        tail.opt_closed_form_add(g);
+        tail.opt_peel(g);
+
+        let Cfg::Loop(peeled) = mem::replace(self, Cfg::empty()) else {
+            unreachable!();
+        };
+        let body = Seq::from_iter([*peeled, tail], g).into_cfg();
+        *self = Cfg::If(Box::new(body));
+    }
+
+    /// Counts the number of CFG nodes which store to each cell, relative to
+    /// the cell pointer on entry, and returns the net shift. Returns `None`
+    /// when a shift is not statically known, i.e., when a nested loop or if
+    /// has a net shift.
+    pub(super) fn count_stores(
+        &self,
+        base: Offset,
+        stores: &mut HashMap<Offset, usize>,
+    ) -> Option<Offset> {
+        match self {
+            Cfg::Block(block) => {
+                for (offset, _) in block.iter_memory() {
+                    *stores.entry(base + offset).or_default() += 1;
+                }
+                Some(block.offset)
+            }
+            Cfg::Seq(seq) => {
+                let mut shift = Offset(0);
+                for cfg in seq.iter() {
+                    shift += cfg.count_stores(base + shift, stores)?;
+                }
+                Some(shift)
+            }
+            Cfg::Loop(body) | Cfg::If(body) | Cfg::Repeat { body, .. } => {
+                (body.count_stores(base, stores)? == Offset(0)).then_some(Offset(0))
+            }
+            Cfg::InputCells { .. } => None,
+        }
+    }
+}
+
+impl Seq {
+    /// Finds the stores in the top-level blocks of this sequence, when it is
+    /// the body of a loop, which would not change after another iteration. A
+    /// store is invariant when its cell is stored to by no other part of the
+    /// body and its value reads no input or cell which is stored to in the
+    /// body. Returns the index of each block with invariant stores and the
+    /// offsets of those stores, or `None` when there are none or the body has
+    /// a net shift.
+    fn invariant_stores(&self, g: &Graph) -> Option<Vec<(usize, Vec<Offset>)>> {
+        let mut stores = HashMap::new();
+        let mut bases = Vec::with_capacity(self.len());
+        let mut shift = Offset(0);
+        for cfg in self.iter() {
+            bases.push(shift);
+            shift += cfg.count_stores(shift, &mut stores)?;
+        }
+        if shift != Offset(0) {
+            return None;
+        }
+        let mut invariant = Vec::new();
+        for (i, (cfg, &base)) in self.iter().zip(&bases).enumerate() {
+            if let Cfg::Block(block) = cfg {
+                let offsets = block
+                    .iter_memory()
+                    .filter(|&(offset, cell)| {
+                        stores[&(base + offset)] == 1
+                            && !block.value_reads_stores(cell, base, &stores, g)
+                    })
+                    .map(|(offset, _)| offset)
+                    .collect::<Vec<_>>();
+                if !offsets.is_empty() {
+                    invariant.push((i, offsets));
                 }
-                cfg.opt_peel(g);
             }
-            Cfg::If(cfg_then) => {
-                cfg_then.opt_peel(g);
+        }
+        (!invariant.is_empty()).then_some(invariant)
+    }
+}
+
+/// Peels the first iteration of loops with invariant stores.
+struct Peel;
+
+impl Rewriter for Peel {
+    fn pre(&mut self, cfg: &mut Cfg, g: &mut Graph) -> bool {
+        match cfg {
+            Cfg::Loop(body) => {
+                if let Some(tail) = body.peeled_tail(g) {
+                    cfg.rewrite(g, |cfg, g| cfg.peel(tail, g));
+                    return false;
+                }
             }
+            Cfg::Repeat { .. } => {
+                // A loop counted by the current cell, which was converted to a
+                // counted loop before its invariant stores were peeled.
+                let factor = cfg.current_count_factor(g);
+                let Cfg::Repeat { body, .. } = &*cfg else {
+                    unreachable!();
+                };
+                if factor.is_some() && factor == body.iter_factor(g) {
+                    if let Some(tail) = body.peeled_tail(g) {
+                        cfg.rewrite(g, |cfg, g| {
+                            let Cfg::Repeat { body, .. } = mem::replace(cfg, Cfg::empty()) else {
+                                unreachable!();
+                            };
+                            *cfg = Cfg::Loop(body);
+                            cfg.peel(tail, g);
+                        });
+                        return false;
+                    }
+                }
+            }
+            _ => {}
         }
+        true
     }
 }
 
@@ -69,4 +190,29 @@ impl Block {
             }
         });
     }
+
+    /// Returns whether a value in this block reads an input or a cell, which
+    /// is in `stores`. The block is at `base` relative to the offsets in
+    /// `stores`.
+    fn value_reads_stores(
+        &self,
+        value: ByteNode,
+        base: Offset,
+        stores: &HashMap<Offset, usize>,
+        g: &Graph,
+    ) -> bool {
+        match g[value] {
+            Node::Copy(offset, block_id) => {
+                block_id != self.id || stores.contains_key(&(base + offset))
+            }
+            Node::Const(_) => false,
+            Node::Input(_) => true,
+            Node::Add(lhs, rhs) | Node::Mul(lhs, rhs) | Node::Pow(lhs, rhs) => {
+                self.value_reads_stores(lhs, base, stores, g)
+                    || self.value_reads_stores(rhs, base, stores, g)
+            }
+            Node::Choose(n, _) => self.value_reads_stores(n, base, stores, g),
+            _ => unreachable!("non-byte node in byte expression"),
+        }
+    }
 }
@@ -0,0 +1,295 @@
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+};
+
+use crate::{
+    cfg::Cfg,
+    egraph::{Graph, Pass},
+};
+
+/// A sequence of optimization passes, which can be repeated until a fixed
+/// point.
+///
+/// Pipelines are described by comma-separated pass names. A pass or a
+/// parenthesized group followed by `*` is repeated until it no longer changes
+/// the CFG or the iteration limit is reached. For example,
+/// `"closed-form,peel,copy-const*"` or `"(closed-form,peel)*"`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pipeline {
+    stages: Vec<Stage>,
+    /// The maximum number of iterations of a repeated stage.
+    limit: usize,
+    /// Whether to verify the IR after each pass.
+    verify: bool,
+}
+
+/// A stage in a pipeline.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Stage {
+    /// Run a pass once.
+    Pass(Pass),
+    /// Run a group of stages in sequence, optionally repeating until a fixed
+    /// point.
+    Group(Vec<Stage>, bool),
+}
+
+/// An error from parsing a pipeline.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PipelineError {
+    /// A name which is not a pass.
+    UnknownPass(String),
+    /// A pass which cannot be run on a CFG.
+    NotRunnable(Pass),
+    /// An unexpected character.
+    UnexpectedChar(char),
+    /// Unmatched `(`.
+    UnclosedGroup,
+    /// Unmatched `)`.
+    UnopenedGroup,
+    /// An empty pass name.
+    Empty,
+}
+
+impl Pipeline {
+    /// The default number of iterations for a repeated stage.
+    pub const DEFAULT_LIMIT: usize = 16;
+
+    /// Parses a pipeline from its description.
+    pub fn parse(src: &str) -> Result<Self, PipelineError> {
+        let mut src = src.trim();
+        let stages = Pipeline::parse_group(&mut src, false)?;
+        Ok(Pipeline {
+            stages,
+            limit: Self::DEFAULT_LIMIT,
+            verify: false,
+        })
+    }
+
+    fn parse_group(src: &mut &str, nested: bool) -> Result<Vec<Stage>, PipelineError> {
+        let mut stages = Vec::new();
+        loop {
+            *src = src.trim_start();
+            let stage = if let Some(rest) = src.strip_prefix('(') {
+                *src = rest;
+                let group = Pipeline::parse_group(src, true)?;
+                *src = src.strip_prefix(')').ok_or(PipelineError::UnclosedGroup)?;
+                Stage::Group(group, false)
+            } else {
+                let len = src
+                    .find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '-'))
+                    .unwrap_or(src.len());
+                let (name, rest) = src.split_at(len);
+                *src = rest;
+                if name.is_empty() {
+                    return Err(match src.chars().next() {
+                        Some(ch) => PipelineError::UnexpectedChar(ch),
+                        None => PipelineError::Empty,
+                    });
+                }
+                let pass = Pass::from_name(name)
+                    .ok_or_else(|| PipelineError::UnknownPass(name.to_owned()))?;
+                if !pass.is_runnable() {
+                    return Err(PipelineError::NotRunnable(pass));
+                }
+                Stage::Pass(pass)
+            };
+            *src = src.trim_start();
+            let stage = if let Some(rest) = src.strip_prefix('*') {
+                *src = rest.trim_start();
+                match stage {
+                    Stage::Group(group, _) => Stage::Group(group, true),
+                    stage => Stage::Group(vec![stage], true),
+                }
+            } else {
+                stage
+            };
+            stages.push(stage);
+            match src.chars().next() {
+                Some(',') => *src = &src[1..],
+                Some(')') if nested => return Ok(stages),
+                Some(')') => return Err(PipelineError::UnopenedGroup),
+                Some(ch) => return Err(PipelineError::UnexpectedChar(ch)),
+                None if nested => return Err(PipelineError::UnclosedGroup),
+                None => return Ok(stages),
+            }
+        }
+    }
+
+    /// Sets the maximum number of iterations of a repeated stage.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Sets whether to verify the CFG and e-graph after each pass. A pass,
+    /// which produces invalid IR, panics with the violated invariant.
+    pub fn with_verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Runs the pipeline on the CFG and returns whether it changed.
+    pub fn run(&self, cfg: &mut Cfg, g: &mut Graph) -> bool {
+        self.run_stages(&self.stages, cfg, g, &mut Pass::run)
+    }
+
+    /// Runs the pipeline on the CFG, calling `run_pass` to run each pass,
+    /// which can observe the CFG and e-graph around it. Returns whether the
+    /// CFG changed.
+    pub(super) fn run_with(
+        &self,
+        cfg: &mut Cfg,
+        g: &mut Graph,
+        run_pass: &mut dyn FnMut(Pass, &mut Cfg, &mut Graph) -> bool,
+    ) -> bool {
+        self.run_stages(&self.stages, cfg, g, run_pass)
+    }
+
+    fn run_stages(
+        &self,
+        stages: &[Stage],
+        cfg: &mut Cfg,
+        g: &mut Graph,
+        run_pass: &mut dyn FnMut(Pass, &mut Cfg, &mut Graph) -> bool,
+    ) -> bool {
+        let mut changed = false;
+        for stage in stages {
+            changed |= match stage {
+                &Stage::Pass(pass) => {
+                    let changed = run_pass(pass, cfg, g);
+                    if self.verify {
+                        if let Err(err) = cfg.verify(g).and_then(|()| g.verify()) {
+                            panic!("invalid IR after pass {}: {err}", pass.name());
+                        }
+                    }
+                    changed
+                }
+                Stage::Group(stages, false) => self.run_stages(stages, cfg, g, run_pass),
+                Stage::Group(stages, true) => {
+                    let mut changed = false;
+                    for _ in 0..self.limit {
+                        if !self.run_stages(stages, cfg, g, run_pass) {
+                            break;
+                        }
+                        changed = true;
+                    }
+                    changed
+                }
+            };
+        }
+        changed
+    }
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline::parse("closed-form,peel").unwrap()
+    }
+}
+
+impl Pass {
+    /// Returns whether this pass transforms a CFG and can be run in a
+    /// pipeline.
+    pub fn is_runnable(self) -> bool {
+        matches!(
+            self,
+            Pass::AddLoopToMul
+                | Pass::QuasiInvariantPeel
+                | Pass::CopyConst
+                | Pass::Unroll
+                | Pass::DeadStore
+                | Pass::Fuse
+                | Pass::Facts
+                | Pass::CoalesceOutput
+                | Pass::InputLoop
+                | Pass::DecimalIo
+                | Pass::ReorderEffects
+        )
+    }
+
+    /// Runs this pass on the CFG and records it as the creator of new nodes.
+    /// Returns whether the CFG changed. Panics if the pass is not runnable.
+    pub fn run(self, cfg: &mut Cfg, g: &mut Graph) -> bool {
+        let before = cfg.clone();
+        let prev = g.pass();
+        g.set_pass(self);
+        match self {
+            Pass::AddLoopToMul => cfg.opt_closed_form_add(g),
+            Pass::QuasiInvariantPeel => cfg.opt_peel(g),
+            Pass::CopyConst => cfg.opt_copy_const(None, g),
+            Pass::Unroll => cfg.opt_unroll(None, g),
+            Pass::DeadStore => cfg.opt_dead_store(g),
+            Pass::Fuse => cfg.opt_fuse(g),
+            Pass::Facts => cfg.opt_facts(g),
+            Pass::CoalesceOutput => cfg.opt_coalesce_output(g),
+            Pass::InputLoop => cfg.opt_input_loop(g),
+            Pass::DecimalIo => cfg.opt_decimal_io(g),
+            Pass::ReorderEffects => cfg.opt_reorder_effects(g),
+            _ => panic!("pass {self:?} cannot be run on a CFG"),
+        }
+        g.set_pass(prev);
+        *cfg != before
+    }
+}
+
+impl Display for PipelineError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PipelineError::UnknownPass(name) => write!(f, "unknown pass {name:?}"),
+            PipelineError::NotRunnable(pass) => write!(f, "pass {} cannot be run", pass.name()),
+            PipelineError::UnexpectedChar(ch) => write!(f, "unexpected {ch:?} in pipeline"),
+            PipelineError::UnclosedGroup => write!(f, "unmatched ("),
+            PipelineError::UnopenedGroup => write!(f, "unmatched )"),
+            PipelineError::Empty => write!(f, "empty pass name"),
+        }
+    }
+}
+
+impl Error for PipelineError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        egraph::Pass,
+        optimize::pipeline::{Pipeline, PipelineError, Stage},
+    };
+
+    #[test]
+    fn parse() {
+        let pipeline = Pipeline::parse("closed-form, peel ,copy-const*").unwrap();
+        assert_eq!(
+            pipeline.stages,
+            [
+                Stage::Pass(Pass::AddLoopToMul),
+                Stage::Pass(Pass::QuasiInvariantPeel),
+                Stage::Group(vec![Stage::Pass(Pass::CopyConst)], true),
+            ],
+        );
+        let pipeline = Pipeline::parse("(closed-form,(peel)*)*").unwrap();
+        assert_eq!(
+            pipeline.stages,
+            [Stage::Group(
+                vec![
+                    Stage::Pass(Pass::AddLoopToMul),
+                    Stage::Group(vec![Stage::Pass(Pass::QuasiInvariantPeel)], true),
+                ],
+                true,
+            )],
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        let err = |src| Pipeline::parse(src).unwrap_err();
+        assert_eq!(
+            err("peel,unroll-all"),
+            PipelineError::UnknownPass("unroll-all".into())
+        );
+        assert_eq!(err("parse"), PipelineError::NotRunnable(Pass::Parse));
+        assert_eq!(err("(peel"), PipelineError::UnclosedGroup);
+        assert_eq!(err("peel)"), PipelineError::UnopenedGroup);
+        assert_eq!(err("peel,"), PipelineError::Empty);
+        assert_eq!(err("peel;"), PipelineError::UnexpectedChar(';'));
+    }
+}
@@ -0,0 +1,71 @@
+use crate::{
+    block::{Block, Effect},
+    cfg::Cfg,
+    egraph::{EffectNode, Graph},
+    node::Node,
+    visit::Rewriter,
+};
+
+impl Cfg {
+    /// Reorders independent effects in the chain of effect tokens of each
+    /// block. Outputs of constants do not depend on memory, so they are moved
+    /// before shift guards, and adjacent outputs are merged. The order of I/O
+    /// effects is preserved.
+    pub fn opt_reorder_effects(&mut self, g: &mut Graph) {
+        self.visit_mut(&mut ReorderEffects, g);
+    }
+}
+
+/// Reorders the effects of each block.
+struct ReorderEffects;
+
+impl Rewriter for ReorderEffects {
+    fn post(&mut self, cfg: &mut Cfg, g: &mut Graph) {
+        if let Cfg::Block(block) = cfg {
+            block.reorder_effects(g);
+        }
+    }
+}
+
+impl Block {
+    /// Reorders the effects of this block and retains the previous chain in the
+    /// e-class of the new chain.
+    fn reorder_effects(&mut self, g: &mut Graph) {
+        let Some(old) = self.effect_token() else {
+            return;
+        };
+        let entry: EffectNode = Node::Entry(self.id).insert_ideal(g);
+        let new =
+            (self.effects(g).into_iter()).fold(entry, |token, effect| token.push_effect(effect, g));
+        if new != old {
+            old.replace(new, g);
+            self.set_effect_token(new, g);
+        }
+    }
+}
+
+impl EffectNode {
+    /// Appends an effect to the chain ending at this token and returns the new
+    /// last token. The effect is moved before trailing shift guards, when it
+    /// outputs constants, and is merged into a preceding output of the same
+    /// kind.
+    fn push_effect(self, effect: Effect, g: &mut Graph) -> Self {
+        let Node::Effect(prev, ref last) = g[self] else {
+            return Node::Effect(self, effect).insert_ideal(g);
+        };
+        let merged = match (last, effect) {
+            (&Effect::GuardShift(offset), effect) if effect.is_const_output(g) => {
+                let token = prev.push_effect(effect, g);
+                return Node::Effect(token, Effect::GuardShift(offset)).insert_ideal(g);
+            }
+            (Effect::Output(values1), Effect::Output(values2)) => {
+                Effect::Output([values1.as_slice(), &values2].concat())
+            }
+            (Effect::OutputBytes(bytes1), Effect::OutputBytes(bytes2)) => {
+                Effect::OutputBytes([bytes1.as_slice(), &bytes2].concat())
+            }
+            (_, effect) => return Node::Effect(self, effect).insert_ideal(g),
+        };
+        Node::Effect(prev, merged).insert_ideal(g)
+    }
+}
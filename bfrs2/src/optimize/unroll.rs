@@ -0,0 +1,143 @@
+use std::{collections::HashMap, mem};
+
+use crate::{
+    block::Block,
+    cfg::{Cfg, Seq},
+    egraph::Graph,
+    node::{Node, Offset},
+};
+
+/// The maximum number of iterations of a loop, which is fully unrolled.
+const MAX_FULL_UNROLL: usize = 16;
+/// The maximum size of a fully unrolled loop, as counted by `Cfg::size`.
+const MAX_UNROLLED_SIZE: usize = 256;
+/// The number of copies of the body in a partially unrolled loop.
+const PARTIAL_UNROLL: usize = 4;
+
+impl Cfg {
+    /// Unrolls loops with a constant number of iterations. Loops with few
+    /// iterations and a small body are fully unrolled and others are unrolled
+    /// by a constant factor, with the remaining iterations after it. The number
+    /// of iterations is known for counted loops with a constant count and for
+    /// loops, whose counter is constant in the preceding block.
+    pub fn opt_unroll(&mut self, pred: Option<&Block>, g: &mut Graph) {
+        match self {
+            Cfg::Block(_) | Cfg::InputCells { .. } => {}
+            Cfg::Seq(seq) => {
+                let mut pred = pred;
+                for cfg in seq.as_slice_mut() {
+                    cfg.opt_unroll(pred, g);
+                    pred = match cfg {
+                        Cfg::Block(block) => Some(block),
+                        Cfg::Seq(seq) => match seq.last() {
+                            Some(Cfg::Block(block)) => Some(block),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                }
+                self.flatten(g);
+            }
+            Cfg::Loop(body) => {
+                body.opt_unroll(None, g);
+                let iters = pred
+                    .and_then(|pred| pred.get_cell(pred.offset))
+                    .and_then(|counter| match g[counter] {
+                        Node::Const(counter) => Some(counter),
+                        _ => None,
+                    })
+                    .zip(body.iter_factor(g))
+                    .map(|(counter, factor)| counter.wrapping_mul(factor));
+                if let Some(iters) = iters {
+                    self.unroll(iters as usize, g);
+                }
+            }
+            Cfg::If(cfg_then) => cfg_then.opt_unroll(pred, g),
+            Cfg::Repeat { count, body, .. } => {
+                body.opt_unroll(None, g);
+                if let Node::Const(iters) = g[*count] {
+                    self.unroll(iters as usize, g);
+                }
+            }
+        }
+    }
+
+    /// Unrolls this loop, which executes a known number of iterations.
+    fn unroll(&mut self, iters: usize, g: &mut Graph) {
+        let (Cfg::Loop(body) | Cfg::Repeat { body, .. }) = mem::replace(self, Cfg::empty()) else {
+            unreachable!();
+        };
+        let size = body.size(g);
+        let (copies, rest) = if iters <= MAX_FULL_UNROLL && iters * size <= MAX_UNROLLED_SIZE {
+            (iters, 0)
+        } else {
+            (iters % PARTIAL_UNROLL, iters / PARTIAL_UNROLL)
+        };
+        let mut seq = Seq::with_capacity(copies + 1);
+        if rest != 0 {
+            let unrolled = (0..PARTIAL_UNROLL)
+                .map(|_| body.clone_fresh(g))
+                .collect::<Vec<_>>();
+            let unrolled = Seq::from_iter(unrolled, g);
+            let id = g.fresh_block_id();
+            let count = Node::Const(rest as u8).insert_ideal(g);
+            seq.push(
+                Cfg::Repeat {
+                    id,
+                    count,
+                    body: Box::new(unrolled.into_cfg()),
+                },
+                g,
+            );
+        }
+        if copies != 0 {
+            // Reuse the original body for the first copy.
+            let clones = (1..copies).map(|_| body.clone_fresh(g)).collect::<Vec<_>>();
+            seq.push(*body, g);
+            clones.into_iter().for_each(|cfg| seq.push(cfg, g));
+        }
+        *self = seq.into_cfg();
+    }
+
+    /// Calculates the factor of the number of iterations this CFG would execute
+    /// as the body of a loop. The number of iterations is the factor multiplied
+    /// by the current cell. This can be calculated when the body has no net
+    /// shift and the current cell is stored only in a single top-level block,
+    /// which adds an odd constant to it.
+    pub(super) fn iter_factor(&self, g: &Graph) -> Option<u8> {
+        match self {
+            Cfg::Block(block) => block.closed_form_iter_factor(g),
+            Cfg::Seq(seq) => {
+                let mut stores = HashMap::new();
+                if self.count_stores(Offset(0), &mut stores)? != Offset(0)
+                    || stores.get(&Offset(0)) != Some(&1)
+                {
+                    return None;
+                }
+                let mut base = Offset(0);
+                for cfg in seq.iter() {
+                    if let Cfg::Block(block) = cfg {
+                        let offset = Offset(-base.0);
+                        if block.get_cell(offset).is_some() {
+                            return block.counter_factor(offset, g);
+                        }
+                        base += block.offset;
+                    }
+                }
+                None
+            }
+            Cfg::Loop(_) | Cfg::If(_) | Cfg::Repeat { .. } | Cfg::InputCells { .. } => None,
+        }
+    }
+
+    /// Approximates the size of the code for this CFG by counting stores,
+    /// effects, and control-flow nodes.
+    fn size(&self, g: &Graph) -> usize {
+        match self {
+            Cfg::Block(block) => block.iter_memory().count() + block.effects(g).len(),
+            Cfg::Seq(seq) => seq.iter().map(|cfg| cfg.size(g)).sum(),
+            Cfg::Loop(body) | Cfg::If(body) | Cfg::Repeat { body, .. } => 1 + body.size(g),
+            Cfg::InputCells { .. } => 1,
+        }
+    }
+}
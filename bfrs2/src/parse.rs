@@ -7,7 +7,7 @@ use std::{
 use crate::{
     block::BlockBuilder,
     cfg::{Cfg, Seq},
-    egraph::Graph,
+    egraph::{Graph, Pass},
 };
 
 /// An error from parsing a Brainfuck program.
@@ -22,7 +22,11 @@ pub enum ParseError {
 impl Graph {
     /// Parses a Brainfuck program to a CFG.
     pub fn parse(&mut self, src: &[u8]) -> Result<Cfg, ParseError> {
-        Parser::new(src, self).parse(true)
+        let pass = self.pass();
+        self.set_pass(Pass::Parse);
+        let cfg = Parser::new(src, self).parse(true);
+        self.set_pass(pass);
+        cfg
     }
 }
 
@@ -0,0 +1,687 @@
+//! Parsing of the textual IR, as printed by `Cfg::pretty`.
+//!
+//! Statements are separated by newlines or `;`. Within a basic block, every
+//! value reads the cells on entry to the block, regardless of the order of
+//! statements, so `p[1] = c0 + 1` and `output([p[0]])` are equivalent to the
+//! printed form, where effects precede stores. Names are bound with `let` and
+//! are scoped to their block. Inputs named like `in3` keep their ID.
+//!
+//! The syntax of bfrs1, as in the `.ir` files of the tests, is also read. It
+//! reads cells directly as `@0`, shifts with `shift 1`, writes the arguments
+//! of effects without parentheses, and binds inputs without `let`, which are
+//! numbered within each block.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Display, Formatter},
+};
+
+use crate::{
+    block::{Block, DecimalEnd, Effect},
+    cfg::{Cfg, Seq},
+    egraph::{ByteNode, Graph, InputNode, Pass},
+    node::{BlockId, InputId, Node, Offset},
+    pretty::Syntax,
+};
+
+/// An error from parsing the textual IR.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IrError {
+    /// The 1-based line of the error.
+    pub line: usize,
+    /// The kind of error.
+    pub kind: IrErrorKind,
+}
+
+/// The kind of an error from parsing the textual IR.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IrErrorKind {
+    /// The IR ended before a complete statement.
+    UnexpectedEof,
+    /// An unexpected token.
+    UnexpectedToken(String),
+    /// An unexpected character.
+    UnexpectedChar(char),
+    /// A name, which is not bound by `let` in the current block.
+    UnboundName(String),
+    /// A constant, which does not fit in a byte.
+    ConstOutOfRange(String),
+    /// An invalid escape sequence in a character or string literal.
+    BadEscape,
+    /// A character or string literal without a closing quote.
+    UnterminatedLiteral,
+    /// Unmatched `{`.
+    UnclosedBrace,
+    /// Unmatched `}`.
+    UnopenedBrace,
+}
+
+impl Graph {
+    /// Parses the textual IR to a CFG.
+    pub fn parse_ir(&mut self, src: &str) -> Result<Cfg, IrError> {
+        self.parse_ir_with(src, Syntax::Bfrs2)
+    }
+
+    /// Parses the textual IR in the given syntax to a CFG.
+    pub fn parse_ir_with(&mut self, src: &str, syntax: Syntax) -> Result<Cfg, IrError> {
+        let pass = self.pass();
+        self.set_pass(Pass::Parse);
+        let cfg = IrParser::new(src, syntax, self).and_then(|mut p| p.parse_seq(false));
+        self.set_pass(pass);
+        cfg
+    }
+}
+
+/// A token of the textual IR.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token<'s> {
+    Ident(&'s str),
+    Int(i64),
+    Char(u8),
+    Str(Vec<u8>),
+    Punct(&'static str),
+    /// The end of a statement, i.e., a newline or `;`.
+    End,
+}
+
+struct IrParser<'s, 'g> {
+    tokens: Vec<(Token<'s>, usize)>,
+    pos: usize,
+    syntax: Syntax,
+    g: &'g mut Graph,
+    /// The block under construction.
+    block: Option<Block>,
+    /// The names bound in the current block.
+    names: HashMap<&'s str, ByteNode>,
+}
+
+impl<'s, 'g> IrParser<'s, 'g> {
+    /// Constructs a new parser and tokenizes the source.
+    fn new(src: &'s str, syntax: Syntax, g: &'g mut Graph) -> Result<Self, IrError> {
+        Ok(IrParser {
+            tokens: tokenize(src, syntax)?,
+            pos: 0,
+            syntax,
+            g,
+            block: None,
+            names: HashMap::new(),
+        })
+    }
+
+    /// Parses a sequence of statements, up to a closing brace, when nested, or
+    /// the end of the source.
+    fn parse_seq(&mut self, nested: bool) -> Result<Cfg, IrError> {
+        let mut seq = Vec::new();
+        loop {
+            let Some(token) = self.peek() else {
+                if nested {
+                    return Err(self.error(IrErrorKind::UnclosedBrace));
+                }
+                break;
+            };
+            match *token {
+                Token::End => {
+                    self.pos += 1;
+                }
+                Token::Punct("}") => {
+                    if !nested {
+                        return Err(self.error(IrErrorKind::UnopenedBrace));
+                    }
+                    self.pos += 1;
+                    break;
+                }
+                Token::Punct("{") => {
+                    self.pos += 1;
+                    self.finish_block(&mut seq);
+                    while !self.eat(&Token::Punct("}"))? {
+                        if !self.eat(&Token::End)? {
+                            self.parse_block_stmt()?;
+                        }
+                    }
+                    self.finish_block(&mut seq);
+                }
+                Token::Ident(kw @ ("while" | "if")) => {
+                    self.pos += 1;
+                    self.finish_block(&mut seq);
+                    let cell: &[Token<'_>] = match self.syntax {
+                        Syntax::Bfrs2 => &[
+                            Token::Ident("p"),
+                            Token::Punct("["),
+                            Token::Int(0),
+                            Token::Punct("]"),
+                        ],
+                        Syntax::Bfrs1 => &[Token::Punct("@"), Token::Int(0)],
+                    };
+                    let cond = [Token::Punct("!="), Token::Int(0), Token::Punct("{")];
+                    for token in cell.iter().chain(&cond) {
+                        self.expect(token)?;
+                    }
+                    let body = Box::new(self.parse_seq(true)?);
+                    seq.push(if kw == "while" {
+                        Cfg::Loop(body)
+                    } else {
+                        Cfg::If(body)
+                    });
+                }
+                Token::Ident("repeat") => {
+                    self.pos += 1;
+                    self.finish_block(&mut seq);
+                    let id = self.g.fresh_block_id();
+                    let count = self.parse_expr(id)?;
+                    self.expect(&Token::Ident("times"))?;
+                    self.expect(&Token::Punct("{"))?;
+                    let body = Box::new(self.parse_seq(true)?);
+                    seq.push(Cfg::Repeat { id, count, body });
+                }
+                Token::Ident("input_cells") => {
+                    self.pos += 1;
+                    self.finish_block(&mut seq);
+                    self.expect(&Token::Punct("("))?;
+                    self.expect(&Token::Ident("step"))?;
+                    self.expect(&Token::Punct(":"))?;
+                    let step = self.parse_offset()?;
+                    self.expect(&Token::Punct(","))?;
+                    self.expect(&Token::Ident("until"))?;
+                    self.expect(&Token::Punct(":"))?;
+                    let delimiter = match self.next()? {
+                        Token::Char(c) => c,
+                        token => return Err(self.unexpected(token)),
+                    };
+                    self.expect(&Token::Punct(")"))?;
+                    seq.push(Cfg::InputCells { step, delimiter });
+                }
+                _ => self.parse_block_stmt()?,
+            }
+        }
+        self.finish_block(&mut seq);
+        Ok(Seq::from_unflattened(seq).into_cfg())
+    }
+
+    /// Parses a statement in a basic block.
+    fn parse_block_stmt(&mut self) -> Result<(), IrError> {
+        let id = self.block().id;
+        match self.next()? {
+            Token::Ident("output") => {
+                self.open_effect()?;
+                let effect = match self.next()? {
+                    Token::Str(bytes) => Effect::OutputBytes(bytes),
+                    Token::Punct("[") => {
+                        let mut values = Vec::new();
+                        while !self.eat(&Token::Punct("]"))? {
+                            if !values.is_empty() {
+                                self.expect(&Token::Punct(","))?;
+                            }
+                            values.push(self.parse_expr(id)?);
+                        }
+                        Effect::Output(values)
+                    }
+                    _ if self.syntax == Syntax::Bfrs1 => {
+                        self.pos -= 1;
+                        Effect::Output(vec![self.parse_expr(id)?])
+                    }
+                    token => return Err(self.unexpected(token)),
+                };
+                self.close_effect()?;
+                // bfrs1 outputs one value at a time, so consecutive outputs
+                // are merged.
+                let block = self.block.as_mut().unwrap();
+                let effect = match (block.last_effect(self.g), effect) {
+                    (Some(Effect::Output(values1)), Effect::Output(values2))
+                        if self.syntax == Syntax::Bfrs1 =>
+                    {
+                        let values = [values1.as_slice(), &values2].concat();
+                        block.pop_effect(self.g);
+                        Effect::Output(values)
+                    }
+                    (_, effect) => effect,
+                };
+                self.push_effect(effect);
+            }
+            Token::Ident("output_decimal") => {
+                self.open_effect()?;
+                let value = self.parse_expr(id)?;
+                self.close_effect()?;
+                self.push_effect(Effect::OutputDecimal(value));
+            }
+            Token::Ident("guard_shift") => {
+                self.open_effect()?;
+                let offset = self.parse_offset()?;
+                self.close_effect()?;
+                let block = self.block();
+                block.guarded_left = block.guarded_left.min(offset);
+                block.guarded_right = block.guarded_right.max(offset);
+                self.push_effect(Effect::GuardShift(offset));
+            }
+            Token::Ident("let") if self.syntax == Syntax::Bfrs2 => {
+                let name = match self.next()? {
+                    Token::Ident(name) => name,
+                    token => return Err(self.unexpected(token)),
+                };
+                self.parse_binding(name, id)?;
+            }
+            Token::Ident("p") if self.syntax == Syntax::Bfrs2 => match self.next()? {
+                Token::Punct("[") => {
+                    let offset = self.parse_offset()?;
+                    self.expect(&Token::Punct("]"))?;
+                    self.expect(&Token::Punct("="))?;
+                    let value = self.parse_expr(id)?;
+                    self.block().set_cell(offset, value);
+                }
+                Token::Punct(op @ ("+=" | "-=")) => {
+                    let shift = match self.next()? {
+                        Token::Int(n) => n,
+                        token => return Err(self.unexpected(token)),
+                    };
+                    self.block().offset += if op == "+=" { shift } else { -shift };
+                }
+                token => return Err(self.unexpected(token)),
+            },
+            Token::Punct("@") if self.syntax == Syntax::Bfrs1 => {
+                let offset = self.parse_offset()?;
+                self.expect(&Token::Punct("="))?;
+                let value = self.parse_expr(id)?;
+                self.block().set_cell(offset, value);
+            }
+            Token::Ident("shift") if self.syntax == Syntax::Bfrs1 => {
+                let shift = self.parse_offset()?;
+                self.block().offset += shift.0;
+            }
+            // bfrs1 binds inputs without `let`.
+            Token::Ident(name) if self.syntax == Syntax::Bfrs1 => self.parse_binding(name, id)?,
+            token => return Err(self.unexpected(token)),
+        }
+        match self.peek() {
+            None | Some(Token::End | Token::Punct("}")) => Ok(()),
+            Some(token) => Err(self.unexpected(token.clone())),
+        }
+    }
+
+    /// Parses the value bound to a name, which is an input or an expression.
+    fn parse_binding(&mut self, name: &'s str, block: BlockId) -> Result<(), IrError> {
+        self.expect(&Token::Punct("="))?;
+        let value = match self.peek() {
+            Some(Token::Ident(
+                "input" | "input_until" | "input_decimal" | "input_decimal_until",
+            )) => self.parse_input(name)?.byte(),
+            _ => self.parse_expr(block)?,
+        };
+        self.names.insert(name, value);
+        Ok(())
+    }
+
+    /// Parses an input effect, which is bound to the name.
+    fn parse_input(&mut self, name: &str) -> Result<InputNode, IrError> {
+        // bfrs1 numbers inputs within each block, so they receive fresh IDs.
+        let id = name.strip_prefix("in").and_then(|n| n.parse().ok());
+        let input = match id {
+            Some(n) if self.syntax == Syntax::Bfrs2 => self.g.input(InputId(n)),
+            _ => self.g.fresh_input(),
+        };
+        let kind = self.next()?;
+        self.open_effect()?;
+        let effect = match kind {
+            Token::Ident("input") => Effect::Input(input),
+            Token::Ident("input_until") => Effect::InputUntil {
+                input,
+                delimiters: self.parse_str()?,
+            },
+            Token::Ident("input_decimal") => {
+                let digits = match self.next()? {
+                    Token::Int(n @ 0..=255) => n as u8,
+                    token => return Err(self.unexpected(token)),
+                };
+                Effect::InputDecimal {
+                    value: input,
+                    end: DecimalEnd::Digits(digits),
+                }
+            }
+            Token::Ident("input_decimal_until") => Effect::InputDecimal {
+                value: input,
+                end: DecimalEnd::Delimiters(self.parse_str()?),
+            },
+            token => return Err(self.unexpected(token)),
+        };
+        self.close_effect()?;
+        self.push_effect(effect);
+        Ok(input)
+    }
+
+    /// Opens the arguments of an effect, which are parenthesized, except in
+    /// bfrs1.
+    fn open_effect(&mut self) -> Result<(), IrError> {
+        match self.syntax {
+            Syntax::Bfrs2 => self.expect(&Token::Punct("(")),
+            Syntax::Bfrs1 => Ok(()),
+        }
+    }
+
+    /// Closes the arguments of an effect.
+    fn close_effect(&mut self) -> Result<(), IrError> {
+        match self.syntax {
+            Syntax::Bfrs2 => self.expect(&Token::Punct(")")),
+            Syntax::Bfrs1 => Ok(()),
+        }
+    }
+
+    /// Parses a sum or difference of terms. Copies read from the block.
+    fn parse_expr(&mut self, block: BlockId) -> Result<ByteNode, IrError> {
+        let mut lhs = self.parse_term(block)?;
+        loop {
+            if self.eat(&Token::Punct("+"))? {
+                let rhs = self.parse_term(block)?;
+                lhs = Node::Add(lhs, rhs).insert(self.g);
+            } else if self.eat(&Token::Punct("-"))? {
+                let rhs = self.parse_term(block)?;
+                let rhs = self.negate(rhs);
+                lhs = Node::Add(lhs, rhs).insert(self.g);
+            } else {
+                return Ok(lhs);
+            }
+        }
+    }
+
+    /// Parses a product of factors.
+    fn parse_term(&mut self, block: BlockId) -> Result<ByteNode, IrError> {
+        let mut lhs = self.parse_factor(block)?;
+        while self.eat(&Token::Punct("*"))? {
+            let rhs = self.parse_factor(block)?;
+            lhs = Node::Mul(lhs, rhs).insert(self.g);
+        }
+        Ok(lhs)
+    }
+
+    /// Parses a constant, copy, name, call, or parenthesized expression.
+    fn parse_factor(&mut self, block: BlockId) -> Result<ByteNode, IrError> {
+        let value = match self.next()? {
+            Token::Int(n) => self.parse_const(n)?,
+            Token::Char(c) => Node::Const(c).insert(self.g),
+            Token::Punct("-") => {
+                let value = self.parse_factor(block)?;
+                self.negate(value)
+            }
+            Token::Punct("(") => {
+                let value = self.parse_expr(block)?;
+                self.expect(&Token::Punct(")"))?;
+                value
+            }
+            Token::Ident("p") if self.syntax == Syntax::Bfrs2 => {
+                self.expect(&Token::Punct("["))?;
+                let offset = self.parse_offset()?;
+                self.expect(&Token::Punct("]"))?;
+                Node::Copy(offset, block).insert(self.g)
+            }
+            Token::Punct("@") if self.syntax == Syntax::Bfrs1 => {
+                let offset = self.parse_offset()?;
+                Node::Copy(offset, block).insert(self.g)
+            }
+            Token::Ident("choose") => {
+                self.expect(&Token::Punct("("))?;
+                let n = self.parse_expr(block)?;
+                self.expect(&Token::Punct(","))?;
+                let k = match self.next()? {
+                    Token::Int(k @ 0..=255) => k as u8,
+                    token => return Err(self.unexpected(token)),
+                };
+                self.expect(&Token::Punct(")"))?;
+                Node::Choose(n, k).insert(self.g)
+            }
+            Token::Ident("pow") => {
+                self.expect(&Token::Punct("("))?;
+                let base = self.parse_expr(block)?;
+                self.expect(&Token::Punct(","))?;
+                let exp = self.parse_expr(block)?;
+                self.expect(&Token::Punct(")"))?;
+                Node::Pow(base, exp).insert(self.g)
+            }
+            Token::Ident(name) => match self.names.get(name) {
+                Some(&value) => value,
+                None => return Err(self.error(IrErrorKind::UnboundName(name.to_owned()))),
+            },
+            token => return Err(self.unexpected(token)),
+        };
+        Ok(value)
+    }
+
+    /// Converts an integer in `-128..=255` to a constant.
+    fn parse_const(&mut self, n: i64) -> Result<ByteNode, IrError> {
+        match n {
+            0..=255 => Ok(Node::Const(n as u8).insert(self.g)),
+            _ => Err(self.error(IrErrorKind::ConstOutOfRange(n.to_string()))),
+        }
+    }
+
+    /// Parses an optionally negative offset.
+    fn parse_offset(&mut self) -> Result<Offset, IrError> {
+        let negative = self.eat(&Token::Punct("-"))?;
+        match self.next()? {
+            Token::Int(n) => Ok(Offset(if negative { -n } else { n })),
+            token => Err(self.unexpected(token)),
+        }
+    }
+
+    /// Parses a string literal.
+    fn parse_str(&mut self) -> Result<Vec<u8>, IrError> {
+        match self.next()? {
+            Token::Str(bytes) => Ok(bytes),
+            token => Err(self.unexpected(token)),
+        }
+    }
+
+    fn negate(&mut self, value: ByteNode) -> ByteNode {
+        Node::Mul(value, Node::Const(255).insert(self.g)).insert(self.g)
+    }
+
+    /// Gets the block under construction, starting a new one if needed.
+    fn block(&mut self) -> &mut Block {
+        if self.block.is_none() {
+            self.names.clear();
+        }
+        self.block
+            .get_or_insert_with(|| Block::new(self.g.fresh_block_id()))
+    }
+
+    /// Appends an effect to the block under construction.
+    fn push_effect(&mut self, effect: Effect) {
+        self.block();
+        let block = self.block.as_mut().unwrap();
+        block.push_effect(effect, self.g);
+    }
+
+    /// Finishes the block under construction, if any, and pushes it to the
+    /// sequence.
+    fn finish_block(&mut self, seq: &mut Vec<Cfg>) {
+        if let Some(block) = self.block.take() {
+            seq.push(Cfg::Block(block));
+        }
+    }
+
+    fn peek(&self) -> Option<&Token<'s>> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> Result<Token<'s>, IrError> {
+        let (token, _) = self
+            .tokens
+            .get(self.pos)
+            .ok_or_else(|| self.error(IrErrorKind::UnexpectedEof))?;
+        self.pos += 1;
+        Ok(token.clone())
+    }
+
+    /// Consumes the next token, if it is the expected token.
+    fn eat(&mut self, expect: &Token<'_>) -> Result<bool, IrError> {
+        match self.peek() {
+            Some(token) if token == expect => {
+                self.pos += 1;
+                Ok(true)
+            }
+            Some(_) => Ok(false),
+            None => Err(self.error(IrErrorKind::UnexpectedEof)),
+        }
+    }
+
+    fn expect(&mut self, expect: &Token<'_>) -> Result<(), IrError> {
+        match self.next()? {
+            token if &token == expect => Ok(()),
+            token => Err(self.unexpected(token)),
+        }
+    }
+
+    fn unexpected(&self, token: Token<'_>) -> IrError {
+        let token = match token {
+            Token::Ident(name) => name.to_owned(),
+            Token::Int(n) => n.to_string(),
+            Token::Char(c) => format!("{:?}", c as char),
+            Token::Str(bytes) => format!("{:?}", String::from_utf8_lossy(&bytes)),
+            Token::Punct(punct) => punct.to_owned(),
+            Token::End => "end of statement".to_owned(),
+        };
+        self.error(IrErrorKind::UnexpectedToken(token))
+    }
+
+    /// Constructs an error at the line of the most recent token.
+    fn error(&self, kind: IrErrorKind) -> IrError {
+        let line = match self.tokens.get(self.pos.saturating_sub(1)) {
+            Some(&(_, line)) => line,
+            None => 1,
+        };
+        IrError { line, kind }
+    }
+}
+
+/// Splits the source into tokens, each with its 1-based line.
+fn tokenize(src: &str, syntax: Syntax) -> Result<Vec<(Token<'_>, usize)>, IrError> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut rest = src;
+    while let Some(ch) = rest.chars().next() {
+        let error = move |kind| IrError { line, kind };
+        let (token, len) = match ch {
+            '\n' => {
+                tokens.push((Token::End, line));
+                line += 1;
+                rest = &rest[1..];
+                continue;
+            }
+            _ if ch.is_whitespace() => {
+                rest = &rest[ch.len_utf8()..];
+                continue;
+            }
+            '/' if syntax == Syntax::Bfrs2 && rest.starts_with("//") => {
+                rest = &rest[rest.find('\n').unwrap_or(rest.len())..];
+                continue;
+            }
+            '#' if syntax == Syntax::Bfrs1 => {
+                rest = &rest[rest.find('\n').unwrap_or(rest.len())..];
+                continue;
+            }
+            ';' => (Token::End, 1),
+            '!' if rest.starts_with("!=") => (Token::Punct("!="), 2),
+            '+' if rest.starts_with("+=") => (Token::Punct("+="), 2),
+            '-' if rest.starts_with("-=") => (Token::Punct("-="), 2),
+            '@' => (Token::Punct("@"), 1),
+            '[' => (Token::Punct("["), 1),
+            ']' => (Token::Punct("]"), 1),
+            '(' => (Token::Punct("("), 1),
+            ')' => (Token::Punct(")"), 1),
+            '{' => (Token::Punct("{"), 1),
+            '}' => (Token::Punct("}"), 1),
+            '=' => (Token::Punct("="), 1),
+            '+' => (Token::Punct("+"), 1),
+            '-' => (Token::Punct("-"), 1),
+            '*' => (Token::Punct("*"), 1),
+            ',' => (Token::Punct(","), 1),
+            ':' => (Token::Punct(":"), 1),
+            '0'..='9' => {
+                let len = rest
+                    .find(|ch: char| !ch.is_ascii_digit())
+                    .unwrap_or(rest.len());
+                let n = rest[..len]
+                    .parse()
+                    .map_err(|_| error(IrErrorKind::ConstOutOfRange(rest[..len].to_owned())))?;
+                (Token::Int(n), len)
+            }
+            'a'..='z' | 'A'..='Z' | '_' => {
+                let len = rest
+                    .find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_'))
+                    .unwrap_or(rest.len());
+                (Token::Ident(&rest[..len]), len)
+            }
+            '\'' => {
+                let mut chars = rest[1..].char_indices();
+                let c = unescape(&mut chars).map_err(error)?;
+                match chars.next() {
+                    Some((i, '\'')) => (Token::Char(c), i + 2),
+                    _ => return Err(error(IrErrorKind::UnterminatedLiteral)),
+                }
+            }
+            '"' => {
+                let mut bytes = Vec::new();
+                let mut chars = rest[1..].char_indices();
+                let len = loop {
+                    match chars.clone().next() {
+                        Some((i, '"')) => break i + 2,
+                        Some(_) => bytes.push(unescape(&mut chars).map_err(error)?),
+                        None => return Err(error(IrErrorKind::UnterminatedLiteral)),
+                    }
+                };
+                (Token::Str(bytes), len)
+            }
+            _ => return Err(error(IrErrorKind::UnexpectedChar(ch))),
+        };
+        tokens.push((token, line));
+        rest = &rest[len..];
+    }
+    Ok(tokens)
+}
+
+/// Reads a possibly escaped character from a literal.
+fn unescape(chars: &mut impl Iterator<Item = (usize, char)>) -> Result<u8, IrErrorKind> {
+    match chars.next() {
+        Some((_, '\\')) => match chars.next() {
+            Some((_, '0')) => Ok(b'\0'),
+            Some((_, 't')) => Ok(b'\t'),
+            Some((_, 'n')) => Ok(b'\n'),
+            Some((_, 'r')) => Ok(b'\r'),
+            Some((_, ch @ ('\\' | '\'' | '"'))) => Ok(ch as u8),
+            Some((_, 'x')) => {
+                let hi = chars.next().and_then(|(_, ch)| ch.to_digit(16));
+                let lo = chars.next().and_then(|(_, ch)| ch.to_digit(16));
+                match (hi, lo) {
+                    (Some(hi), Some(lo)) => Ok((hi * 16 + lo) as u8),
+                    _ => Err(IrErrorKind::BadEscape),
+                }
+            }
+            Some(_) => Err(IrErrorKind::BadEscape),
+            None => Err(IrErrorKind::UnterminatedLiteral),
+        },
+        Some((_, ch)) if ch.is_ascii() => Ok(ch as u8),
+        Some(_) => Err(IrErrorKind::BadEscape),
+        None => Err(IrErrorKind::UnterminatedLiteral),
+    }
+}
+
+impl Display for IrError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+impl Display for IrErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            IrErrorKind::UnexpectedEof => write!(f, "unexpected end of IR"),
+            IrErrorKind::UnexpectedToken(token) => write!(f, "unexpected token {token:?}"),
+            IrErrorKind::UnexpectedChar(ch) => write!(f, "unexpected {ch:?}"),
+            IrErrorKind::UnboundName(name) => write!(f, "name {name} not bound by let"),
+            IrErrorKind::ConstOutOfRange(c) => write!(f, "constant {c} out of range for byte"),
+            IrErrorKind::BadEscape => write!(f, "invalid escape sequence"),
+            IrErrorKind::UnterminatedLiteral => write!(f, "unterminated literal"),
+            IrErrorKind::UnclosedBrace => write!(f, "unmatched {{"),
+            IrErrorKind::UnopenedBrace => write!(f, "unmatched }}"),
+        }
+    }
+}
+
+impl Error for IrError {}
@@ -0,0 +1,356 @@
+//! Polynomial normal form for byte expressions.
+//!
+//! Byte expressions are canonicalized to a sum of monomials with coefficients
+//! modulo 2^8, so that expressions which are equal as polynomials receive the
+//! same `ByteNode`. Monomials are products of atoms, i.e., nodes other than `Add`,
+//! `Mul`, and `Const`, which are sorted by variable order.
+//!
+//! Note that polynomials over bytes which differ as polynomials can still be
+//! equal as functions (e.g., `128*x*x` and `128*x`), so this is a normal form
+//! for ring identities, not for all semantic equivalences.
+
+use std::cmp::Ordering;
+
+use crate::{
+    egraph::{ByteNode, Graph},
+    node::Node,
+};
+
+/// A polynomial over bytes, represented as a sum of monomials.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Poly {
+    /// Terms sorted by monomial order. Coefficients are non-zero and each
+    /// monomial occurs at most once.
+    terms: Vec<Term>,
+}
+
+/// A monomial with a coefficient.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Term {
+    /// The atoms of the monomial, sorted by variable order and repeated for
+    /// powers. A constant term has no atoms.
+    atoms: Vec<ByteNode>,
+    coeff: u8,
+}
+
+impl Poly {
+    /// The maximum number of terms in a product, before the product is instead
+    /// treated as an opaque atom to bound expansion.
+    const MAX_TERMS: usize = 64;
+
+    /// Constructs a constant polynomial.
+    pub fn constant(c: u8) -> Self {
+        let terms = if c == 0 {
+            Vec::new()
+        } else {
+            vec![Term {
+                atoms: Vec::new(),
+                coeff: c,
+            }]
+        };
+        Poly { terms }
+    }
+
+    /// Constructs a polynomial of a single atom.
+    pub fn atom(id: ByteNode) -> Self {
+        Poly {
+            terms: vec![Term {
+                atoms: vec![id],
+                coeff: 1,
+            }],
+        }
+    }
+
+    /// Converts an expression to a polynomial.
+    pub fn of(id: ByteNode, g: &Graph) -> Self {
+        match g[id] {
+            Node::Const(c) => Poly::constant(c),
+            Node::Add(lhs, rhs) => Poly::of(lhs, g).add(&Poly::of(rhs, g), g),
+            Node::Mul(lhs, rhs) => Poly::of(lhs, g)
+                .mul(&Poly::of(rhs, g), g)
+                .unwrap_or_else(|| Poly::atom(id)),
+            Node::Copy(..) | Node::Input(_) | Node::Choose(..) | Node::Pow(..) => Poly::atom(id),
+            _ => unreachable!("non-byte node in byte expression"),
+        }
+    }
+
+    /// Returns the constant value of this polynomial, if it has no atoms.
+    pub fn as_const(&self) -> Option<u8> {
+        match self.terms.as_slice() {
+            [] => Some(0),
+            [term] if term.atoms.is_empty() => Some(term.coeff),
+            _ => None,
+        }
+    }
+
+    /// Returns the coefficient of the monomial consisting of a single atom.
+    pub fn coeff_of(&self, atom: ByteNode) -> u8 {
+        self.terms
+            .iter()
+            .find(|term| term.atoms == [atom])
+            .map_or(0, |term| term.coeff)
+    }
+
+    /// Removes the monomial consisting of a single atom and returns its
+    /// coefficient.
+    pub fn remove_linear(&mut self, atom: ByteNode) -> u8 {
+        match self.terms.iter().position(|term| term.atoms == [atom]) {
+            Some(i) => self.terms.remove(i).coeff,
+            None => 0,
+        }
+    }
+
+    /// Splits this polynomial by powers of an atom. Returns the coefficient
+    /// polynomial of each power of the atom, indexed by the exponent, without
+    /// trailing zero coefficients.
+    pub fn powers_of(&self, atom: ByteNode, g: &Graph) -> Vec<Poly> {
+        let mut powers = Vec::<Poly>::new();
+        for term in &self.terms {
+            let exp = term.atoms.iter().filter(|&&a| a == atom).count();
+            let atoms = term.atoms.iter().copied().filter(|&a| a != atom).collect();
+            let coeff = Poly {
+                terms: vec![Term {
+                    atoms,
+                    coeff: term.coeff,
+                }],
+            };
+            if powers.len() <= exp {
+                powers.resize(exp + 1, Poly::constant(0));
+            }
+            powers[exp] = powers[exp].add(&coeff, g);
+        }
+        powers
+    }
+
+    /// Returns the atoms in this polynomial, including repetitions.
+    pub fn atoms(&self) -> impl Iterator<Item = ByteNode> + '_ {
+        self.terms
+            .iter()
+            .flat_map(|term| term.atoms.iter().copied())
+    }
+
+    /// Computes the sum of two polynomials.
+    pub fn add(&self, other: &Poly, g: &Graph) -> Poly {
+        let mut terms = Vec::with_capacity(self.terms.len() + other.terms.len());
+        let (mut i, mut j) = (0, 0);
+        while i < self.terms.len() && j < other.terms.len() {
+            let (a, b) = (&self.terms[i], &other.terms[j]);
+            match cmp_monomials(&a.atoms, &b.atoms, g) {
+                Ordering::Less => {
+                    terms.push(a.clone());
+                    i += 1;
+                }
+                Ordering::Greater => {
+                    terms.push(b.clone());
+                    j += 1;
+                }
+                Ordering::Equal => {
+                    let coeff = a.coeff.wrapping_add(b.coeff);
+                    if coeff != 0 {
+                        terms.push(Term {
+                            atoms: a.atoms.clone(),
+                            coeff,
+                        });
+                    }
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        terms.extend_from_slice(&self.terms[i..]);
+        terms.extend_from_slice(&other.terms[j..]);
+        Poly { terms }
+    }
+
+    /// Computes the product of two polynomials, or `None` if it would have
+    /// more than `MAX_TERMS` terms.
+    pub fn mul(&self, other: &Poly, g: &Graph) -> Option<Poly> {
+        if self.terms.len() * other.terms.len() > Self::MAX_TERMS {
+            return None;
+        }
+        let mut product = Poly::constant(0);
+        for a in &self.terms {
+            let mut partial = Vec::with_capacity(other.terms.len());
+            for b in &other.terms {
+                let coeff = a.coeff.wrapping_mul(b.coeff);
+                if coeff != 0 {
+                    partial.push(Term {
+                        atoms: merge_atoms(&a.atoms, &b.atoms, g),
+                        coeff,
+                    });
+                }
+            }
+            partial.sort_by(|a, b| cmp_monomials(&a.atoms, &b.atoms, g));
+            product = product.add(&Poly { terms: partial }, g);
+        }
+        Some(product)
+    }
+
+    /// Simplifies this polynomial using facts about its atoms. Atoms with a
+    /// known value are replaced with it and powers of atoms, which are known to
+    /// be 0 or 1, are replaced with the atom itself, since `b * b = b` for
+    /// booleans. Returns `None` if neither applies.
+    pub fn simplify_with_facts(&self, g: &Graph) -> Option<Poly> {
+        let mut changed = false;
+        let mut simplified = Poly::constant(0);
+        for term in &self.terms {
+            let mut coeff = term.coeff;
+            let mut atoms = Vec::with_capacity(term.atoms.len());
+            for &atom in &term.atoms {
+                let facts = g.facts(atom);
+                if let Some(c) = facts.as_const() {
+                    coeff = coeff.wrapping_mul(c);
+                    changed = true;
+                } else if atoms.last() == Some(&atom) && facts.is_boolean() {
+                    changed = true;
+                } else {
+                    atoms.push(atom);
+                }
+            }
+            if coeff != 0 {
+                let term = Term { atoms, coeff };
+                simplified = simplified.add(&Poly { terms: vec![term] }, g);
+            }
+        }
+        changed.then_some(simplified)
+    }
+
+    /// Inserts this polynomial as a left-associative sum of monomials, with
+    /// the constant term last. Each monomial is a left-associative product of
+    /// its atoms, followed by its coefficient.
+    pub fn insert(&self, g: &mut Graph) -> ByteNode {
+        let mut sum = None;
+        for term in &self.terms {
+            let mut product = None;
+            for &atom in &term.atoms {
+                product = Some(match product {
+                    Some(product) => Node::Mul(product, atom).insert_ideal(g),
+                    None => atom,
+                });
+            }
+            let term = match product {
+                Some(product) if term.coeff == 1 => product,
+                Some(product) => {
+                    let coeff = Node::Const(term.coeff).insert_ideal(g);
+                    Node::Mul(product, coeff).insert_ideal(g)
+                }
+                None => Node::Const(term.coeff).insert_ideal(g),
+            };
+            sum = Some(match sum {
+                Some(sum) => Node::Add(sum, term).insert_ideal(g),
+                None => term,
+            });
+        }
+        sum.unwrap_or_else(|| Node::Const(0).insert_ideal(g))
+    }
+}
+
+/// Orders monomials by comparing atoms pairwise by variable order. A monomial
+/// is ordered before those it is a prefix of, except that the constant
+/// monomial is ordered last.
+fn cmp_monomials(a: &[ByteNode], b: &[ByteNode], g: &Graph) -> Ordering {
+    match (a.is_empty(), b.is_empty()) {
+        (true, true) => return Ordering::Equal,
+        (true, false) => return Ordering::Greater,
+        (false, true) => return Ordering::Less,
+        (false, false) => {}
+    }
+    for (&a, &b) in a.iter().zip(b) {
+        let ord = cmp_atoms(a, b, g);
+        if ord.is_ne() {
+            return ord;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+/// Orders atoms by variable order: copies by block and offset, then inputs by
+/// ID, then other atoms by ID.
+fn cmp_atoms(a: ByteNode, b: ByteNode, g: &Graph) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+    match (&g[a], &g[b]) {
+        (Node::Copy(offset1, block1), Node::Copy(offset2, block2)) => {
+            (block1.0, offset1).cmp(&(block2.0, offset2))
+        }
+        (Node::Input(id1), Node::Input(id2)) => id1.0.cmp(&id2.0),
+        (Node::Copy(..), _) => Ordering::Less,
+        (_, Node::Copy(..)) => Ordering::Greater,
+        (Node::Input(_), _) => Ordering::Less,
+        (_, Node::Input(_)) => Ordering::Greater,
+        _ => a.index().cmp(&b.index()),
+    }
+}
+
+/// Merges two sorted lists of atoms.
+fn merge_atoms(a: &[ByteNode], b: &[ByteNode], g: &Graph) -> Vec<ByteNode> {
+    let mut atoms = Vec::with_capacity(a.len() + b.len());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if cmp_atoms(a[i], b[j], g).is_le() {
+            atoms.push(a[i]);
+            i += 1;
+        } else {
+            atoms.push(b[j]);
+            j += 1;
+        }
+    }
+    atoms.extend_from_slice(&a[i..]);
+    atoms.extend_from_slice(&b[j..]);
+    atoms
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        egraph::Graph,
+        node::{BlockId, Node, Offset},
+    };
+
+    #[test]
+    fn cancel() {
+        let mut g = Graph::new();
+        let x = Node::Copy(Offset(0), BlockId(0)).insert(&mut g);
+        let neg_one = Node::Const(255).insert(&mut g);
+        let neg_x = Node::Mul(x, neg_one).insert(&mut g);
+        let zero = Node::Const(0).insert(&mut g);
+        assert_eq!(Node::Add(x, neg_x).insert(&mut g), zero);
+        assert_eq!(Node::Add(neg_x, x).insert(&mut g), zero);
+    }
+
+    #[test]
+    fn same_id() {
+        let mut g = Graph::new();
+        let x = Node::Copy(Offset(0), BlockId(0)).insert(&mut g);
+        let y = Node::Copy(Offset(1), BlockId(0)).insert(&mut g);
+        let one = Node::Const(1).insert(&mut g);
+        let two = Node::Const(2).insert(&mut g);
+        // (x + 1) * (y + 1)
+        let x_1 = Node::Add(x, one).insert(&mut g);
+        let y_1 = Node::Add(y, one).insert(&mut g);
+        let product = Node::Mul(y_1, x_1).insert(&mut g);
+        // x*y + x + y + 1
+        let xy = Node::Mul(x, y).insert(&mut g);
+        let sum = Node::Add(Node::Add(y, xy).insert(&mut g), x_1).insert(&mut g);
+        assert_eq!(product, sum);
+        // (x + x) * 128 = 0
+        let x_x = Node::Add(x, x).insert(&mut g);
+        assert_eq!(x_x, Node::Mul(x, two).insert(&mut g));
+        let c128 = Node::Const(128).insert(&mut g);
+        let zero = Node::Mul(x_x, c128).insert(&mut g);
+        assert_eq!(g[zero], Node::Const(0));
+    }
+
+    #[test]
+    fn variable_order() {
+        let mut g = Graph::new();
+        let x = Node::Copy(Offset(0), BlockId(0)).insert(&mut g);
+        let y = Node::Copy(Offset(-1), BlockId(0)).insert(&mut g);
+        let three = Node::Const(3).insert(&mut g);
+        let x_3 = Node::Add(x, three).insert(&mut g);
+        let sum = Node::Add(x_3, y).insert(&mut g);
+        let y_x = Node::Add(y, x).insert_ideal(&mut g);
+        assert_eq!(g[sum], Node::Add(y_x, three));
+    }
+}
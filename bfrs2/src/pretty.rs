@@ -1,3 +1,5 @@
+//! Pretty-printing of CFGs and byte expressions.
+
 use std::{
     collections::BTreeSet,
     fmt::{self, Display, Formatter, Write},
@@ -5,23 +7,102 @@ use std::{
 };
 
 use crate::{
-    block::{Block, Effect},
+    block::{Block, DecimalEnd, Effect},
     cfg::Cfg,
-    egraph::{Graph, NodeId, NodeRef},
+    egraph::{ByteNode, Graph, NodeRef, TypedId},
+    extract::Extraction,
     node::{BlockId, Node, Offset},
 };
 
+/// Options for pretty-printing a CFG.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PrettyOptions {
+    /// The syntax to print.
+    pub syntax: Syntax,
+    /// Whether to print `guard_shift` effects.
+    pub guards: bool,
+    /// Whether to annotate blocks with their IDs.
+    pub block_ids: bool,
+    /// Whether to annotate stored and effect values with their node IDs.
+    pub node_ids: bool,
+    /// Whether to colour the output with ANSI escape codes.
+    pub color: bool,
+}
+
+/// The syntax of pretty-printed CFGs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Syntax {
+    /// The C-like syntax with `p[0]` for cells and `let` bindings for copies,
+    /// which is read by `Graph::parse_ir`.
+    Bfrs2,
+    /// The syntax of bfrs1 with `@0` for cells and explicit `shift`, as in the
+    /// `.ir` files of the tests, which is read by `Graph::parse_ir_with`.
+    Bfrs1,
+}
+
+/// A style of highlighted text.
+#[derive(Clone, Copy)]
+enum Style {
+    Keyword,
+    Effect,
+    Literal,
+    Comment,
+}
+
+impl Default for PrettyOptions {
+    fn default() -> Self {
+        PrettyOptions {
+            syntax: Syntax::Bfrs2,
+            guards: true,
+            block_ids: false,
+            node_ids: false,
+            color: false,
+        }
+    }
+}
+
+impl Style {
+    fn ansi(self) -> &'static str {
+        match self {
+            Style::Keyword => "\x1b[1;35m",
+            Style::Effect => "\x1b[36m",
+            Style::Literal => "\x1b[33m",
+            Style::Comment => "\x1b[2m",
+        }
+    }
+}
+
 impl Cfg {
+    /// Pretty-prints this CFG with the default options.
     pub fn pretty(&self, g: &Graph) -> String {
+        self.pretty_with(g, &PrettyOptions::default())
+    }
+
+    /// Pretty-prints this CFG with the options.
+    pub fn pretty_with(&self, g: &Graph, options: &PrettyOptions) -> String {
+        let mut s = String::new();
+        PrettyPrinter::new(&mut s, g, *options)
+            .pretty_cfg(self, 0)
+            .unwrap();
+        s
+    }
+
+    /// Pretty-prints this CFG with each value replaced by the cheapest equal
+    /// term.
+    pub fn pretty_extracted(&self, g: &Graph, ex: &Extraction) -> String {
         let mut s = String::new();
-        PrettyPrinter::new(&mut s, g).pretty_cfg(self, 0).unwrap();
+        let mut pp = PrettyPrinter::new(&mut s, g, PrettyOptions::default());
+        pp.extraction = Some(ex);
+        pp.pretty_cfg(self, 0).unwrap();
         s
     }
 }
 
 impl Display for NodeRef<'_> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        PrettyPrinter::new(f, self.graph()).pretty_node(self.id(), false)
+        let node =
+            ByteNode::new(self.id(), self.graph()).expect("non-byte node in byte expression");
+        PrettyPrinter::new(f, self.graph(), PrettyOptions::default()).pretty_node(node, false)
     }
 }
 
@@ -30,17 +111,21 @@ struct PrettyPrinter<'w, 'g> {
     indent_buf: String,
     copies_scratch: BTreeSet<Offset>,
     g: &'g Graph,
+    extraction: Option<&'g Extraction>,
+    options: PrettyOptions,
 }
 
 impl<'w, 'g> PrettyPrinter<'w, 'g> {
     const INDENT: &'static str = "    ";
 
-    fn new(w: &'w mut (dyn Write + 'w), g: &'g Graph) -> Self {
+    fn new(w: &'w mut (dyn Write + 'w), g: &'g Graph, options: PrettyOptions) -> Self {
         PrettyPrinter {
             w,
             indent_buf: Self::INDENT.repeat(4),
             copies_scratch: BTreeSet::new(),
             g,
+            extraction: None,
+            options,
         }
     }
 
@@ -55,6 +140,11 @@ impl<'w, 'g> PrettyPrinter<'w, 'g> {
                 }
                 let mut seq = seq.iter().peekable();
                 while let Some(cfg) = seq.next() {
+                    // bfrs1 braces every block in a sequence.
+                    if let (Cfg::Block(block), Syntax::Bfrs1) = (cfg, self.options.syntax) {
+                        self.pretty_block(block, indent, true)?;
+                        continue;
+                    }
                     // Print blocks with braces only when there are adjacent
                     // blocks that are not coalesced.
                     if let Cfg::Block(block) = cfg {
@@ -73,24 +163,63 @@ impl<'w, 'g> PrettyPrinter<'w, 'g> {
             }
             Cfg::Loop(cfg) => {
                 self.indent(indent)?;
-                write!(self.w, "while p[0] != 0 {{\n")?;
+                self.keyword("while")?;
+                write!(self.w, " ")?;
+                self.pretty_cell(Offset(0))?;
+                writeln!(self.w, " != 0 {{")?;
                 self.pretty_cfg(cfg, indent + 1)?;
                 self.indent(indent)?;
-                write!(self.w, "}}\n")
+                writeln!(self.w, "}}")
             }
             Cfg::If(cfg_then) => {
                 self.indent(indent)?;
-                write!(self.w, "if p[0] != 0 {{\n")?;
+                self.keyword("if")?;
+                write!(self.w, " ")?;
+                self.pretty_cell(Offset(0))?;
+                writeln!(self.w, " != 0 {{")?;
                 self.pretty_cfg(cfg_then, indent + 1)?;
                 self.indent(indent)?;
-                write!(self.w, "}}\n")
+                writeln!(self.w, "}}")
+            }
+            &Cfg::Repeat {
+                id,
+                count,
+                ref body,
+            } => {
+                self.indent(indent)?;
+                self.keyword("repeat")?;
+                write!(self.w, " ")?;
+                self.pretty_node(count, false)?;
+                write!(self.w, " ")?;
+                self.keyword("times")?;
+                write!(self.w, " {{")?;
+                if self.options.block_ids {
+                    write!(self.w, " ")?;
+                    self.comment(format_args!("b{}", id.0))?;
+                }
+                writeln!(self.w)?;
+                self.pretty_cfg(body, indent + 1)?;
+                self.indent(indent)?;
+                writeln!(self.w, "}}")
+            }
+            &Cfg::InputCells { step, delimiter } => {
+                self.indent(indent)?;
+                self.styled(Style::Effect, format_args!("input_cells"))?;
+                write!(self.w, "(step: {}, until: ", step.0)?;
+                self.pretty_char(delimiter)?;
+                writeln!(self.w, ")")
             }
         }
     }
 
     fn pretty_block(&mut self, block: &Block, mut indent: usize, braced: bool) -> fmt::Result {
-        fn visit_copies(node: NodeRef<'_>, current_block: BlockId, copies: &mut BTreeSet<Offset>) {
-            match *node.node() {
+        fn visit_copies(
+            pp: &PrettyPrinter<'_, '_>,
+            node: ByteNode,
+            current_block: BlockId,
+            copies: &mut BTreeSet<Offset>,
+        ) {
+            match *pp.node(node) {
                 Node::Copy(offset, block_id) => {
                     if block_id != current_block {
                         panic!("copy not from current block");
@@ -98,94 +227,183 @@ impl<'w, 'g> PrettyPrinter<'w, 'g> {
                     copies.insert(offset);
                 }
                 Node::Const(_) | Node::Input(_) => {}
-                Node::Add(lhs, rhs) | Node::Mul(lhs, rhs) => {
-                    visit_copies(node.get(lhs), current_block, copies);
-                    visit_copies(node.get(rhs), current_block, copies);
+                Node::Add(lhs, rhs) | Node::Mul(lhs, rhs) | Node::Pow(lhs, rhs) => {
+                    visit_copies(pp, lhs, current_block, copies);
+                    visit_copies(pp, rhs, current_block, copies);
                 }
+                Node::Choose(n, _) => visit_copies(pp, n, current_block, copies),
+                _ => unreachable!("non-byte node in byte expression"),
             }
         }
 
+        let syntax = self.options.syntax;
         if braced {
             self.indent(indent)?;
-            write!(self.w, "{{\n")?;
+            write!(self.w, "{{")?;
+            if self.options.block_ids {
+                write!(self.w, " ")?;
+                self.comment(format_args!("b{}", block.id.0))?;
+            }
+            writeln!(self.w)?;
             indent += 1;
-        }
-        for effect in &block.effects {
+        } else if self.options.block_ids {
             self.indent(indent)?;
-            self.pretty_effect(effect)?;
-            write!(self.w, "\n")?;
+            self.comment(format_args!("b{}", block.id.0))?;
+            writeln!(self.w)?;
         }
-        let mut copies = mem::take(&mut self.copies_scratch);
-        copies.clear();
-        for (_, node) in block.iter_memory() {
-            visit_copies(self.g.get(node), block.id, &mut copies);
+        for effect in &block.effects(self.g) {
+            match (effect, syntax) {
+                (Effect::GuardShift(_), _) if !self.options.guards => {}
+                // bfrs1 outputs one value at a time.
+                (Effect::Output(values), Syntax::Bfrs1) => {
+                    for &value in values {
+                        self.indent(indent)?;
+                        self.open_effect("output")?;
+                        self.pretty_node(value, false)?;
+                        self.pretty_node_ids(&[value])?;
+                        writeln!(self.w)?;
+                    }
+                }
+                _ => {
+                    self.indent(indent)?;
+                    self.pretty_effect(effect)?;
+                    self.pretty_node_ids(effect.nodes())?;
+                    writeln!(self.w)?;
+                }
+            }
         }
-        for &copy in &copies {
-            self.indent(indent)?;
-            write!(self.w, "let ")?;
-            self.pretty_copy(copy)?;
-            write!(self.w, " = p[{}]\n", copy.0)?;
+        // bfrs1 reads cells on entry to the block directly, instead of binding
+        // them.
+        let use_copies = syntax == Syntax::Bfrs2;
+        if use_copies {
+            let mut copies = mem::take(&mut self.copies_scratch);
+            copies.clear();
+            // Identity stores are not printed, so their copies are not bound.
+            for (offset, node) in block.iter_memory() {
+                if *self.node(node) != Node::Copy(offset, block.id) {
+                    visit_copies(self, node, block.id, &mut copies);
+                }
+            }
+            for &copy in &copies {
+                self.indent(indent)?;
+                self.keyword("let")?;
+                write!(self.w, " ")?;
+                self.pretty_copy(copy)?;
+                writeln!(self.w, " = p[{}]", copy.0)?;
+            }
+            self.copies_scratch = copies;
         }
-        self.copies_scratch = copies;
         for (offset, node) in block.iter_memory() {
-            if self.g[node] != Node::Copy(offset, block.id) {
+            if *self.node(node) != Node::Copy(offset, block.id) {
                 self.indent(indent)?;
-                write!(self.w, "p[{}] = ", offset.0)?;
-                self.pretty_node(node, true)?;
+                self.pretty_cell(offset)?;
+                write!(self.w, " = ")?;
+                self.pretty_node(node, use_copies)?;
+                self.pretty_node_ids(&[node])?;
                 writeln!(self.w)?;
             }
         }
         if block.offset != Offset(0) {
             self.indent(indent)?;
-            if block.offset.0 < 0 {
-                write!(self.w, "p -= {}\n", block.offset.0.unsigned_abs())?;
-            } else {
-                write!(self.w, "p += {}\n", block.offset.0)?;
+            match syntax {
+                Syntax::Bfrs2 if block.offset.0 < 0 => {
+                    writeln!(self.w, "p -= {}", block.offset.0.unsigned_abs())?;
+                }
+                Syntax::Bfrs2 => writeln!(self.w, "p += {}", block.offset.0)?,
+                Syntax::Bfrs1 => {
+                    self.keyword("shift")?;
+                    writeln!(self.w, " {}", block.offset.0)?;
+                }
             }
         }
         if braced {
             self.indent(indent - 1)?;
-            write!(self.w, "}}\n")?;
+            writeln!(self.w, "}}")?;
         }
         Ok(())
     }
 
-    fn pretty_node(&mut self, node: NodeId, use_copies: bool) -> fmt::Result {
-        let node = self.g.get(node);
-        match *node.node() {
+    /// Gets a node, or the cheapest equivalent node when printing extracted
+    /// terms.
+    fn node(&self, id: ByteNode) -> &'g Node {
+        match self.extraction {
+            Some(ex) => &self.g[ex.best(id)],
+            None => &self.g[id],
+        }
+    }
+
+    fn pretty_node(&mut self, node: ByteNode, use_copies: bool) -> fmt::Result {
+        match *self.node(node) {
             Node::Copy(offset, _) => {
                 if use_copies {
                     self.pretty_copy(offset)
                 } else {
-                    write!(self.w, "p[{}]", offset.0)
+                    self.pretty_cell(offset)
                 }
             }
-            Node::Const(c) => write!(self.w, "{}", c as i8),
+            Node::Const(c) => self.styled(Style::Literal, format_args!("{}", c as i8)),
             Node::Input(id) => write!(self.w, "in{}", id.0),
             Node::Add(lhs, rhs) => {
                 self.pretty_node(lhs, use_copies)?;
-                let rhs_node = &self.g[rhs];
-                if let Node::Const(rhs) = *rhs_node {
-                    if (rhs as i8) < 0 {
-                        return write!(self.w, " - {}", (rhs as i8).unsigned_abs());
+                let rhs_node = self.node(rhs);
+                match *rhs_node {
+                    Node::Const(rhs) if (rhs as i8) < 0 => {
+                        write!(self.w, " - ")?;
+                        let rhs = (rhs as i8).unsigned_abs();
+                        return self.styled(Style::Literal, format_args!("{rhs}"));
+                    }
+                    Node::Mul(a, b) => {
+                        // Print a negative coefficient as subtraction.
+                        if let Node::Const(c) = *self.node(b) {
+                            if (c as i8) < 0 {
+                                write!(self.w, " - ")?;
+                                self.group_node(
+                                    a,
+                                    matches!(self.node(a), Node::Add(..)),
+                                    use_copies,
+                                )?;
+                                if c != 255 {
+                                    write!(self.w, " * ")?;
+                                    let c = (c as i8).unsigned_abs();
+                                    self.styled(Style::Literal, format_args!("{c}"))?;
+                                }
+                                return Ok(());
+                            }
+                        }
                     }
+                    _ => {}
                 }
                 write!(self.w, " + ")?;
                 self.group_node(rhs, matches!(rhs_node, Node::Add(..)), use_copies)
             }
             Node::Mul(lhs, rhs) => {
-                self.group_node(lhs, matches!(&self.g[lhs], Node::Add(..)), use_copies)?;
+                self.group_node(lhs, matches!(self.node(lhs), Node::Add(..)), use_copies)?;
                 write!(self.w, " * ")?;
                 self.group_node(
                     rhs,
-                    matches!(&self.g[rhs], Node::Add(..) | Node::Mul(..)),
+                    matches!(self.node(rhs), Node::Add(..) | Node::Mul(..)),
                     use_copies,
                 )
             }
+            Node::Choose(n, k) => {
+                write!(self.w, "choose(")?;
+                self.pretty_node(n, use_copies)?;
+                write!(self.w, ", ")?;
+                self.styled(Style::Literal, format_args!("{k}"))?;
+                write!(self.w, ")")
+            }
+            Node::Pow(base, exp) => {
+                write!(self.w, "pow(")?;
+                self.pretty_node(base, use_copies)?;
+                write!(self.w, ", ")?;
+                self.pretty_node(exp, use_copies)?;
+                write!(self.w, ")")
+            }
+            _ => unreachable!("non-byte node in byte expression"),
         }
     }
 
-    fn group_node(&mut self, node: NodeId, grouped: bool, use_copies: bool) -> fmt::Result {
+    fn group_node(&mut self, node: ByteNode, grouped: bool, use_copies: bool) -> fmt::Result {
         if grouped {
             write!(self.w, "(")?;
         }
@@ -207,44 +425,189 @@ impl<'w, 'g> PrettyPrinter<'w, 'g> {
     fn pretty_effect(&mut self, effect: &Effect) -> fmt::Result {
         match effect {
             Effect::Output(values) => {
-                write!(self.w, "output(")?;
+                self.open_effect("output")?;
                 self.pretty_array(values)?;
-                write!(self.w, ")")
+                self.close_effect()
+            }
+            Effect::OutputBytes(bytes) => {
+                self.open_effect("output")?;
+                self.pretty_string(bytes)?;
+                self.close_effect()
+            }
+            &Effect::OutputDecimal(value) => {
+                self.open_effect("output_decimal")?;
+                self.pretty_node(value, false)?;
+                self.close_effect()
+            }
+            &Effect::Input(input) => {
+                self.pretty_binding(input.byte())?;
+                self.styled(Style::Effect, format_args!("input"))?;
+                match self.options.syntax {
+                    Syntax::Bfrs2 => write!(self.w, "()"),
+                    Syntax::Bfrs1 => Ok(()),
+                }
+            }
+            Effect::InputUntil { input, delimiters } => {
+                self.pretty_binding(input.byte())?;
+                self.open_effect("input_until")?;
+                self.pretty_string(delimiters)?;
+                self.close_effect()
+            }
+            Effect::InputDecimal { value, end } => {
+                self.pretty_binding(value.byte())?;
+                match end {
+                    &DecimalEnd::Digits(digits) => {
+                        self.open_effect("input_decimal")?;
+                        self.styled(Style::Literal, format_args!("{digits}"))?;
+                    }
+                    DecimalEnd::Delimiters(delimiters) => {
+                        self.open_effect("input_decimal_until")?;
+                        self.pretty_string(delimiters)?;
+                    }
+                }
+                self.close_effect()
+            }
+            &Effect::GuardShift(offset) => {
+                self.open_effect("guard_shift")?;
+                write!(self.w, "{}", offset.0)?;
+                self.close_effect()
             }
-            &Effect::Input(id) => write!(self.w, "let {} = input()", self.g.get(id)),
-            &Effect::GuardShift(offset) => write!(self.w, "guard_shift({})", offset.0),
         }
     }
 
-    fn pretty_array(&mut self, values: &[NodeId]) -> fmt::Result {
-        if values.iter().all(|&v| matches!(self.g[v], Node::Const(_))) {
-            write!(self.w, "\"")?;
-            for &v in values {
-                let Node::Const(b) = self.g[v] else {
-                    unreachable!();
-                };
-                self.escape_char(b)?;
-            }
-            write!(self.w, "\"")
+    /// Writes the name of an effect and opens its arguments.
+    fn open_effect(&mut self, name: &str) -> fmt::Result {
+        self.styled(Style::Effect, format_args!("{name}"))?;
+        match self.options.syntax {
+            Syntax::Bfrs2 => write!(self.w, "("),
+            Syntax::Bfrs1 => write!(self.w, " "),
+        }
+    }
+
+    /// Closes the arguments of an effect.
+    fn close_effect(&mut self) -> fmt::Result {
+        match self.options.syntax {
+            Syntax::Bfrs2 => write!(self.w, ")"),
+            Syntax::Bfrs1 => Ok(()),
+        }
+    }
+
+    /// Writes the binding of an input to its name.
+    fn pretty_binding(&mut self, input: ByteNode) -> fmt::Result {
+        if self.options.syntax == Syntax::Bfrs2 {
+            self.keyword("let")?;
+            write!(self.w, " ")?;
+        }
+        self.pretty_node(input, false)?;
+        write!(self.w, " = ")
+    }
+
+    fn pretty_array(&mut self, values: &[ByteNode]) -> fmt::Result {
+        if values
+            .iter()
+            .all(|&v| matches!(self.node(v), Node::Const(_)))
+        {
+            let bytes = values
+                .iter()
+                .map(|&v| match *self.node(v) {
+                    Node::Const(b) => b,
+                    _ => unreachable!(),
+                })
+                .collect::<Vec<_>>();
+            self.pretty_string(&bytes)
         } else {
             write!(self.w, "[")?;
             for (i, &v) in values.iter().enumerate() {
                 if i != 0 {
                     write!(self.w, ", ")?;
                 }
-                let v = self.g.get(v);
-                if let Node::Const(ch) = *v {
-                    write!(self.w, "'")?;
-                    self.escape_char(ch)?;
-                    write!(self.w, "'")?;
+                if let Node::Const(ch) = *self.node(v) {
+                    self.pretty_char(ch)?;
                 } else {
-                    write!(self.w, "{v}")?;
+                    self.pretty_node(v, false)?;
                 }
             }
             write!(self.w, "]")
         }
     }
 
+    fn pretty_string(&mut self, bytes: &[u8]) -> fmt::Result {
+        self.begin_style(Style::Literal)?;
+        write!(self.w, "\"")?;
+        for &b in bytes {
+            self.escape_char(b)?;
+        }
+        write!(self.w, "\"")?;
+        self.end_style()
+    }
+
+    fn pretty_char(&mut self, ch: u8) -> fmt::Result {
+        self.begin_style(Style::Literal)?;
+        write!(self.w, "'")?;
+        self.escape_char(ch)?;
+        write!(self.w, "'")?;
+        self.end_style()
+    }
+
+    /// Writes a cell relative to the cell pointer.
+    fn pretty_cell(&mut self, offset: Offset) -> fmt::Result {
+        match self.options.syntax {
+            Syntax::Bfrs2 => write!(self.w, "p[{}]", offset.0),
+            Syntax::Bfrs1 => write!(self.w, "@{}", offset.0),
+        }
+    }
+
+    /// Annotates the end of a line with the IDs of the nodes, when enabled.
+    fn pretty_node_ids(&mut self, nodes: &[ByteNode]) -> fmt::Result {
+        if !self.options.node_ids || nodes.is_empty() {
+            return Ok(());
+        }
+        let mut ids = String::new();
+        for (i, node) in nodes.iter().enumerate() {
+            if nodes[..i].contains(node) {
+                continue;
+            } else if i != 0 {
+                ids.push_str(", ");
+            }
+            write!(ids, "n{}", node.index())?;
+        }
+        write!(self.w, " ")?;
+        self.comment(format_args!("{ids}"))
+    }
+
+    fn keyword(&mut self, keyword: &str) -> fmt::Result {
+        self.styled(Style::Keyword, format_args!("{keyword}"))
+    }
+
+    /// Writes a comment, which is ignored by the parser of the syntax.
+    fn comment(&mut self, args: fmt::Arguments<'_>) -> fmt::Result {
+        let marker = match self.options.syntax {
+            Syntax::Bfrs2 => "//",
+            Syntax::Bfrs1 => "#",
+        };
+        self.styled(Style::Comment, format_args!("{marker} {args}"))
+    }
+
+    fn styled(&mut self, style: Style, args: fmt::Arguments<'_>) -> fmt::Result {
+        self.begin_style(style)?;
+        self.w.write_fmt(args)?;
+        self.end_style()
+    }
+
+    fn begin_style(&mut self, style: Style) -> fmt::Result {
+        if self.options.color {
+            self.w.write_str(style.ansi())?;
+        }
+        Ok(())
+    }
+
+    fn end_style(&mut self) -> fmt::Result {
+        if self.options.color {
+            self.w.write_str("\x1b[0m")?;
+        }
+        Ok(())
+    }
+
     fn escape_char(&mut self, b: u8) -> fmt::Result {
         match b {
             b'\0' => write!(self.w, "\\0"),
@@ -252,6 +615,7 @@ impl<'w, 'g> PrettyPrinter<'w, 'g> {
             b'\n' => write!(self.w, "\\n"),
             b'\r' => write!(self.w, "\\r"),
             b'\\' => write!(self.w, "\\\\"),
+            b'"' => write!(self.w, "\\\""),
             _ if b.is_ascii() && !b.is_ascii_control() => {
                 write!(self.w, "{}", b as char)
             }
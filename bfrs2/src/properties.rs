@@ -1,19 +1,26 @@
 use crate::{
     block::{Block, Effect},
-    egraph::NodeRef,
+    egraph::{ByteNode, Graph, NodeRef, TypedId},
     node::{BlockId, Node, Offset},
+    poly::Poly,
 };
 
 impl NodeRef<'_> {
     /// Returns whether this value is loop-invariant or an add-assign with a
     /// loop-invariant value.
     pub fn is_add_assign(&self, offset: Offset, block: &Block) -> bool {
-        if let Node::Add(lhs, rhs) = *self.node() {
-            *self.get(lhs) == Node::Copy(offset, block.id)
-                && !self.get(rhs).reads_from(block, block.id)
-        } else {
-            !self.reads_from(block, block.id)
+        let g = self.graph();
+        let value = ByteNode::new(self.id(), g).expect("non-byte node in byte expression");
+        let mut addend = Poly::of(value, g);
+        if let Some(copy) = g.find(&Node::Copy(offset, block.id)) {
+            if !matches!(addend.remove_linear(copy), 0 | 1) {
+                return false;
+            }
         }
+        let reads = addend
+            .atoms()
+            .any(|atom| self.get(atom).reads_from(block, block.id));
+        !reads
     }
 
     /// Returns whether this value reads from the block.
@@ -24,10 +31,12 @@ impl NodeRef<'_> {
             }
             Node::Const(_) => false,
             Node::Input(_) => true,
-            Node::Add(lhs, rhs) | Node::Mul(lhs, rhs) => {
+            Node::Add(lhs, rhs) | Node::Mul(lhs, rhs) | Node::Pow(lhs, rhs) => {
                 self.get(lhs).reads_from(block, copy_from)
                     || self.get(rhs).reads_from(block, copy_from)
             }
+            Node::Choose(n, _) => self.get(n).reads_from(block, copy_from),
+            _ => unreachable!("non-byte node in byte expression"),
         }
     }
 }
@@ -37,9 +46,9 @@ impl Block {
     /// guards. Returns `None` when the block has I/O, `Some(false)` when the
     /// block has no I/O and no guards, and `Some(true)` when the block has no
     /// I/O and has guards.
-    pub fn is_pure(&self) -> Option<bool> {
+    pub fn is_pure(&self, g: &Graph) -> Option<bool> {
         let mut has_guards = false;
-        for effect in &self.effects {
+        for effect in self.effects(g) {
             match effect {
                 Effect::GuardShift(_) => has_guards = true,
                 _ => return None,
@@ -0,0 +1,555 @@
+//! Equality saturation with declarative rewrite rules.
+//!
+//! Rules are written as S-expression patterns over byte nodes, for example,
+//! `(* ?x 2) => (+ ?x ?x)`. Patterns are matched against equality classes, so a
+//! rule applies when any equal node has the required shape. Each match inserts
+//! the right-hand side and unions it with the matched node. Afterwards, the
+//! e-graph is rebuilt to restore congruence, i.e., nodes with equal operands
+//! are themselves made equal. Versions of a node, which passes recorded in its
+//! e-class, are not equal to it and are not matched.
+
+use std::{
+    collections::HashSet,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    mem,
+};
+
+use crate::{
+    egraph::{self, ByteNode, Graph, Pass, TypedId},
+    node::Node,
+};
+
+/// A pattern over byte nodes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    /// A pattern variable, which matches any equality class. All occurrences
+    /// of the same variable must match the same equality class.
+    Var(Var),
+    /// A constant byte.
+    Const(u8),
+    /// Addition of two bytes.
+    Add(Box<Pattern>, Box<Pattern>),
+    /// Multiplication of two bytes.
+    Mul(Box<Pattern>, Box<Pattern>),
+}
+
+/// A pattern variable, identified by its index in the rule which declares it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Var(u32);
+
+/// A rewrite rule, which replaces nodes matching the left-hand side with the
+/// right-hand side.
+pub struct Rewrite {
+    name: String,
+    lhs: Pattern,
+    rhs: Applier,
+    vars: Vec<String>,
+}
+
+/// The right-hand side of a rewrite.
+enum Applier {
+    /// Instantiates a pattern with the matched variables.
+    Pattern(Pattern),
+    /// Computes the replacement node, or `None` to not apply the rule.
+    Fn(fn(&Bindings<'_>, &mut Graph) -> Option<ByteNode>),
+}
+
+/// The equality classes bound to variables in a match of a rewrite.
+pub struct Bindings<'a> {
+    vars: &'a [String],
+    subst: &'a [Option<ByteNode>],
+}
+
+/// Limits on equality saturation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// The maximum number of iterations of matching and rebuilding.
+    pub iters: usize,
+    /// The maximum number of nodes in the e-graph, after which no more
+    /// iterations are started.
+    pub nodes: usize,
+}
+
+/// The reason equality saturation stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// No rule produced a new node or union.
+    Saturated,
+    /// The iteration limit was reached.
+    IterLimit,
+    /// The node limit was reached.
+    NodeLimit,
+}
+
+/// An error from parsing a pattern.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PatternError {
+    /// The pattern ended before a complete term.
+    UnexpectedEof,
+    /// An unexpected token.
+    UnexpectedToken(String),
+    /// An unknown operator after `(`.
+    UnknownOperator(String),
+    /// A constant, which does not fit in a byte.
+    ConstOutOfRange(String),
+    /// A variable used in the right-hand side, which is not bound by the
+    /// left-hand side.
+    UnboundVar(String),
+    /// A rule, which has a variable at the root of its left-hand side.
+    VarRoot,
+}
+
+impl Rewrite {
+    /// Constructs a rewrite from S-expression patterns. Variables are written
+    /// `?name`, constants as bytes or negative bytes, and operators as `(+ a
+    /// b)` and `(* a b)`.
+    pub fn new(name: &str, lhs: &str, rhs: &str) -> Result<Self, PatternError> {
+        let mut vars = Vec::new();
+        let lhs = Pattern::parse(lhs, &mut vars, true)?;
+        let rhs = Pattern::parse(rhs, &mut vars, false)?;
+        Rewrite::build(name, lhs, Applier::Pattern(rhs), vars)
+    }
+
+    /// Constructs a rewrite, which computes its replacement with a function.
+    /// This is useful for conditional rules and constant folding.
+    pub fn new_fn(
+        name: &str,
+        lhs: &str,
+        rhs: fn(&Bindings<'_>, &mut Graph) -> Option<ByteNode>,
+    ) -> Result<Self, PatternError> {
+        let mut vars = Vec::new();
+        let lhs = Pattern::parse(lhs, &mut vars, true)?;
+        Rewrite::build(name, lhs, Applier::Fn(rhs), vars)
+    }
+
+    fn build(
+        name: &str,
+        lhs: Pattern,
+        rhs: Applier,
+        vars: Vec<String>,
+    ) -> Result<Self, PatternError> {
+        if let Pattern::Var(_) = lhs {
+            return Err(PatternError::VarRoot);
+        }
+        Ok(Rewrite {
+            name: name.to_owned(),
+            lhs,
+            rhs,
+            vars,
+        })
+    }
+
+    /// The name of this rule.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Finds all matches of this rule, as pairs of the matched node and the
+    /// substitution of its variables.
+    fn search(&self, g: &Graph) -> Vec<(ByteNode, Vec<Option<ByteNode>>)> {
+        let mut matches = Vec::new();
+        let mut substs = Vec::new();
+        for id in g.ids() {
+            let Some(id) = ByteNode::new(id, g) else {
+                continue;
+            };
+            substs.clear();
+            substs.push(vec![None; self.vars.len()]);
+            self.lhs.match_node(id, g, &mut substs);
+            matches.extend(substs.drain(..).map(|subst| (id, subst)));
+        }
+        matches
+    }
+
+    /// Applies this rule to a match and returns whether the e-graph changed.
+    fn apply(&self, id: ByteNode, subst: &[Option<ByteNode>], g: &mut Graph) -> bool {
+        let len = g.len();
+        let new = match &self.rhs {
+            Applier::Pattern(rhs) => rhs.instantiate(subst, g),
+            Applier::Fn(f) => {
+                let bindings = Bindings {
+                    vars: &self.vars,
+                    subst,
+                };
+                match f(&bindings, g) {
+                    Some(new) => new,
+                    None => return false,
+                }
+            }
+        };
+        g.union(id, new) || g.len() != len
+    }
+}
+
+impl Pattern {
+    /// Parses a pattern from an S-expression. Variables are added to `vars`
+    /// when `bind` is set, and otherwise must already be declared.
+    fn parse(src: &str, vars: &mut Vec<String>, bind: bool) -> Result<Self, PatternError> {
+        let mut tokens = Tokens { src };
+        let pattern = Pattern::parse_term(&mut tokens, vars, bind)?;
+        match tokens.next() {
+            None => Ok(pattern),
+            Some(token) => Err(PatternError::UnexpectedToken(token.to_owned())),
+        }
+    }
+
+    fn parse_term(
+        tokens: &mut Tokens<'_>,
+        vars: &mut Vec<String>,
+        bind: bool,
+    ) -> Result<Self, PatternError> {
+        let token = tokens.next().ok_or(PatternError::UnexpectedEof)?;
+        if token == "(" {
+            let op = tokens.next().ok_or(PatternError::UnexpectedEof)?;
+            let make = match op {
+                "+" => Pattern::Add,
+                "*" => Pattern::Mul,
+                _ => return Err(PatternError::UnknownOperator(op.to_owned())),
+            };
+            let lhs = Pattern::parse_term(tokens, vars, bind)?;
+            let rhs = Pattern::parse_term(tokens, vars, bind)?;
+            match tokens.next() {
+                Some(")") => Ok(make(Box::new(lhs), Box::new(rhs))),
+                Some(token) => Err(PatternError::UnexpectedToken(token.to_owned())),
+                None => Err(PatternError::UnexpectedEof),
+            }
+        } else if let Some(name) = token.strip_prefix('?') {
+            let index = match vars.iter().position(|var| var == name) {
+                Some(index) => index,
+                None if bind => {
+                    vars.push(name.to_owned());
+                    vars.len() - 1
+                }
+                None => return Err(PatternError::UnboundVar(name.to_owned())),
+            };
+            Ok(Pattern::Var(Var(index as u32)))
+        } else if let Ok(c) = token.parse::<i16>() {
+            match c {
+                0..=255 => Ok(Pattern::Const(c as u8)),
+                -128..=-1 => Ok(Pattern::Const(c as i8 as u8)),
+                _ => Err(PatternError::ConstOutOfRange(token.to_owned())),
+            }
+        } else {
+            Err(PatternError::UnexpectedToken(token.to_owned()))
+        }
+    }
+
+    /// Matches this pattern against the structure of a single node and
+    /// extends each substitution in `substs` with the variables bound by it.
+    /// Substitutions which do not match are removed.
+    fn match_node(&self, id: ByteNode, g: &Graph, substs: &mut Vec<Vec<Option<ByteNode>>>) {
+        match (self, &g[id]) {
+            (Pattern::Var(var), _) => {
+                let leader = g.leader(id);
+                substs.retain_mut(|subst| match subst[var.index()] {
+                    Some(bound) => bound == leader,
+                    None => {
+                        subst[var.index()] = Some(leader);
+                        true
+                    }
+                });
+            }
+            (&Pattern::Const(c1), &Node::Const(c2)) if c1 == c2 => {}
+            (Pattern::Add(p1, p2), &Node::Add(lhs, rhs))
+            | (Pattern::Mul(p1, p2), &Node::Mul(lhs, rhs)) => {
+                p1.match_eclass(lhs, g, substs);
+                p2.match_eclass(rhs, g, substs);
+            }
+            _ => substs.clear(),
+        }
+    }
+
+    /// Matches this pattern against any node, which is proven equal to `id`.
+    fn match_eclass(&self, id: ByteNode, g: &Graph, substs: &mut Vec<Vec<Option<ByteNode>>>) {
+        let nodes = g.equal_nodes(id);
+        if let (Pattern::Var(_), _) | (_, [_]) = (self, nodes) {
+            return self.match_node(id, g, substs);
+        }
+        let inputs = mem::take(substs);
+        let mut scratch = Vec::new();
+        for &node in nodes {
+            scratch.clone_from(&inputs);
+            self.match_node(egraph::wrap(node), g, &mut scratch);
+            substs.append(&mut scratch);
+        }
+        let mut seen = HashSet::new();
+        substs.retain(|subst| seen.insert(subst.clone()));
+    }
+
+    /// Inserts this pattern with its variables substituted.
+    fn instantiate(&self, subst: &[Option<ByteNode>], g: &mut Graph) -> ByteNode {
+        match self {
+            Pattern::Var(var) => subst[var.index()].expect("unbound variable"),
+            &Pattern::Const(c) => Node::Const(c).insert_ideal(g),
+            Pattern::Add(lhs, rhs) => {
+                let lhs = lhs.instantiate(subst, g);
+                let rhs = rhs.instantiate(subst, g);
+                Node::Add(lhs, rhs).insert_ideal(g)
+            }
+            Pattern::Mul(lhs, rhs) => {
+                let lhs = lhs.instantiate(subst, g);
+                let rhs = rhs.instantiate(subst, g);
+                Node::Mul(lhs, rhs).insert_ideal(g)
+            }
+        }
+    }
+}
+
+impl Var {
+    #[inline]
+    fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl Bindings<'_> {
+    /// Gets the leader of the equality class bound to the named variable.
+    /// Panics if the variable is not declared by the rule.
+    pub fn get(&self, name: &str) -> ByteNode {
+        let index = self
+            .vars
+            .iter()
+            .position(|var| var == name)
+            .expect("undeclared variable");
+        self.subst[index].expect("unbound variable")
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            iters: 30,
+            nodes: 10_000,
+        }
+    }
+}
+
+impl Graph {
+    /// Applies the rules until no rule changes the e-graph or a limit is
+    /// reached. All matches in an iteration are found before any are applied,
+    /// so the result does not depend on the order of the rules.
+    pub fn saturate(&mut self, rules: &[Rewrite], limits: Limits) -> StopReason {
+        let pass = self.pass();
+        self.set_pass(Pass::EqualitySaturation);
+        let reason = self.saturate_(rules, limits);
+        self.set_pass(pass);
+        reason
+    }
+
+    fn saturate_(&mut self, rules: &[Rewrite], limits: Limits) -> StopReason {
+        for _ in 0..limits.iters {
+            if self.len() >= limits.nodes {
+                return StopReason::NodeLimit;
+            }
+            let matches = rules
+                .iter()
+                .map(|rule| rule.search(self))
+                .collect::<Vec<_>>();
+            let mut changed = false;
+            for (rule, matches) in rules.iter().zip(matches) {
+                for (id, subst) in matches {
+                    changed |= rule.apply(id, &subst, self);
+                }
+            }
+            changed |= self.rebuild();
+            if !changed {
+                return StopReason::Saturated;
+            }
+        }
+        StopReason::IterLimit
+    }
+
+    /// Restores congruence after unions: nodes whose operands are equal are
+    /// unioned into the same equality class. Returns whether the e-graph changed.
+    pub fn rebuild(&mut self) -> bool {
+        let mut changed = false;
+        loop {
+            let mut changed_iter = false;
+            for id in self.ids().collect::<Vec<_>>() {
+                let canon = match self[id] {
+                    Node::Add(lhs, rhs) => Node::Add(self.leader(lhs), self.leader(rhs)),
+                    Node::Mul(lhs, rhs) => Node::Mul(self.leader(lhs), self.leader(rhs)),
+                    Node::Choose(n, k) => Node::Choose(self.leader(n), k),
+                    Node::Pow(base, exp) => Node::Pow(self.leader(base), self.leader(exp)),
+                    Node::Copy(..) | Node::Const(_) | Node::Input(_) => continue,
+                    // Control-flow, condition, and effect nodes are not
+                    // rebuilt, since rules never union them.
+                    _ => continue,
+                };
+                if canon != self[id] {
+                    let len = self.len();
+                    let canon = canon.insert_ideal(self);
+                    changed_iter |= self.len() != len;
+                    changed_iter |= self.union(id, canon);
+                }
+            }
+            if !changed_iter {
+                return changed;
+            }
+            changed = true;
+        }
+    }
+}
+
+/// Rules for algebraic identities of bytes modulo 2^8.
+pub fn arithmetic_rules() -> Vec<Rewrite> {
+    fn fold(b: &Bindings<'_>, g: &mut Graph, f: fn(u8, u8) -> u8) -> Option<ByteNode> {
+        let a = g.const_value(b.get("a"))?;
+        let c = g.const_value(b.get("b"))?;
+        Some(Node::Const(f(a, c)).insert_ideal(g))
+    }
+    let rules = [
+        Rewrite::new_fn("fold-add", "(+ ?a ?b)", |b, g| fold(b, g, u8::wrapping_add)),
+        Rewrite::new_fn("fold-mul", "(* ?a ?b)", |b, g| fold(b, g, u8::wrapping_mul)),
+        Rewrite::new("add-0", "(+ ?x 0)", "?x"),
+        Rewrite::new("mul-1", "(* ?x 1)", "?x"),
+        Rewrite::new("mul-0", "(* ?x 0)", "0"),
+        Rewrite::new("comm-add", "(+ ?x ?y)", "(+ ?y ?x)"),
+        Rewrite::new("comm-mul", "(* ?x ?y)", "(* ?y ?x)"),
+        Rewrite::new("assoc-add", "(+ (+ ?x ?y) ?z)", "(+ ?x (+ ?y ?z))"),
+        Rewrite::new("assoc-mul", "(* (* ?x ?y) ?z)", "(* ?x (* ?y ?z))"),
+        Rewrite::new("distribute", "(* ?x (+ ?y ?z))", "(+ (* ?x ?y) (* ?x ?z))"),
+        Rewrite::new("factor", "(+ (* ?x ?y) (* ?x ?z))", "(* ?x (+ ?y ?z))"),
+        Rewrite::new("double", "(* ?x 2)", "(+ ?x ?x)"),
+    ];
+    rules
+        .into_iter()
+        .map(|rule| rule.expect("invalid built-in rule"))
+        .collect()
+}
+
+/// A tokenizer for S-expressions.
+struct Tokens<'s> {
+    src: &'s str,
+}
+
+impl<'s> Tokens<'s> {
+    fn next(&mut self) -> Option<&'s str> {
+        self.src = self.src.trim_start();
+        let len = match self.src.chars().next()? {
+            '(' | ')' => 1,
+            _ => self
+                .src
+                .find(|ch: char| ch.is_whitespace() || ch == '(' || ch == ')')
+                .unwrap_or(self.src.len()),
+        };
+        let (token, rest) = self.src.split_at(len);
+        self.src = rest;
+        Some(token)
+    }
+}
+
+impl Display for PatternError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PatternError::UnexpectedEof => write!(f, "unexpected end of pattern"),
+            PatternError::UnexpectedToken(token) => write!(f, "unexpected token {token:?}"),
+            PatternError::UnknownOperator(op) => write!(f, "unknown operator {op:?}"),
+            PatternError::ConstOutOfRange(c) => write!(f, "constant {c} out of range for byte"),
+            PatternError::UnboundVar(var) => write!(f, "variable ?{var} not bound by pattern"),
+            PatternError::VarRoot => write!(f, "pattern cannot be only a variable"),
+        }
+    }
+}
+
+impl Error for PatternError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        egraph::{ByteNode, Graph},
+        node::{BlockId, Node, Offset},
+        rewrite::{arithmetic_rules, Limits, PatternError, Rewrite, StopReason},
+    };
+
+    #[test]
+    fn double() {
+        let mut g = Graph::new();
+        let x = Node::Copy(Offset(0), BlockId(0)).insert_ideal(&mut g);
+        let two = Node::Const(2).insert_ideal(&mut g);
+        let mul: ByteNode = Node::Mul(x, two).insert_ideal(&mut g);
+        let add: ByteNode = Node::Add(x, x).insert_ideal(&mut g);
+        let rules = [Rewrite::new("double", "(* ?x 2)", "(+ ?x ?x)").unwrap()];
+        assert_eq!(g.saturate(&rules, Limits::default()), StopReason::Saturated);
+        assert_eq!(g.leader(mul), g.leader(add));
+    }
+
+    #[test]
+    fn congruence() {
+        let mut g = Graph::new();
+        let x = Node::Copy(Offset(0), BlockId(0)).insert_ideal(&mut g);
+        let y = Node::Copy(Offset(1), BlockId(0)).insert_ideal(&mut g);
+        let x_y = Node::Add(x, y).insert_ideal(&mut g);
+        let y_x = Node::Add(y, x).insert_ideal(&mut g);
+        let three = Node::Const(3).insert_ideal(&mut g);
+        let mul1: ByteNode = Node::Mul(x_y, three).insert_ideal(&mut g);
+        let mul2: ByteNode = Node::Mul(y_x, three).insert_ideal(&mut g);
+        g.union(x_y, y_x);
+        assert_ne!(g.leader(mul1), g.leader(mul2));
+        assert!(g.rebuild());
+        assert_eq!(g.leader(mul1), g.leader(mul2));
+    }
+
+    #[test]
+    fn distribute_and_fold() {
+        let mut g = Graph::new();
+        let x = Node::Copy(Offset(0), BlockId(0)).insert_ideal(&mut g);
+        let c2 = Node::Const(2).insert_ideal(&mut g);
+        let c3 = Node::Const(3).insert_ideal(&mut g);
+        let c5: ByteNode = Node::Const(5).insert_ideal(&mut g);
+        // (x + 2) * 3
+        let x_2 = Node::Add(x, c2).insert_ideal(&mut g);
+        let lhs: ByteNode = Node::Mul(x_2, c3).insert_ideal(&mut g);
+        // x * 3 + 6
+        let x_3 = Node::Mul(x, c3).insert_ideal(&mut g);
+        let c6 = Node::Const(6).insert_ideal(&mut g);
+        let rhs: ByteNode = Node::Add(x_3, c6).insert_ideal(&mut g);
+        // 2 + 3
+        let sum: ByteNode = Node::Add(c2, c3).insert_ideal(&mut g);
+        let limits = Limits {
+            iters: 8,
+            nodes: 5_000,
+        };
+        g.saturate(&arithmetic_rules(), limits);
+        assert_eq!(g.leader(lhs), g.leader(rhs));
+        assert_eq!(g.leader(sum), g.leader(c5));
+        assert_eq!(g.const_value(sum), Some(5));
+    }
+
+    #[test]
+    fn versions_not_equal() {
+        let mut g = Graph::new();
+        let x: ByteNode = Node::Copy(Offset(0), BlockId(0)).insert_ideal(&mut g);
+        let c0 = Node::Const(0).insert_ideal(&mut g);
+        let c1 = Node::Const(1).insert_ideal(&mut g);
+        // A pass stores a new value to the cell, which is only a version.
+        x.replace(c0, &mut g);
+        let sum: ByteNode = Node::Add(x, c1).insert_ideal(&mut g);
+        g.saturate(&arithmetic_rules(), Limits::default());
+        assert_eq!(g.const_value(x), None);
+        assert_eq!(g.const_value(sum), None);
+        assert_ne!(g.leader(sum), g.leader(c1));
+        assert_eq!(g.canonical(x), c0);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let err = |lhs, rhs| Rewrite::new("r", lhs, rhs).err();
+        assert_eq!(
+            err("(+ ?x 0)", "?y"),
+            Some(PatternError::UnboundVar("y".into()))
+        );
+        assert_eq!(err("?x", "?x"), Some(PatternError::VarRoot));
+        assert_eq!(
+            err("(- ?x 0)", "?x"),
+            Some(PatternError::UnknownOperator("-".into()))
+        );
+        assert_eq!(
+            err("(+ ?x 256)", "?x"),
+            Some(PatternError::ConstOutOfRange("256".into()))
+        );
+        assert_eq!(err("(+ ?x 0", "?x"), Some(PatternError::UnexpectedEof));
+        assert_eq!(err("(+ ?x -1)", "?x"), None);
+    }
+}
@@ -0,0 +1,1114 @@
+//! Serialization of snapshots of an e-graph together with its CFG.
+//!
+//! A snapshot can be written in a compact binary format or as readable JSON.
+//! Both encode the same tree of values: the binary format writes objects as
+//! lists of their values in field order, while JSON keys them by name. Node
+//! and e-class IDs are written as 0-based indices. Every node refers only to
+//! nodes before it, so loading inserts them in order, which rebuilds the
+//! hash table for value numbering. The facts of byte nodes are recomputed,
+//! except for assumptions on copies and inputs, which are stored.
+//!
+//! The binary format starts with [`MAGIC`], followed by the value tree, which
+//! begins with the format version. Each value is a tag byte, followed by its
+//! payload:
+//!
+//! - `0`: null
+//! - `1`: integer, as a zigzag LEB128
+//! - `2`: UTF-8 string, as a LEB128 length and its bytes
+//! - `3`: byte string, as a LEB128 length and its bytes
+//! - `4`: list or object, as a LEB128 length and its values
+
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter, Write},
+    str,
+};
+
+use crate::{
+    analysis::Facts,
+    block::{Block, DecimalEnd, Effect},
+    cfg::{Cfg, Seq},
+    egraph::{Eclass, EclassId, Graph, Pass, TypedId},
+    node::{BlockId, InputId, Node, Offset},
+    verify::VerifyError,
+};
+
+/// The magic bytes, which start the binary format.
+pub const MAGIC: [u8; 8] = *b"bfrs2ir\0";
+
+/// The version of the snapshot format, which is written. It is incremented
+/// when the format changes incompatibly.
+pub const VERSION: i64 = 3;
+
+/// An error from loading a snapshot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeserializeError {
+    /// The data does not start with [`MAGIC`].
+    BadMagic,
+    /// A format version, which cannot be read.
+    UnsupportedVersion(i64),
+    /// Malformed binary or JSON at the byte offset.
+    Syntax(usize),
+    /// A field is missing.
+    MissingField(&'static str),
+    /// A field has the wrong type or is out of range.
+    BadValue(&'static str),
+    /// An unknown kind of node, effect, or CFG.
+    UnknownKind(String),
+    /// An unknown pass name.
+    UnknownPass(String),
+    /// A node ID, which is out of bounds or does not precede its user.
+    BadNodeId(i64),
+    /// A node ID, which refers to a node of the wrong kind for its user.
+    WrongKind {
+        /// The node ID.
+        id: i64,
+        /// The expected kind of node.
+        expected: &'static str,
+    },
+    /// An e-class ID, which is out of bounds.
+    BadEclassId(i64),
+    /// A node, which is structurally identical to an earlier node.
+    DuplicateNode(usize),
+    /// The e-graph or CFG violates an invariant.
+    Invalid(VerifyError),
+}
+
+impl Graph {
+    /// Serializes this e-graph and the CFG in the binary format.
+    pub fn serialize(&self, cfg: &Cfg) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        write_binary(&mut out, &self.snapshot(cfg));
+        out
+    }
+
+    /// Serializes this e-graph and the CFG as JSON.
+    pub fn serialize_json(&self, cfg: &Cfg) -> String {
+        let mut out = String::new();
+        write_json(&mut out, &self.snapshot(cfg), 0);
+        out.push('\n');
+        out
+    }
+
+    /// Loads an e-graph and CFG from the binary format.
+    pub fn deserialize(bytes: &[u8]) -> Result<(Graph, Cfg), DeserializeError> {
+        let bytes = bytes
+            .strip_prefix(&MAGIC)
+            .ok_or(DeserializeError::BadMagic)?;
+        let mut r = BinaryReader { bytes, pos: 0 };
+        let value = r.read_value()?;
+        if r.pos != bytes.len() {
+            return Err(DeserializeError::Syntax(MAGIC.len() + r.pos));
+        }
+        Graph::load(value)
+    }
+
+    /// Loads an e-graph and CFG from JSON.
+    pub fn deserialize_json(src: &str) -> Result<(Graph, Cfg), DeserializeError> {
+        let mut r = JsonReader {
+            src: src.as_bytes(),
+            pos: 0,
+        };
+        let value = r.read_value()?;
+        r.skip_whitespace();
+        if r.pos != src.len() {
+            return Err(DeserializeError::Syntax(r.pos));
+        }
+        Graph::load(value)
+    }
+
+    fn snapshot(&self, cfg: &Cfg) -> Value {
+        let (next_block, next_input) = self.next_ids();
+        let nodes = self.ids().map(|id| {
+            let entry = self.entry(id);
+            let facts = match entry.node() {
+                Node::Copy(..) | Node::Input(_) if self.facts(id) != Facts::TOP => {
+                    let facts = self.facts(id);
+                    let parts = [facts.min(), facts.max()];
+                    let bits = [facts.known_zeros(), facts.known_ones()];
+                    Value::list_of(parts.into_iter().chain(bits))
+                }
+                _ => Value::Null,
+            };
+            Value::object([
+                ("node", node_value(entry.node())),
+                ("creator", entry.creator().name().into()),
+                ("eclass", entry.eclass().map(eclass_value).into()),
+                (
+                    "equal",
+                    (self.leader(id) != id).then(|| self.leader(id)).into(),
+                ),
+                ("facts", facts),
+            ])
+        });
+        let eclasses = (0..self.eclasses() as u32).map(|index| {
+            match self.eclass_entry(EclassId::from_index(index)) {
+                Ok(eclass) => Value::object([
+                    ("kind", "eclass".into()),
+                    ("canon", eclass.canon().into()),
+                    ("nodes", Value::list_of(eclass.nodes().iter().copied())),
+                ]),
+                Err(eid) => Value::object([("kind", "union".into()), ("into", eclass_value(eid))]),
+            }
+        });
+        let graph = Value::object([
+            ("next_block", next_block.0.into()),
+            ("next_input", next_input.0.into()),
+            ("pass", self.pass().name().into()),
+            ("nodes", Value::list_of(nodes)),
+            ("eclasses", Value::list_of(eclasses)),
+        ]);
+        Value::object([
+            ("version", VERSION.into()),
+            ("graph", graph),
+            ("cfg", cfg_value(cfg)),
+        ])
+    }
+
+    fn load(value: Value) -> Result<(Graph, Cfg), DeserializeError> {
+        let mut fields = Fields::of(value, "snapshot")?;
+        let version = fields.get("version")?.int("version")?;
+        if version != VERSION {
+            return Err(DeserializeError::UnsupportedVersion(version));
+        }
+        let mut graph = Fields::of(fields.get("graph")?, "graph")?;
+        let next_block = BlockId(graph.get("next_block")?.uint("next_block")?);
+        let next_input = InputId(graph.get("next_input")?.uint("next_input")?);
+        let pass = load_pass(graph.get("pass")?)?;
+
+        let mut g = Graph::new();
+        let mut node_eclasses = Vec::new();
+        let mut leaders = Vec::new();
+        for (index, entry) in graph.get("nodes")?.list("nodes")?.into_iter().enumerate() {
+            let mut entry = Fields::of(entry, "nodes")?;
+            let node = load_node(entry.get("node")?, &g)?;
+            g.set_pass(load_pass(entry.get("creator")?)?);
+            let id = g.insert(node);
+            if id.index() != index {
+                return Err(DeserializeError::DuplicateNode(index));
+            }
+            node_eclasses.push(entry.get("eclass")?.option(|eid| eid.int("eclass"))?);
+            leaders.push(entry.get("equal")?);
+            if let Some(facts) = entry.get("facts")?.option(load_facts)? {
+                if !matches!(g[id], Node::Copy(..) | Node::Input(_)) {
+                    return Err(DeserializeError::BadValue("facts"));
+                }
+                g.assume(id, facts);
+            }
+        }
+        let eclasses = graph.get("eclasses")?.list("eclasses")?;
+        let eclasses_len = eclasses.len();
+        let eclass_id = |eid: i64| match u32::try_from(eid) {
+            Ok(index) if (index as usize) < eclasses_len => Ok(EclassId::from_index(index)),
+            _ => Err(DeserializeError::BadEclassId(eid)),
+        };
+        let node_eclasses = node_eclasses
+            .into_iter()
+            .map(|eid| eid.map(eclass_id).transpose())
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut entries = Vec::with_capacity(eclasses.len());
+        for eclass in eclasses {
+            let mut eclass = Fields::of(eclass, "eclasses")?;
+            let kind = eclass.get("kind")?.string("kind")?;
+            entries.push(match &*kind {
+                "eclass" => {
+                    let canon = eclass.node_id("canon", &g)?;
+                    let nodes = eclass.get("nodes")?.list("nodes")?;
+                    let nodes = nodes
+                        .into_iter()
+                        .map(|id| load_node_id(id, &g))
+                        .collect::<Result<_, _>>()?;
+                    Ok(Eclass::new(canon, nodes))
+                }
+                "union" => Err(eclass_id(eclass.get("into")?.int("into")?)?),
+                _ => return Err(DeserializeError::UnknownKind(kind)),
+            });
+        }
+        g.restore_eclasses(entries, node_eclasses);
+        // Leaders may come after the nodes they lead, so they are loaded once
+        // all nodes are inserted.
+        let leaders = leaders
+            .into_iter()
+            .map(|leader| leader.option(|id| load_node_id(id, &g)))
+            .collect::<Result<_, _>>()?;
+        g.restore_equalities(leaders);
+        g.verify().map_err(DeserializeError::Invalid)?;
+        g.set_next_ids(next_block, next_input);
+        g.set_pass(pass);
+
+        let cfg = load_cfg(fields.get("cfg")?, &g)?;
+        cfg.verify(&g).map_err(DeserializeError::Invalid)?;
+        Ok((g, cfg))
+    }
+}
+
+fn eclass_value(eid: EclassId) -> Value {
+    Value::Int(eid.index() as i64)
+}
+
+fn node_value(node: &Node) -> Value {
+    match *node {
+        Node::Copy(offset, block) => Value::object([
+            ("kind", "copy".into()),
+            ("offset", offset.into()),
+            ("block", block.0.into()),
+        ]),
+        Node::Const(c) => Value::object([("kind", "const".into()), ("value", c.into())]),
+        Node::Input(id) => Value::object([("kind", "input".into()), ("id", id.0.into())]),
+        Node::Add(lhs, rhs) => Value::object([
+            ("kind", "add".into()),
+            ("lhs", lhs.into()),
+            ("rhs", rhs.into()),
+        ]),
+        Node::Mul(lhs, rhs) => Value::object([
+            ("kind", "mul".into()),
+            ("lhs", lhs.into()),
+            ("rhs", rhs.into()),
+        ]),
+        Node::Choose(n, k) => {
+            Value::object([("kind", "choose".into()), ("n", n.into()), ("k", k.into())])
+        }
+        Node::Pow(base, exp) => Value::object([
+            ("kind", "pow".into()),
+            ("base", base.into()),
+            ("exp", exp.into()),
+        ]),
+        Node::Block(ref block) => {
+            Value::object([("kind", "block".into()), ("block", block_value(block))])
+        }
+        Node::Seq(ref seq) => Value::object([
+            ("kind", "seq".into()),
+            ("seq", Value::list_of(seq.iter().copied())),
+        ]),
+        Node::Loop(cond, body) => Value::object([
+            ("kind", "loop".into()),
+            ("cond", cond.into()),
+            ("body", body.into()),
+        ]),
+        Node::If(cond, body) => Value::object([
+            ("kind", "if".into()),
+            ("cond", cond.into()),
+            ("body", body.into()),
+        ]),
+        Node::Repeat { id, count, body } => Value::object([
+            ("kind", "repeat".into()),
+            ("id", id.0.into()),
+            ("count", count.into()),
+            ("body", body.into()),
+        ]),
+        Node::InputCells { step, delimiter } => Value::object([
+            ("kind", "input_cells".into()),
+            ("step", step.into()),
+            ("delimiter", delimiter.into()),
+        ]),
+        Node::NonZero(offset) => {
+            Value::object([("kind", "non_zero".into()), ("offset", offset.into())])
+        }
+        Node::Entry(block) => Value::object([("kind", "entry".into()), ("block", block.0.into())]),
+        Node::Effect(token, ref effect) => Value::object([
+            ("kind", "effect".into()),
+            ("token", token.into()),
+            ("effect", effect_value(effect)),
+        ]),
+    }
+}
+
+fn block_value(block: &Block) -> Value {
+    let memory = (block.min_offset().0..block.max_offset().0)
+        .map(|offset| Value::from(block.get_cell(Offset(offset))));
+    Value::object([
+        ("id", block.id.0.into()),
+        ("offset", block.offset.into()),
+        ("guarded_left", block.guarded_left.into()),
+        ("guarded_right", block.guarded_right.into()),
+        ("min_offset", block.min_offset().into()),
+        ("memory", Value::list_of(memory)),
+        ("effects", block.effect_token().into()),
+    ])
+}
+
+fn effect_value(effect: &Effect) -> Value {
+    match *effect {
+        Effect::Output(ref values) => Value::object([
+            ("kind", "output".into()),
+            ("values", Value::list_of(values.iter().copied())),
+        ]),
+        Effect::OutputBytes(ref bytes) => Value::object([
+            ("kind", "output_bytes".into()),
+            ("bytes", Value::Bytes(bytes.clone())),
+        ]),
+        Effect::OutputDecimal(value) => {
+            Value::object([("kind", "output_decimal".into()), ("value", value.into())])
+        }
+        Effect::Input(input) => Value::object([("kind", "input".into()), ("input", input.into())]),
+        Effect::InputUntil {
+            input,
+            ref delimiters,
+        } => Value::object([
+            ("kind", "input_until".into()),
+            ("input", input.into()),
+            ("delimiters", Value::Bytes(delimiters.clone())),
+        ]),
+        Effect::InputDecimal { value, ref end } => {
+            let end = match *end {
+                DecimalEnd::Digits(n) => {
+                    Value::object([("kind", "digits".into()), ("digits", n.into())])
+                }
+                DecimalEnd::Delimiters(ref delimiters) => Value::object([
+                    ("kind", "delimiters".into()),
+                    ("delimiters", Value::Bytes(delimiters.clone())),
+                ]),
+            };
+            Value::object([
+                ("kind", "input_decimal".into()),
+                ("value", value.into()),
+                ("end", end),
+            ])
+        }
+        Effect::GuardShift(offset) => {
+            Value::object([("kind", "guard_shift".into()), ("offset", offset.into())])
+        }
+    }
+}
+
+fn cfg_value(cfg: &Cfg) -> Value {
+    match *cfg {
+        Cfg::Block(ref block) => {
+            Value::object([("kind", "block".into()), ("block", block_value(block))])
+        }
+        Cfg::Seq(ref seq) => Value::object([
+            ("kind", "seq".into()),
+            ("seq", Value::list_of(seq.iter().map(cfg_value))),
+        ]),
+        Cfg::Loop(ref body) => Value::object([("kind", "loop".into()), ("body", cfg_value(body))]),
+        Cfg::If(ref body) => Value::object([("kind", "if".into()), ("body", cfg_value(body))]),
+        Cfg::Repeat {
+            id,
+            count,
+            ref body,
+        } => Value::object([
+            ("kind", "repeat".into()),
+            ("id", id.0.into()),
+            ("count", count.into()),
+            ("body", cfg_value(body)),
+        ]),
+        Cfg::InputCells { step, delimiter } => Value::object([
+            ("kind", "input_cells".into()),
+            ("step", step.into()),
+            ("delimiter", delimiter.into()),
+        ]),
+    }
+}
+
+fn load_pass(value: Value) -> Result<Pass, DeserializeError> {
+    let name = value.string("pass")?;
+    Pass::from_name(&name).ok_or(DeserializeError::UnknownPass(name))
+}
+
+fn load_facts(value: Value) -> Result<Facts, DeserializeError> {
+    let parts = value.list("facts")?;
+    let [min, max, zeros, ones] = <[Value; 4]>::try_from(parts)
+        .map_err(|_| DeserializeError::BadValue("facts"))?
+        .map(|part| part.uint::<u8>("facts"));
+    let (min, max, zeros, ones) = (min?, max?, zeros?, ones?);
+    if min > max || zeros & ones != 0 {
+        return Err(DeserializeError::BadValue("facts"));
+    }
+    match Facts::range(min, max).meet(&Facts::bits(zeros, ones)) {
+        Some(facts)
+            if (
+                facts.min(),
+                facts.max(),
+                facts.known_zeros(),
+                facts.known_ones(),
+            ) == (min, max, zeros, ones) =>
+        {
+            Ok(facts)
+        }
+        _ => Err(DeserializeError::BadValue("facts")),
+    }
+}
+
+/// Loads the ID of a node, which must already be in the e-graph and be of the
+/// requested kind.
+fn load_node_id<T: TypedId>(value: Value, g: &Graph) -> Result<T, DeserializeError> {
+    let index = value.int("node ID")?;
+    let id = usize::try_from(index)
+        .ok()
+        .and_then(|i| g.node_id(i))
+        .ok_or(DeserializeError::BadNodeId(index))?;
+    T::new(id, g).ok_or(DeserializeError::WrongKind {
+        id: index,
+        expected: T::KIND,
+    })
+}
+
+fn load_node(value: Value, g: &Graph) -> Result<Node, DeserializeError> {
+    let mut fields = Fields::of(value, "node")?;
+    let kind = fields.get("kind")?.string("kind")?;
+    Ok(match &*kind {
+        "copy" => {
+            let offset = fields.get("offset")?.offset("offset")?;
+            Node::Copy(offset, BlockId(fields.get("block")?.uint("block")?))
+        }
+        "const" => Node::Const(fields.get("value")?.uint("value")?),
+        "input" => Node::Input(InputId(fields.get("id")?.uint("id")?)),
+        "add" => Node::Add(fields.node_id("lhs", g)?, fields.node_id("rhs", g)?),
+        "mul" => Node::Mul(fields.node_id("lhs", g)?, fields.node_id("rhs", g)?),
+        "choose" => Node::Choose(fields.node_id("n", g)?, fields.get("k")?.uint("k")?),
+        "pow" => Node::Pow(fields.node_id("base", g)?, fields.node_id("exp", g)?),
+        "block" => Node::Block(Box::new(load_block(fields.get("block")?, g)?)),
+        "seq" => Node::Seq(
+            fields
+                .get("seq")?
+                .list("seq")?
+                .into_iter()
+                .map(|id| load_node_id(id, g))
+                .collect::<Result<_, _>>()?,
+        ),
+        "loop" => Node::Loop(fields.node_id("cond", g)?, fields.node_id("body", g)?),
+        "if" => Node::If(fields.node_id("cond", g)?, fields.node_id("body", g)?),
+        "repeat" => Node::Repeat {
+            id: BlockId(fields.get("id")?.uint("id")?),
+            count: fields.node_id("count", g)?,
+            body: fields.node_id("body", g)?,
+        },
+        "input_cells" => Node::InputCells {
+            step: fields.get("step")?.offset("step")?,
+            delimiter: fields.get("delimiter")?.uint("delimiter")?,
+        },
+        "non_zero" => Node::NonZero(fields.get("offset")?.offset("offset")?),
+        "entry" => Node::Entry(BlockId(fields.get("block")?.uint("block")?)),
+        "effect" => {
+            let token = fields.node_id("token", g)?;
+            Node::Effect(token, load_effect(fields.get("effect")?, g)?)
+        }
+        _ => return Err(DeserializeError::UnknownKind(kind)),
+    })
+}
+
+fn load_block(value: Value, g: &Graph) -> Result<Block, DeserializeError> {
+    let mut fields = Fields::of(value, "block")?;
+    let mut block = Block::new(BlockId(fields.get("id")?.uint("id")?));
+    block.offset = fields.get("offset")?.offset("offset")?;
+    block.guarded_left = fields.get("guarded_left")?.offset("guarded_left")?;
+    block.guarded_right = fields.get("guarded_right")?.offset("guarded_right")?;
+    let min_offset = fields.get("min_offset")?.offset("min_offset")?;
+    let memory = fields.get("memory")?.list("memory")?;
+    let max_offset = i64::try_from(memory.len())
+        .ok()
+        .and_then(|len| min_offset.0.checked_add(len))
+        .ok_or(DeserializeError::BadValue("memory"))?;
+    block.reserve(min_offset, Offset(max_offset));
+    for (offset, cell) in (min_offset.0..).zip(memory) {
+        if let Some(cell) = cell.option(|cell| load_node_id(cell, g))? {
+            block.set_cell(Offset(offset), cell);
+        }
+    }
+    let token = (fields.get("effects")?).option(|token| load_node_id(token, g))?;
+    if let Some(token) = token {
+        block.set_effect_token(token, g);
+    }
+    Ok(block)
+}
+
+fn load_effect(value: Value, g: &Graph) -> Result<Effect, DeserializeError> {
+    let mut fields = Fields::of(value, "effect")?;
+    let kind = fields.get("kind")?.string("kind")?;
+    Ok(match &*kind {
+        "output" => Effect::Output(
+            fields
+                .get("values")?
+                .list("values")?
+                .into_iter()
+                .map(|id| load_node_id(id, g))
+                .collect::<Result<_, _>>()?,
+        ),
+        "output_bytes" => Effect::OutputBytes(fields.get("bytes")?.bytes("bytes")?),
+        "output_decimal" => Effect::OutputDecimal(fields.node_id("value", g)?),
+        "input" => Effect::Input(fields.node_id("input", g)?),
+        "input_until" => Effect::InputUntil {
+            input: fields.node_id("input", g)?,
+            delimiters: fields.get("delimiters")?.bytes("delimiters")?,
+        },
+        "input_decimal" => {
+            let value = fields.node_id("value", g)?;
+            let mut end = Fields::of(fields.get("end")?, "end")?;
+            let kind = end.get("kind")?.string("kind")?;
+            let end = match &*kind {
+                "digits" => DecimalEnd::Digits(end.get("digits")?.uint("digits")?),
+                "delimiters" => DecimalEnd::Delimiters(end.get("delimiters")?.bytes("delimiters")?),
+                _ => return Err(DeserializeError::UnknownKind(kind)),
+            };
+            Effect::InputDecimal { value, end }
+        }
+        "guard_shift" => Effect::GuardShift(fields.get("offset")?.offset("offset")?),
+        _ => return Err(DeserializeError::UnknownKind(kind)),
+    })
+}
+
+fn load_cfg(value: Value, g: &Graph) -> Result<Cfg, DeserializeError> {
+    let mut fields = Fields::of(value, "cfg")?;
+    let kind = fields.get("kind")?.string("kind")?;
+    Ok(match &*kind {
+        "block" => Cfg::Block(load_block(fields.get("block")?, g)?),
+        "seq" => Cfg::Seq(Seq::from_unflattened(
+            fields
+                .get("seq")?
+                .list("seq")?
+                .into_iter()
+                .map(|cfg| load_cfg(cfg, g))
+                .collect::<Result<_, _>>()?,
+        )),
+        "loop" => Cfg::Loop(Box::new(load_cfg(fields.get("body")?, g)?)),
+        "if" => Cfg::If(Box::new(load_cfg(fields.get("body")?, g)?)),
+        "repeat" => Cfg::Repeat {
+            id: BlockId(fields.get("id")?.uint("id")?),
+            count: fields.node_id("count", g)?,
+            body: Box::new(load_cfg(fields.get("body")?, g)?),
+        },
+        "input_cells" => Cfg::InputCells {
+            step: fields.get("step")?.offset("step")?,
+            delimiter: fields.get("delimiter")?.uint("delimiter")?,
+        },
+        _ => return Err(DeserializeError::UnknownKind(kind)),
+    })
+}
+
+/// A value in the tree, which both formats encode.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Value {
+    Null,
+    Int(i64),
+    Str(String),
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+    /// An object with fields in order. The binary format writes it as a list.
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    fn object<const N: usize>(fields: [(&str, Value); N]) -> Self {
+        Value::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_owned(), value))
+                .collect(),
+        )
+    }
+
+    fn list_of<T: Into<Value>>(values: impl IntoIterator<Item = T>) -> Self {
+        Value::List(values.into_iter().map(Into::into).collect())
+    }
+
+    fn int(self, field: &'static str) -> Result<i64, DeserializeError> {
+        match self {
+            Value::Int(n) => Ok(n),
+            _ => Err(DeserializeError::BadValue(field)),
+        }
+    }
+
+    fn uint<T: TryFrom<i64>>(self, field: &'static str) -> Result<T, DeserializeError> {
+        T::try_from(self.int(field)?).map_err(|_| DeserializeError::BadValue(field))
+    }
+
+    fn offset(self, field: &'static str) -> Result<Offset, DeserializeError> {
+        Ok(Offset(self.int(field)?))
+    }
+
+    fn string(self, field: &'static str) -> Result<String, DeserializeError> {
+        match self {
+            Value::Str(s) => Ok(s),
+            _ => Err(DeserializeError::BadValue(field)),
+        }
+    }
+
+    /// Gets a byte string. JSON writes bytes as a string of the characters
+    /// U+0000 to U+00FF.
+    fn bytes(self, field: &'static str) -> Result<Vec<u8>, DeserializeError> {
+        match self {
+            Value::Bytes(bytes) => Ok(bytes),
+            Value::Str(s) => s
+                .chars()
+                .map(|ch| u8::try_from(ch).map_err(|_| DeserializeError::BadValue(field)))
+                .collect(),
+            _ => Err(DeserializeError::BadValue(field)),
+        }
+    }
+
+    fn list(self, field: &'static str) -> Result<Vec<Value>, DeserializeError> {
+        match self {
+            Value::List(values) => Ok(values),
+            _ => Err(DeserializeError::BadValue(field)),
+        }
+    }
+
+    fn option<T>(
+        self,
+        f: impl FnOnce(Value) -> Result<T, DeserializeError>,
+    ) -> Result<Option<T>, DeserializeError> {
+        match self {
+            Value::Null => Ok(None),
+            value => f(value).map(Some),
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Int(n)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(n: u32) -> Self {
+        Value::Int(n.into())
+    }
+}
+
+impl From<u8> for Value {
+    fn from(n: u8) -> Self {
+        Value::Int(n.into())
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Str(s.to_owned())
+    }
+}
+
+impl From<Offset> for Value {
+    fn from(offset: Offset) -> Self {
+        Value::Int(offset.0)
+    }
+}
+
+impl<T: TypedId> From<T> for Value {
+    fn from(id: T) -> Self {
+        Value::Int(id.into().index() as i64)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+/// The fields of an object, which are accessed in order for the binary format
+/// and by name for JSON.
+enum Fields {
+    List(std::vec::IntoIter<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Fields {
+    fn of(value: Value, field: &'static str) -> Result<Self, DeserializeError> {
+        match value {
+            Value::List(values) => Ok(Fields::List(values.into_iter())),
+            Value::Object(fields) => Ok(Fields::Object(fields)),
+            _ => Err(DeserializeError::BadValue(field)),
+        }
+    }
+
+    fn get(&mut self, name: &'static str) -> Result<Value, DeserializeError> {
+        let value = match self {
+            Fields::List(values) => values.next(),
+            Fields::Object(fields) => fields
+                .iter()
+                .position(|(key, _)| key == name)
+                .map(|i| fields.remove(i).1),
+        };
+        value.ok_or(DeserializeError::MissingField(name))
+    }
+
+    /// Gets a field with the ID of a node, which must already be in the
+    /// e-graph and be of the requested kind.
+    fn node_id<T: TypedId>(
+        &mut self,
+        name: &'static str,
+        g: &Graph,
+    ) -> Result<T, DeserializeError> {
+        load_node_id(self.get(name)?, g)
+    }
+}
+
+fn write_binary(out: &mut Vec<u8>, value: &Value) {
+    fn write_len(out: &mut Vec<u8>, len: usize) {
+        write_leb128(out, len as u64);
+    }
+    match value {
+        Value::Null => out.push(0),
+        &Value::Int(n) => {
+            out.push(1);
+            write_leb128(out, ((n << 1) ^ (n >> 63)) as u64);
+        }
+        Value::Str(s) => {
+            out.push(2);
+            write_len(out, s.len());
+            out.extend_from_slice(s.as_bytes());
+        }
+        Value::Bytes(bytes) => {
+            out.push(3);
+            write_len(out, bytes.len());
+            out.extend_from_slice(bytes);
+        }
+        Value::List(values) => {
+            out.push(4);
+            write_len(out, values.len());
+            values.iter().for_each(|value| write_binary(out, value));
+        }
+        Value::Object(fields) => {
+            out.push(4);
+            write_len(out, fields.len());
+            fields
+                .iter()
+                .for_each(|(_, value)| write_binary(out, value));
+        }
+    }
+}
+
+fn write_leb128(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+struct BinaryReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl BinaryReader<'_> {
+    fn read_value(&mut self) -> Result<Value, DeserializeError> {
+        let start = self.pos;
+        let tag = self.read_byte()?;
+        Ok(match tag {
+            0 => Value::Null,
+            1 => {
+                let n = self.read_leb128()?;
+                Value::Int((n >> 1) as i64 ^ -((n & 1) as i64))
+            }
+            2 => {
+                let bytes = self.read_slice()?;
+                let s = str::from_utf8(bytes).map_err(|_| DeserializeError::Syntax(start))?;
+                Value::Str(s.to_owned())
+            }
+            3 => Value::Bytes(self.read_slice()?.to_vec()),
+            4 => {
+                let len = self.read_len()?;
+                // Every value takes at least one byte, which bounds the
+                // allocation for malformed lengths.
+                let mut values = Vec::with_capacity(len.min(self.bytes.len() - self.pos));
+                for _ in 0..len {
+                    values.push(self.read_value()?);
+                }
+                Value::List(values)
+            }
+            _ => return Err(DeserializeError::Syntax(MAGIC.len() + start)),
+        })
+    }
+
+    fn read_byte(&mut self) -> Result<u8, DeserializeError> {
+        let b = *self
+            .bytes
+            .get(self.pos)
+            .ok_or(DeserializeError::Syntax(MAGIC.len() + self.pos))?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn read_leb128(&mut self) -> Result<u64, DeserializeError> {
+        let start = self.pos;
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.read_byte()?;
+            n |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(DeserializeError::Syntax(MAGIC.len() + start))
+    }
+
+    fn read_len(&mut self) -> Result<usize, DeserializeError> {
+        let start = self.pos;
+        let len = self.read_leb128()?;
+        usize::try_from(len).map_err(|_| DeserializeError::Syntax(MAGIC.len() + start))
+    }
+
+    fn read_slice(&mut self) -> Result<&[u8], DeserializeError> {
+        let start = self.pos;
+        let len = self.read_len()?;
+        if len > self.bytes.len() - self.pos {
+            return Err(DeserializeError::Syntax(MAGIC.len() + start));
+        }
+        self.pos += len;
+        Ok(&self.bytes[self.pos - len..self.pos])
+    }
+}
+
+/// Writes a value as JSON. Values, which fit on a line, are written inline and
+/// others are expanded with one element per line.
+fn write_json(out: &mut String, value: &Value, indent: usize) {
+    const WIDTH: usize = 100;
+    let mut inline = String::new();
+    write_json_inline(&mut inline, value);
+    if indent * 2 + inline.len() <= WIDTH {
+        out.push_str(&inline);
+        return;
+    }
+    let newline = |out: &mut String, indent: usize| {
+        out.push('\n');
+        (0..indent).for_each(|_| out.push_str("  "));
+    };
+    match value {
+        Value::List(values) => {
+            out.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i != 0 {
+                    out.push(',');
+                }
+                newline(out, indent + 1);
+                write_json(out, value, indent + 1);
+            }
+            newline(out, indent);
+            out.push(']');
+        }
+        Value::Object(fields) => {
+            out.push('{');
+            for (i, (key, value)) in fields.iter().enumerate() {
+                if i != 0 {
+                    out.push(',');
+                }
+                newline(out, indent + 1);
+                write_json_str(out, key.chars());
+                out.push_str(": ");
+                write_json(out, value, indent + 1);
+            }
+            newline(out, indent);
+            out.push('}');
+        }
+        _ => out.push_str(&inline),
+    }
+}
+
+fn write_json_inline(out: &mut String, value: &Value) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Int(n) => write!(out, "{n}").unwrap(),
+        Value::Str(s) => write_json_str(out, s.chars()),
+        Value::Bytes(bytes) => write_json_str(out, bytes.iter().map(|&b| b as char)),
+        Value::List(values) => {
+            out.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i != 0 {
+                    out.push_str(", ");
+                }
+                write_json_inline(out, value);
+            }
+            out.push(']');
+        }
+        Value::Object(fields) => {
+            out.push('{');
+            for (i, (key, value)) in fields.iter().enumerate() {
+                if i != 0 {
+                    out.push_str(", ");
+                }
+                write_json_str(out, key.chars());
+                out.push_str(": ");
+                write_json_inline(out, value);
+            }
+            out.push('}');
+        }
+    }
+}
+
+fn write_json_str(out: &mut String, chars: impl Iterator<Item = char>) {
+    out.push('"');
+    for ch in chars {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\0'..='\x1f' | '\x7f'..='\u{a0}' => write!(out, "\\u{:04x}", ch as u32).unwrap(),
+            _ => out.push(ch),
+        }
+    }
+    out.push('"');
+}
+
+struct JsonReader<'a> {
+    src: &'a [u8],
+    pos: usize,
+}
+
+impl JsonReader<'_> {
+    fn read_value(&mut self) -> Result<Value, DeserializeError> {
+        self.skip_whitespace();
+        match self.src.get(self.pos) {
+            Some(b'n') => self.read_keyword("null", Value::Null),
+            Some(b'"') => self.read_str().map(Value::Str),
+            Some(b'-' | b'0'..=b'9') => self.read_int(),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut values = Vec::new();
+                if !self.eat(b']') {
+                    loop {
+                        values.push(self.read_value()?);
+                        if self.eat(b']') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+                Ok(Value::List(values))
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                if !self.eat(b'}') {
+                    loop {
+                        self.skip_whitespace();
+                        let key = self.read_str()?;
+                        self.expect(b':')?;
+                        fields.push((key, self.read_value()?));
+                        if self.eat(b'}') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+                Ok(Value::Object(fields))
+            }
+            _ => Err(DeserializeError::Syntax(self.pos)),
+        }
+    }
+
+    fn read_keyword(&mut self, keyword: &str, value: Value) -> Result<Value, DeserializeError> {
+        if !self.src[self.pos..].starts_with(keyword.as_bytes()) {
+            return Err(DeserializeError::Syntax(self.pos));
+        }
+        self.pos += keyword.len();
+        Ok(value)
+    }
+
+    fn read_int(&mut self) -> Result<Value, DeserializeError> {
+        let start = self.pos;
+        if self.src[self.pos] == b'-' {
+            self.pos += 1;
+        }
+        while self.src.get(self.pos).is_some_and(u8::is_ascii_digit) {
+            self.pos += 1;
+        }
+        // SAFETY: The bytes are ASCII.
+        let digits = unsafe { str::from_utf8_unchecked(&self.src[start..self.pos]) };
+        digits
+            .parse()
+            .map(Value::Int)
+            .map_err(|_| DeserializeError::Syntax(start))
+    }
+
+    fn read_str(&mut self) -> Result<String, DeserializeError> {
+        self.expect(b'"')?;
+        let mut s = Vec::new();
+        loop {
+            let start = self.pos;
+            let b = *self
+                .src
+                .get(self.pos)
+                .ok_or(DeserializeError::Syntax(self.pos))?;
+            self.pos += 1;
+            match b {
+                b'"' => break,
+                b'\\' => {
+                    let b = *self
+                        .src
+                        .get(self.pos)
+                        .ok_or(DeserializeError::Syntax(start))?;
+                    self.pos += 1;
+                    let ch = match b {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\x08',
+                        b'f' => '\x0c',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let hex = self
+                                .src
+                                .get(self.pos..self.pos + 4)
+                                .and_then(|hex| str::from_utf8(hex).ok())
+                                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                .and_then(char::from_u32)
+                                .ok_or(DeserializeError::Syntax(start))?;
+                            self.pos += 4;
+                            hex
+                        }
+                        _ => return Err(DeserializeError::Syntax(start)),
+                    };
+                    s.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                b'\0'..=b'\x1f' => return Err(DeserializeError::Syntax(start)),
+                _ => s.push(b),
+            }
+        }
+        // The source is a `str`, so the string is UTF-8.
+        Ok(String::from_utf8(s).unwrap())
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.src.get(self.pos) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, b: u8) -> bool {
+        self.skip_whitespace();
+        if self.src.get(self.pos) == Some(&b) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, b: u8) -> Result<(), DeserializeError> {
+        if self.eat(b) {
+            Ok(())
+        } else {
+            Err(DeserializeError::Syntax(self.pos))
+        }
+    }
+}
+
+impl Display for DeserializeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            DeserializeError::BadMagic => write!(f, "not a bfrs2 snapshot"),
+            DeserializeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {version}")
+            }
+            DeserializeError::Syntax(offset) => write!(f, "malformed snapshot at byte {offset}"),
+            DeserializeError::MissingField(name) => write!(f, "missing field {name}"),
+            DeserializeError::BadValue(name) => write!(f, "invalid value for {name}"),
+            DeserializeError::UnknownKind(kind) => write!(f, "unknown kind {kind:?}"),
+            DeserializeError::UnknownPass(name) => write!(f, "unknown pass {name:?}"),
+            DeserializeError::BadNodeId(id) => write!(f, "invalid node ID {id}"),
+            DeserializeError::WrongKind { id, expected } => {
+                write!(f, "node {id} is not a {expected}")
+            }
+            DeserializeError::BadEclassId(id) => write!(f, "invalid e-class ID {id}"),
+            DeserializeError::DuplicateNode(index) => write!(f, "node {index} is a duplicate"),
+            DeserializeError::Invalid(err) => write!(f, "invalid IR: {err}"),
+        }
+    }
+}
+
+impl Error for DeserializeError {}
@@ -0,0 +1,206 @@
+//! Verification of IR invariants.
+//!
+//! Passes are written ad hoc and can interact unsoundly, so the invariants,
+//! which other passes and the pretty printer rely on, are checked separately
+//! from the passes which should maintain them.
+
+use std::{
+    collections::HashSet,
+    error::Error,
+    fmt::{self, Display, Formatter},
+};
+
+use crate::{
+    block::{Block, Effect},
+    cfg::Cfg,
+    egraph::{ByteNode, EclassId, EffectNode, Graph, NodeId},
+    node::{BlockId, InputId, Node, Offset},
+};
+
+/// A violated invariant of the IR.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VerifyError {
+    /// A `Seq`, which contains a nested `Seq`.
+    NestedSeq,
+    /// A `Seq`, which contains adjacent blocks, that should be concatenated.
+    AdjacentBlocks,
+    /// A `Seq` with one element, which should be unwrapped.
+    SingletonSeq,
+    /// A copy in a block, which reads from another block.
+    ForeignCopy {
+        /// The enclosing block.
+        block: BlockId,
+        /// The copy.
+        copy: ByteNode,
+    },
+    /// A store to a cell outside the guarded range of its block.
+    UnguardedStore {
+        /// The block.
+        block: BlockId,
+        /// The offset of the cell.
+        offset: Offset,
+    },
+    /// A chain of effects in a block, which does not start at the entry token
+    /// of the block.
+    ForeignEffect {
+        /// The enclosing block.
+        block: BlockId,
+        /// The last token of the chain.
+        token: EffectNode,
+    },
+    /// An input, which is read by more than one effect.
+    DuplicateInput(InputId),
+    /// A chain of e-class unions, which does not terminate.
+    UnionCycle(EclassId),
+    /// A node, which is not in the e-class it refers to.
+    NotInEclass(NodeId),
+    /// A node, whose leader does not lead itself or is not of the same kind.
+    NotEqual(NodeId),
+}
+
+impl Cfg {
+    /// Checks the invariants of this CFG and the byte nodes it uses.
+    pub fn verify(&self, g: &Graph) -> Result<(), VerifyError> {
+        self.verify_inputs(g, &mut HashSet::new())
+    }
+
+    fn verify_inputs(&self, g: &Graph, inputs: &mut HashSet<InputId>) -> Result<(), VerifyError> {
+        match self {
+            Cfg::Block(block) => block.verify_inputs(g, inputs),
+            Cfg::Seq(seq) => {
+                if seq.len() == 1 {
+                    return Err(VerifyError::SingletonSeq);
+                }
+                for pair in seq.windows(2) {
+                    if let [Cfg::Block(_), Cfg::Block(_)] = pair {
+                        return Err(VerifyError::AdjacentBlocks);
+                    }
+                }
+                for cfg in seq.iter() {
+                    if let Cfg::Seq(_) = cfg {
+                        return Err(VerifyError::NestedSeq);
+                    }
+                    cfg.verify_inputs(g, inputs)?;
+                }
+                Ok(())
+            }
+            Cfg::Loop(body) | Cfg::If(body) => body.verify_inputs(g, inputs),
+            &Cfg::Repeat {
+                id,
+                count,
+                ref body,
+            } => {
+                verify_copies(count, id, g, &mut HashSet::new())?;
+                body.verify_inputs(g, inputs)
+            }
+            Cfg::InputCells { .. } => Ok(()),
+        }
+    }
+}
+
+impl Block {
+    /// Checks the invariants of this block and the byte nodes it uses.
+    pub fn verify(&self, g: &Graph) -> Result<(), VerifyError> {
+        self.verify_inputs(g, &mut HashSet::new())
+    }
+
+    fn verify_inputs(&self, g: &Graph, inputs: &mut HashSet<InputId>) -> Result<(), VerifyError> {
+        let mut visited = HashSet::new();
+        for (offset, _) in self.iter_memory() {
+            if offset < self.guarded_left || offset > self.guarded_right {
+                return Err(VerifyError::UnguardedStore {
+                    block: self.id,
+                    offset,
+                });
+            }
+        }
+        let effects = self.effects(g);
+        let values = effects.iter().flat_map(Effect::nodes).copied();
+        for value in self.iter_memory().map(|(_, cell)| cell).chain(values) {
+            verify_copies(value, self.id, g, &mut visited)?;
+        }
+        for effect in &effects {
+            let input = match *effect {
+                Effect::Input(input)
+                | Effect::InputUntil { input, .. }
+                | Effect::InputDecimal { value: input, .. } => input,
+                _ => continue,
+            };
+            let Node::Input(id) = g[input] else {
+                unreachable!();
+            };
+            if !inputs.insert(id) {
+                return Err(VerifyError::DuplicateInput(id));
+            }
+        }
+        if let Some(token) = self.effect_token() {
+            let mut entry = token;
+            while let Node::Effect(prev, _) = g[entry] {
+                entry = prev;
+            }
+            if g[entry] != Node::Entry(self.id) {
+                return Err(VerifyError::ForeignEffect {
+                    block: self.id,
+                    token,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks that a value is a byte computation, which only copies from the
+/// block.
+fn verify_copies(
+    value: ByteNode,
+    block: BlockId,
+    g: &Graph,
+    visited: &mut HashSet<ByteNode>,
+) -> Result<(), VerifyError> {
+    if !visited.insert(value) {
+        return Ok(());
+    }
+    match g[value] {
+        Node::Copy(_, b) if b != block => Err(VerifyError::ForeignCopy { block, copy: value }),
+        Node::Copy(..) | Node::Const(_) | Node::Input(_) => Ok(()),
+        Node::Add(lhs, rhs) | Node::Mul(lhs, rhs) | Node::Pow(lhs, rhs) => {
+            verify_copies(lhs, block, g, visited)?;
+            verify_copies(rhs, block, g, visited)
+        }
+        Node::Choose(n, _) => verify_copies(n, block, g, visited),
+        _ => unreachable!("non-byte node in byte expression"),
+    }
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::NestedSeq => write!(f, "sequence is not flattened"),
+            VerifyError::AdjacentBlocks => write!(f, "sequence has adjacent blocks"),
+            VerifyError::SingletonSeq => write!(f, "sequence has one element"),
+            VerifyError::ForeignCopy { block, copy } => {
+                write!(f, "copy {copy:?} not from current block {}", block.0)
+            }
+            VerifyError::UnguardedStore { block, offset } => {
+                write!(
+                    f,
+                    "store to p[{}] in block {} is not guarded",
+                    offset.0, block.0
+                )
+            }
+            VerifyError::ForeignEffect { block, token } => {
+                write!(
+                    f,
+                    "effect chain {token:?} not from current block {}",
+                    block.0
+                )
+            }
+            VerifyError::DuplicateInput(id) => write!(f, "input in{} is read more than once", id.0),
+            VerifyError::UnionCycle(eid) => write!(f, "e-class {eid:?} has cyclic unions"),
+            VerifyError::NotInEclass(id) => write!(f, "node {id:?} is not in its e-class"),
+            VerifyError::NotEqual(id) => write!(f, "node {id:?} has an invalid equality leader"),
+        }
+    }
+}
+
+impl Error for VerifyError {}
@@ -0,0 +1,69 @@
+//! Generic traversals of CFGs.
+//!
+//! A pass implements hooks for the CFG nodes it transforms and the traversal
+//! handles recursion into the children of every variant. Sequences are
+//! flattened after their children are rewritten, so hooks may replace a node
+//! with a block or sequence.
+
+use crate::{cfg::Cfg, egraph::Graph};
+
+/// A read-only depth-first traversal of a CFG.
+pub trait Visitor {
+    /// Called before the children of a CFG node are visited. Returns whether
+    /// to visit them.
+    fn pre(&mut self, cfg: &Cfg, g: &Graph) -> bool {
+        let _ = (cfg, g);
+        true
+    }
+
+    /// Called after the children of a CFG node are visited or skipped.
+    fn post(&mut self, cfg: &Cfg, g: &Graph) {
+        let _ = (cfg, g);
+    }
+}
+
+/// A mutating depth-first rewrite of a CFG.
+pub trait Rewriter {
+    /// Called before the children of a CFG node are rewritten. Returns whether
+    /// to rewrite them. When the node is replaced, the children of the
+    /// replacement are rewritten.
+    fn pre(&mut self, cfg: &mut Cfg, g: &mut Graph) -> bool {
+        let _ = (cfg, g);
+        true
+    }
+
+    /// Called after the children of a CFG node are rewritten or skipped. A
+    /// sequence has been flattened, when its children were rewritten.
+    fn post(&mut self, cfg: &mut Cfg, g: &mut Graph) {
+        let _ = (cfg, g);
+    }
+}
+
+impl Cfg {
+    /// Visits this CFG and its children in depth-first order.
+    pub fn visit(&self, v: &mut impl Visitor, g: &Graph) {
+        if v.pre(self, g) {
+            match self {
+                Cfg::Block(_) | Cfg::InputCells { .. } => {}
+                Cfg::Seq(seq) => seq.iter().for_each(|cfg| cfg.visit(v, g)),
+                Cfg::Loop(body) | Cfg::If(body) | Cfg::Repeat { body, .. } => body.visit(v, g),
+            }
+        }
+        v.post(self, g);
+    }
+
+    /// Rewrites this CFG and its children in depth-first order.
+    pub fn visit_mut(&mut self, r: &mut impl Rewriter, g: &mut Graph) {
+        if r.pre(self, g) {
+            match self {
+                Cfg::Block(_) | Cfg::InputCells { .. } => {}
+                Cfg::Seq(seq) => {
+                    seq.iter_mut().for_each(|cfg| cfg.visit_mut(r, g));
+                    self.flatten(g);
+                }
+                Cfg::Loop(body) | Cfg::If(body) | Cfg::Repeat { body, .. } => body.visit_mut(r, g),
+            }
+        }
+        r.post(self, g);
+    }
+}
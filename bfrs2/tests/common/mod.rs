@@ -0,0 +1,48 @@
+//! Helpers shared by the integration tests.
+
+#![allow(dead_code)]
+
+use bfrs2::{cfg::Cfg, egraph::Graph};
+
+/// Parses the source, optimizes it, verifies it, and checks the pretty-printed
+/// result.
+pub fn test_optimize(src: &str, optimize: impl FnOnce(&mut Cfg, &mut Graph), expect: &str) {
+    let mut g = Graph::new();
+    let mut cfg = g.parse(src.as_bytes()).unwrap();
+    optimize(&mut cfg, &mut g);
+    cfg.verify(&g).unwrap();
+    g.verify().unwrap();
+    assert_eq!(cfg.pretty(&g), unindent(expect));
+}
+
+/// Parses the IR, optimizes it, verifies it, and checks the pretty-printed
+/// result.
+pub fn test_optimize_ir(ir: &str, optimize: impl FnOnce(&mut Cfg, &mut Graph), expect: &str) {
+    let mut g = Graph::new();
+    let mut cfg = g.parse_ir(&unindent(ir)).unwrap();
+    optimize(&mut cfg, &mut g);
+    cfg.verify(&g).unwrap();
+    g.verify().unwrap();
+    assert_eq!(cfg.pretty(&g), unindent(expect));
+}
+
+/// Removes the common leading indentation and surrounding blank lines.
+pub fn unindent(s: &str) -> String {
+    let lines = s
+        .lines()
+        .skip_while(|line| line.trim().is_empty())
+        .collect::<Vec<_>>();
+    let indent = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let mut unindented = String::new();
+    for line in lines {
+        unindented.push_str(line.get(indent..).unwrap_or("").trim_end());
+        unindented.push('\n');
+    }
+    unindented.truncate(unindented.trim_end().len() + 1);
+    unindented
+}
@@ -0,0 +1,312 @@
+//! Decimal I/O idioms, recognized in the examples for I/O and in the IR of
+//! bfrs1 for them.
+
+mod common;
+
+use std::fs;
+
+use bfrs2::{cfg::Cfg, egraph::Graph, optimize::Pipeline, pretty::Syntax};
+use common::{test_optimize, unindent};
+
+/// Reads an example program for I/O.
+fn read_io(name: &str) -> String {
+    fs::read_to_string(format!("../tests/third_party/esolang/algorithms/io/{name}")).unwrap()
+}
+
+/// Optimizes with decimal I/O recognition.
+fn optimize_decimal(cfg: &mut Cfg, g: &mut Graph) {
+    Pipeline::parse("closed-form,decimal-io")
+        .unwrap()
+        .run(cfg, g);
+}
+
+/// Parses the IR of bfrs1 for an example, recognizes decimal I/O in it,
+/// verifies it, and checks the pretty-printed result.
+fn test_golden(name: &str, expect: &str) {
+    let mut g = Graph::new();
+    let mut cfg = g.parse_ir_with(&read_io(name), Syntax::Bfrs1).unwrap();
+    Pipeline::parse("decimal-io").unwrap().run(&mut cfg, &mut g);
+    cfg.verify(&g).unwrap();
+    g.verify().unwrap();
+    assert_eq!(cfg.pretty(&g), unindent(expect));
+}
+
+#[test]
+fn decimal_output() {
+    test_optimize(
+        &read_io("print_decimal.b"),
+        optimize_decimal,
+        "
+            repeat p[0] times {
+                guard_shift(-1)
+                output([p[-1]])
+                let cn1 = p[-1]
+                let c0 = p[0]
+                p[-1] = cn1 - 4
+                p[0] = c0 - 1
+            }
+            guard_shift(1)
+            guard_shift(2)
+            output_decimal(p[0] + p[1] * 15 - 91)
+            guard_shift(7)
+            let c0 = p[0]
+            let c1 = p[1]
+            p[0] = c0 + c1 * 15 - 91
+            p[1] = 0
+            p[2] = 0
+            p += 1
+        ",
+    );
+    test_optimize(
+        &read_io("print_decimal_any_size1.b"),
+        optimize_decimal,
+        "
+            while p[0] != 0 {
+                let in0 = input()
+                output([in0, in0])
+                p[0] = in0
+                while p[0] != 0 {
+                    guard_shift(1)
+                    let c0 = p[0]
+                    let c1 = p[1]
+                    p[0] = c0 - 1
+                    p[1] = c1 - 1
+                    p += 1
+                    while p[0] != 0 {
+                        guard_shift(1)
+                        guard_shift(2)
+                        guard_shift(3)
+                        let c1 = p[1]
+                        p[1] = c1 + 1
+                        p += 3
+                    }
+                    guard_shift(1)
+                    p += 1
+                    while p[0] != 0 {
+                        if p[0] != 0 {
+                            guard_shift(-1)
+                            let cn1 = p[-1]
+                            let c0 = p[0]
+                            p[-1] = cn1 + c0
+                            p[0] = 0
+                        }
+                        guard_shift(1)
+                        guard_shift(2)
+                        guard_shift(3)
+                        let c0 = p[0]
+                        let c1 = p[1]
+                        p[0] = c0 + 1
+                        p[1] = c1 + 1
+                        p += 3
+                    }
+                    guard_shift(-1)
+                    guard_shift(-2)
+                    guard_shift(-3)
+                    guard_shift(-4)
+                    guard_shift(-5)
+                    p -= 5
+                }
+                output([p[0]])
+            }
+            output_decimal(p[0])
+            guard_shift(7)
+        ",
+    );
+    test_optimize(
+        &read_io("print_decimal_any_size2.b"),
+        optimize_decimal,
+        "
+            guard_shift(2)
+            output_decimal(p[2])
+            guard_shift(6)
+            p[2] = 0
+        ",
+    );
+    test_optimize(
+        ">>++++++++++<<[->+>-[>+>>]>[+[-<+>]>+>>]<<<<<<]>>[-]>>>++++++++++<[->-[>+>>]>[+[-<+>]>+>>]<<<<<]>[-]>>[>++++++[-<++++++++>]<.<<+>+>[-]]<[<[->-<]++++++[->++++++++<]>.[-]]<<++++++[-<++++++++>]<.[-]<<[-<+>]",
+        optimize_decimal,
+        "
+            output_decimal(p[0])
+            guard_shift(7)
+            p += 1
+        ",
+    );
+}
+
+#[test]
+fn decimal_output_golden() {
+    test_golden(
+        "print_decimal.ir",
+        "
+            guard_shift(1)
+            guard_shift(2)
+            output_decimal(p[0] + p[1] * 15 - 91)
+            guard_shift(7)
+            let c0 = p[0]
+            let c1 = p[1]
+            p[0] = c0 + c1 * 15 - 91
+            p[1] = 0
+            p[2] = 0
+            p += 1
+        ",
+    );
+    test_golden(
+        "print_decimal_any_size1.ir",
+        "
+            output_decimal(p[0])
+            guard_shift(7)
+        ",
+    );
+    test_golden(
+        "print_decimal_any_size2.ir",
+        "
+            guard_shift(2)
+            output_decimal(p[2])
+            guard_shift(6)
+            p[2] = 0
+        ",
+    );
+}
+
+#[test]
+fn decimal_input() {
+    test_optimize(
+        "[-]>[-]+[[-]>[-],[+[-----------[>[-]++++++[<------>-]<--<<[->>++++++++++<<]>>[-<<+>>]<+>]]]<]<",
+        optimize_decimal,
+        r#"
+            guard_shift(1)
+            p[0] = 0
+            p[1] = 1
+            p += 1
+            if p[0] != 0 {
+                guard_shift(1)
+                let in1 = input_decimal_until("\0\n\xff")
+                guard_shift(-1)
+                p[-1] = in1
+                p[0] = 0
+                p[1] = 0
+            }
+            guard_shift(-1)
+            p -= 1
+        "#,
+    );
+    test_optimize(
+        ",>++++++++[<------>-],>++++++++[<------>-],>++++++++[<------>-]<<[>>++++++++++<<-]<[>>>>++++++++++<<<<-]>>>>[>++++++++++<-]>[<<+>>-]<<<[>+<-]>[<<<+>>>-]",
+        optimize_decimal,
+        "
+            guard_shift(1)
+            guard_shift(2)
+            let in3 = input_decimal(3)
+            guard_shift(3)
+            guard_shift(4)
+            guard_shift(5)
+            p[0] = in3
+            p[1] = 0
+            p[2] = 0
+            p[3] = 0
+            p[4] = 0
+            p[5] = 0
+            p += 3
+        ",
+    );
+    // A digit stores to a cell, which is unknown on entry and not stored on
+    // exit, so the loop must not be replaced.
+    test_optimize(
+        ">>>,<<<[-]>[-]+[[-]>[-],[+[-----------[>[-]++++++[<------>-]<--<<[->>++++++++++<<]>>[-<<+>>]<+>]]]<]>>.",
+        |cfg, g| {
+            Pipeline::parse("closed-form,input-loop,decimal-io").unwrap().run(cfg, g);
+        },
+        "
+            guard_shift(1)
+            guard_shift(2)
+            guard_shift(3)
+            let in0 = input()
+            p[0] = 0
+            p[1] = 1
+            p[3] = in0
+            p += 1
+            while p[0] != 0 {
+                guard_shift(1)
+                let in1 = input()
+                p[0] = 0
+                p[1] = in1
+                p += 1
+                while p[0] != 0 {
+                    let c0 = p[0]
+                    p[0] = c0 + 1
+                    while p[0] != 0 {
+                        let c0 = p[0]
+                        p[0] = c0 - 11
+                        while p[0] != 0 {
+                            guard_shift(1)
+                            guard_shift(-1)
+                            guard_shift(-2)
+                            let cn2 = p[-2]
+                            let cn1 = p[-1]
+                            let c0 = p[0]
+                            p[-2] = cn2 * 10 + c0 - 38
+                            p[-1] = cn1 + 1
+                            p[0] = 0
+                            p[1] = 0
+                        }
+                    }
+                }
+                guard_shift(-1)
+                p -= 1
+            }
+            guard_shift(1)
+            guard_shift(2)
+            output([p[2]])
+            p += 2
+        ",
+    );
+}
+
+#[test]
+fn decimal_input_golden() {
+    test_golden(
+        "read_decimal1.ir",
+        r#"
+            guard_shift(1)
+            p[1] = 1
+            p += 1
+            if p[0] != 0 {
+                guard_shift(1)
+                let in1 = input_decimal_until("\0\n\xff")
+                guard_shift(-1)
+                p[-1] = in1
+                p[0] = 0
+                p[1] = 0
+            }
+            guard_shift(-1)
+            p -= 1
+        "#,
+    );
+    test_golden(
+        "read_decimal2.ir",
+        "
+            let in1 = input_decimal(1)
+            guard_shift(1)
+            p[0] = in1
+            p[1] = 0
+        ",
+    );
+    test_golden(
+        "read_decimal3.ir",
+        "
+            guard_shift(1)
+            guard_shift(2)
+            let in3 = input_decimal(3)
+            guard_shift(3)
+            guard_shift(4)
+            guard_shift(5)
+            p[0] = in3
+            p[1] = 0
+            p[2] = 0
+            p[3] = 0
+            p[4] = 0
+            p[5] = 0
+            p += 3
+        ",
+    );
+}
@@ -0,0 +1,41 @@
+use bfrs2::{
+    cfg::Cfg,
+    dot::Dot,
+    egraph::{Graph, Pass},
+};
+
+#[test]
+fn eclass_clusters() {
+    let mut g = Graph::new();
+    let mut cfg = g.parse(b"[-]++").unwrap();
+    Pass::AddLoopToMul.run(&mut cfg, &mut g);
+    let dot = g.dot();
+    assert!(dot.starts_with("digraph egraph {\n"));
+    assert!(dot.ends_with("}\n"));
+    assert!(dot.contains("subgraph cluster_n"));
+    assert!(dot.contains("peripheries=2"));
+    assert!(dot.contains(r##"pass_0 [label="parse", fillcolor="#e0e0e0"];"##));
+    assert!(dot.contains(r#"[label="closed-form""#));
+}
+
+#[test]
+fn reachable_from_cfg() {
+    let mut g = Graph::new();
+    g.parse(b"+++.").unwrap();
+    let cfg = g.parse(b",>+<").unwrap();
+    let Cfg::Block(block) = &cfg else {
+        panic!("not a block: {cfg:?}");
+    };
+    let all = g.dot();
+    let from_cfg = Dot::new(&g).reachable_from_cfg(&cfg).to_string();
+    assert_eq!(
+        from_cfg,
+        Dot::new(&g).reachable_from_block(block).to_string(),
+    );
+    assert!(all.contains(r#"label="3""#));
+    assert!(!from_cfg.contains(r#"label="3""#));
+    assert!(from_cfg.contains(r#"label="in0""#));
+    assert!(from_cfg.contains(r#"label="p[1] @b2""#));
+    let empty = Dot::new(&g).reachable_from_cfg(&Cfg::empty()).to_string();
+    assert!(!empty.contains(" -> "));
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use bfrs2::{egraph::Graph, optimize::Pipeline};
+use common::unindent;
+
+#[test]
+fn report() {
+    let mut g = Graph::new();
+    let mut cfg = g.parse(b"[-]++[.-]").unwrap();
+    let history = Pipeline::parse("closed-form,peel,copy-const")
+        .unwrap()
+        .run_with_history(&mut cfg, &mut g);
+    assert_eq!(history.steps.len(), 3);
+    assert_eq!(history.steps[2].pretty, cfg.pretty(&g));
+    assert!(history
+        .steps
+        .iter()
+        .all(|step| step.changed || step.unions.is_empty()));
+    assert_eq!(
+        history.report(&g),
+        unindent(
+            "
+            pass 1: closed-form
+            --- initial
+            +++ closed-form
+            @@ -1,10 +1,5 @@
+            -while p[0] != 0 {
+            -    let c0 = p[0]
+            -    p[0] = c0 - 1
+            -}
+            -let c0 = p[0]
+            -p[0] = c0 + 2
+            -while p[0] != 0 {
+            +p[0] = 2
+            +repeat p[0] times {
+                 output([p[0]])
+                 let c0 = p[0]
+                 p[0] = c0 - 1
+            unions:
+              n2 (p[0] - 1) into n11 (0)
+              n14 (loop) into n15 (block b1)
+              n18 (loop) into n19 (repeat b6)
+            pass 2: peel (unchanged)
+            pass 3: copy-const
+            --- closed-form
+            +++ copy-const
+            @@ -1,5 +1,5 @@
+             p[0] = 2
+            -repeat p[0] times {
+            +repeat 2 times {
+                 output([p[0]])
+                 let c0 = p[0]
+                 p[0] = c0 - 1
+            ",
+        ),
+    );
+}
@@ -0,0 +1,705 @@
+mod common;
+
+use bfrs2::{
+    block::{Block, Effect},
+    cfg::{Cfg, Seq},
+    egraph::{BoolNode, ByteNode, CtrlNode, EffectNode, Graph, InputNode, Pass, TypedId},
+    node::{Node, Offset},
+    optimize::Pipeline,
+    rewrite::{arithmetic_rules, Limits},
+    visit::{Rewriter, Visitor},
+};
+use common::{test_optimize, unindent};
+
+#[test]
+fn fixed_repetition_loops() {
+    test_optimize(
+        "[.-]",
+        |cfg, g| cfg.opt_closed_form_add(g),
+        "
+            repeat p[0] times {
+                output([p[0]])
+                let c0 = p[0]
+                p[0] = c0 - 1
+            }
+        ",
+    );
+    test_optimize(
+        "[+++++++++++++++.>++<]",
+        |cfg, g| cfg.opt_closed_form_add(g),
+        "
+            repeat p[0] * 17 times {
+                output([p[0] + 15])
+                guard_shift(1)
+                let c0 = p[0]
+                let c1 = p[1]
+                p[0] = c0 + 15
+                p[1] = c1 + 2
+            }
+        ",
+    );
+    test_optimize(
+        "[-]++++[.-]",
+        |cfg, g| {
+            cfg.opt_closed_form_add(g);
+            cfg.opt_copy_const(None, g);
+        },
+        "
+            p[0] = 4
+            repeat 4 times {
+                output([p[0]])
+                let c0 = p[0]
+                p[0] = c0 - 1
+            }
+        ",
+    );
+}
+
+#[test]
+fn peel_seq() {
+    test_optimize(
+        "[>[.-]>[-]<<-]",
+        |cfg, g| {
+            cfg.opt_closed_form_add(g);
+            cfg.opt_peel(g);
+        },
+        "
+            if p[0] != 0 {
+                guard_shift(1)
+                p += 1
+                repeat p[0] times {
+                    output([p[0]])
+                    let c0 = p[0]
+                    p[0] = c0 - 1
+                }
+                guard_shift(1)
+                guard_shift(-1)
+                let cn1 = p[-1]
+                p[-1] = cn1 - 1
+                p[1] = 0
+                p -= 1
+                while p[0] != 0 {
+                    guard_shift(1)
+                    p += 1
+                    repeat p[0] times {
+                        output([p[0]])
+                        let c0 = p[0]
+                        p[0] = c0 - 1
+                    }
+                    guard_shift(1)
+                    guard_shift(-1)
+                    let cn1 = p[-1]
+                    p[-1] = cn1 - 1
+                    p -= 1
+                }
+            }
+        ",
+    );
+}
+
+#[test]
+fn peel_seq_shifting_inner_loop() {
+    // The inner loop has a net shift, so stores can't be located.
+    test_optimize(
+        "[>[>]>[-]<<-]",
+        |cfg, g| {
+            cfg.opt_closed_form_add(g);
+            cfg.opt_peel(g);
+        },
+        "
+            while p[0] != 0 {
+                guard_shift(1)
+                p += 1
+                while p[0] != 0 {
+                    guard_shift(1)
+                    p += 1
+                }
+                guard_shift(1)
+                guard_shift(-1)
+                let cn1 = p[-1]
+                p[-1] = cn1 - 1
+                p[1] = 0
+                p -= 1
+            }
+        ",
+    );
+}
+
+#[test]
+fn pipeline_fixed_point() {
+    let mut g = Graph::new();
+    let mut cfg = g.parse(b"[>[.-]>[-]<<-]").unwrap();
+    let pipeline = Pipeline::parse("(closed-form,peel)*").unwrap();
+    assert!(pipeline.run(&mut cfg, &mut g));
+    let optimized = cfg.clone();
+    assert!(!pipeline.run(&mut cfg, &mut g));
+    assert!(cfg == optimized);
+}
+
+#[test]
+fn retain_versions() {
+    let mut g = Graph::new();
+    let mut cfg = g.parse(b"[>[.-]>[-]<<-]").unwrap();
+    let parsed = cfg.clone();
+    assert!(Pass::AddLoopToMul.run(&mut cfg, &mut g));
+    let closed_form = cfg.clone();
+    assert!(Pass::QuasiInvariantPeel.run(&mut cfg, &mut g));
+    assert!(!Pass::QuasiInvariantPeel.run(&mut cfg, &mut g));
+
+    // Only the rewritten loops are inserted, so the inner loop, which was
+    // converted to a counted loop, has two versions.
+    let inner = |cfg: &Cfg| {
+        let (Cfg::Loop(body) | Cfg::If(body)) = cfg else {
+            panic!("not a loop: {cfg:?}");
+        };
+        let Cfg::Seq(seq) = &**body else {
+            panic!("not a sequence: {body:?}");
+        };
+        seq[1].clone()
+    };
+    let len = g.len();
+    let counted = inner(&parsed).insert(&mut g);
+    assert_eq!(g.len(), len);
+    let versions = g.versions(counted);
+    assert_eq!(versions.len(), 2);
+    assert!(Cfg::from_node(versions[0], &g) == inner(&parsed));
+    assert!(Cfg::from_node(versions[1], &g) == inner(&closed_form));
+    assert_eq!(g.entry(versions[1]).creator(), Pass::AddLoopToMul);
+
+    // The outer loop was peeled as a whole.
+    let peeled = closed_form.insert(&mut g);
+    assert_eq!(g.len(), len);
+    let versions = g.versions(peeled);
+    assert_eq!(versions.len(), 2);
+    assert_eq!(g.canonical(peeled), cfg.insert(&mut g));
+    assert!(Cfg::from_node(versions[1], &g) == cfg);
+    assert_eq!(g.entry(versions[1]).creator(), Pass::QuasiInvariantPeel);
+}
+
+#[test]
+fn unroll_constant() {
+    test_optimize(
+        "[-]+++[.-]",
+        |cfg, g| {
+            Pipeline::parse("(closed-form,copy-const,unroll)*")
+                .unwrap()
+                .run(cfg, g);
+        },
+        r#"
+            output("\x03\x02\x01")
+            p[0] = 0
+        "#,
+    );
+    test_optimize(
+        "[-]++++[>,.<-]",
+        |cfg, g| {
+            Pipeline::parse("(closed-form,copy-const,unroll)*")
+                .unwrap()
+                .run(cfg, g);
+        },
+        "
+            guard_shift(1)
+            let in0 = input()
+            output([in0])
+            let in1 = input()
+            output([in1])
+            let in2 = input()
+            output([in2])
+            let in3 = input()
+            output([in3])
+            p[0] = 0
+            p[1] = in3
+        ",
+    );
+}
+
+#[test]
+fn unroll_partial() {
+    test_optimize(
+        "[-]++++++++++++++++++++++[>.<-]",
+        |cfg, g| {
+            cfg.opt_closed_form_add(g);
+            cfg.opt_copy_const(None, g);
+            cfg.opt_unroll(None, g);
+        },
+        "
+            p[0] = 22
+            repeat 5 times {
+                guard_shift(1)
+                output([p[1], p[1], p[1], p[1]])
+                let c0 = p[0]
+                p[0] = c0 - 4
+            }
+            guard_shift(1)
+            output([p[1], p[1]])
+            let c0 = p[0]
+            p[0] = c0 - 2
+        ",
+    );
+}
+
+#[test]
+fn dead_store_overwritten() {
+    test_optimize(
+        ">>[-]<<[.-]>>,.",
+        |cfg, g| {
+            cfg.opt_closed_form_add(g);
+            cfg.opt_dead_store(g);
+        },
+        "
+            guard_shift(1)
+            guard_shift(2)
+            repeat p[0] times {
+                output([p[0]])
+                let c0 = p[0]
+                p[0] = c0 - 1
+            }
+            guard_shift(1)
+            guard_shift(2)
+            let in0 = input()
+            output([in0])
+            p += 2
+        ",
+    );
+}
+
+#[test]
+fn dead_store_counter() {
+    test_optimize(
+        "[>.<-]>[-]<",
+        |cfg, g| {
+            cfg.opt_closed_form_add(g);
+            cfg.opt_dead_store(g);
+        },
+        "
+            repeat p[0] times {
+                guard_shift(1)
+                output([p[1]])
+            }
+            guard_shift(1)
+        ",
+    );
+}
+
+#[test]
+fn redundant_loops() {
+    test_optimize(
+        "[->+<][->+<]",
+        |cfg, g| cfg.opt_fuse(g),
+        "
+            while p[0] != 0 {
+                guard_shift(1)
+                let c0 = p[0]
+                let c1 = p[1]
+                p[0] = c0 - 1
+                p[1] = c1 + 1
+            }
+        ",
+    );
+    test_optimize(
+        "[->+<][->>+<<]",
+        |cfg, g| {
+            cfg.opt_closed_form_add(g);
+            cfg.opt_fuse(g);
+        },
+        "
+            if p[0] != 0 {
+                guard_shift(1)
+                let c0 = p[0]
+                let c1 = p[1]
+                p[0] = 0
+                p[1] = c0 + c1
+            }
+        ",
+    );
+    test_optimize(
+        "[.-][.-]",
+        |cfg, g| {
+            cfg.opt_closed_form_add(g);
+            cfg.opt_fuse(g);
+        },
+        "
+            repeat p[0] times {
+                output([p[0]])
+                let c0 = p[0]
+                p[0] = c0 - 1
+            }
+        ",
+    );
+}
+
+#[test]
+fn fuse_closed_form_loops() {
+    let mut g = Graph::new();
+    let mut cfg = g.parse(b"[->+<][->>+<<]").unwrap();
+    Pipeline::parse("closed-form,fuse")
+        .unwrap()
+        .run(&mut cfg, &mut g);
+    // The second loop never runs, so the loops share one block.
+    let Cfg::If(body) = &cfg else {
+        panic!("expected one if");
+    };
+    assert!(matches!(**body, Cfg::Block(_)));
+    assert_eq!(
+        cfg.pretty(&g),
+        unindent(
+            "
+                if p[0] != 0 {
+                    guard_shift(1)
+                    let c0 = p[0]
+                    let c1 = p[1]
+                    p[0] = 0
+                    p[1] = c0 + c1
+                }
+            ",
+        ),
+    );
+}
+
+#[test]
+fn fuse_ifs() {
+    let mut g = Graph::new();
+    let then1 = g.parse(b">+<").unwrap();
+    let then2 = g.parse(b">>.<<").unwrap();
+    let mut cfg =
+        Seq::from_iter([Cfg::If(Box::new(then1)), Cfg::If(Box::new(then2))], &mut g).into_cfg();
+    cfg.opt_fuse(&mut g);
+    assert_eq!(
+        cfg.pretty(&g),
+        unindent(
+            "
+                if p[0] != 0 {
+                    guard_shift(1)
+                    guard_shift(2)
+                    output([p[2]])
+                    let c1 = p[1]
+                    p[1] = c1 + 1
+                }
+            ",
+        ),
+    );
+}
+
+#[test]
+fn facts_boolean_loops() {
+    test_optimize(
+        ">[-]<[>+<[-]]>[.-]",
+        |cfg, g| {
+            cfg.opt_closed_form_add(g);
+            cfg.opt_facts(g);
+        },
+        "
+            guard_shift(1)
+            p[1] = 0
+            if p[0] != 0 {
+                guard_shift(1)
+                p[0] = 0
+                p[1] = 1
+            }
+            guard_shift(1)
+            p += 1
+            if p[0] != 0 {
+                output(\"\\x01\")
+                p[0] = 0
+            }
+        ",
+    );
+    test_optimize(
+        "[-]>[-]<[-]+[>+<-]>[<.>-]",
+        |cfg, g| {
+            cfg.opt_closed_form_add(g);
+            cfg.opt_facts(g);
+        },
+        "
+            guard_shift(1)
+            output(\"\\0\")
+            p[0] = 0
+            p[1] = 0
+            p += 1
+        ",
+    );
+}
+
+#[test]
+fn coalesce_output() {
+    let mut g = Graph::new();
+    let src = format!("[-]{}.[-]{}.", "+".repeat(72), "+".repeat(105));
+    let mut cfg = g.parse(src.as_bytes()).unwrap();
+    cfg.opt_closed_form_add(&mut g);
+    cfg.opt_coalesce_output(&mut g);
+    let Cfg::Block(block) = &cfg else {
+        panic!("not a block: {cfg:?}");
+    };
+    assert_eq!(block.effects(&g), [Effect::OutputBytes(b"Hi".to_vec())]);
+
+    test_optimize(
+        "[-]+.>.<.+.",
+        |cfg, g| {
+            cfg.opt_closed_form_add(g);
+            cfg.opt_coalesce_output(g);
+        },
+        r#"
+            output("\x01")
+            guard_shift(1)
+            output([p[1]])
+            output("\x01\x02")
+            p[0] = 2
+        "#,
+    );
+}
+
+#[test]
+fn input_loops() {
+    test_optimize(
+        ",[>,]",
+        |cfg, g| cfg.opt_input_loop(g),
+        "
+            let in0 = input()
+            p[0] = in0
+            input_cells(step: 1, until: '\\0')
+        ",
+    );
+    test_optimize(
+        "----------[++++++++++>,----------]",
+        |cfg, g| cfg.opt_input_loop(g),
+        "
+            let c0 = p[0]
+            p[0] = c0 - 10
+            input_cells(step: 1, until: '\\n')
+        ",
+    );
+    test_optimize(
+        ",----------[,----------]",
+        |cfg, g| cfg.opt_input_loop(g),
+        r#"
+            let in0 = input()
+            p[0] = in0 - 10
+            if p[0] != 0 {
+                let in2 = input_until("\n")
+                p[0] = 0
+            }
+        "#,
+    );
+    test_optimize(
+        "+[>[-]<,----------[---[>+<[-]]]>[<+>-]<]",
+        |cfg, g| {
+            cfg.opt_closed_form_add(g);
+            cfg.opt_input_loop(g);
+        },
+        r#"
+            let c0 = p[0]
+            p[0] = c0 + 1
+            if p[0] != 0 {
+                guard_shift(1)
+                let in1 = input_until("\n\r")
+                p[0] = 0
+                p[1] = 0
+            }
+        "#,
+    );
+}
+
+#[test]
+fn reorder_effects() {
+    test_optimize(
+        "[-]+.>[-]++.>,.<<.",
+        |cfg, g| {
+            Pipeline::parse("closed-form,copy-const,reorder-effects")
+                .unwrap()
+                .run(cfg, g);
+        },
+        r#"
+            output("\x01\x02")
+            guard_shift(1)
+            guard_shift(2)
+            let in0 = input()
+            output([in0, '\x01'])
+            p[0] = 1
+            p[1] = 2
+            p[2] = in0
+        "#,
+    );
+}
+
+#[test]
+fn compact() {
+    let mut g = Graph::new();
+    let mut cfg = g.parse(b"+[>[.-]>[-]<<-]>,[>+<-]").unwrap();
+    let pipeline = Pipeline::parse("(closed-form,peel,facts)*").unwrap();
+    pipeline.run(&mut cfg, &mut g);
+    let pretty = cfg.pretty(&g);
+    let len = g.len();
+
+    let removed = g.compact(&mut cfg, false);
+    assert!(removed > 0);
+    assert_eq!(g.len(), len - removed);
+    assert_eq!(cfg.pretty(&g), pretty);
+    cfg.verify(&g).unwrap();
+    g.verify().unwrap();
+    for id in g.ids() {
+        assert_eq!(g.find(&g[id]), Some(id));
+        assert!(g[id]
+            .operands()
+            .iter()
+            .all(|operand| operand.index() < id.index()));
+    }
+    assert_eq!(g.compact(&mut cfg, false), 0);
+
+    // The compacted graph can still be optimized.
+    let mut g2 = Graph::new();
+    let mut cfg2 = g2.parse(b"+[>[.-]>[-]<<-]>,[>+<-]").unwrap();
+    pipeline.run(&mut cfg2, &mut g2);
+    Pipeline::parse("copy-const,dead-store")
+        .unwrap()
+        .run(&mut cfg2, &mut g2);
+    Pipeline::parse("copy-const,dead-store")
+        .unwrap()
+        .run(&mut cfg, &mut g);
+    assert_eq!(cfg.pretty(&g), cfg2.pretty(&g2));
+}
+
+#[test]
+fn compact_versions() {
+    let mut g = Graph::new();
+    let mut cfg = g.parse(b"+[>[.-]>[-]<<-]>,[>+<-]").unwrap();
+    Pipeline::parse("(closed-form,peel,facts)*")
+        .unwrap()
+        .run(&mut cfg, &mut g);
+    let versions = |g: &Graph| {
+        g.ids()
+            .filter(|&id| g[id].is_ctrl() && g.versions(id)[0] == id && g.versions(id).len() > 1)
+            .map(|id| {
+                (g.versions(id).iter())
+                    .map(|&version| Cfg::from_node(CtrlNode::new(version, g).unwrap(), g).pretty(g))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>()
+    };
+    let before = versions(&g);
+    assert!(!before.is_empty());
+    let pretty = cfg.pretty(&g);
+
+    // The versions of loops are kept with the nodes they use.
+    assert!(g.compact(&mut cfg, true) > 0);
+    g.verify().unwrap();
+    assert_eq!(cfg.pretty(&g), pretty);
+    assert_eq!(versions(&g), before);
+    assert_eq!(g.compact(&mut cfg, true), 0);
+
+    // Otherwise, they are removed.
+    assert!(g.compact(&mut cfg, false) > 0);
+    g.verify().unwrap();
+    assert_eq!(cfg.pretty(&g), pretty);
+    assert!(g.ids().all(|id| !g[id].is_ctrl()));
+}
+
+#[test]
+fn compact_equalities() {
+    let mut g = Graph::new();
+    let mut cfg = g.parse_ir("p[1] = p[0] * (p[1] + 1)\n").unwrap();
+    g.saturate(&arithmetic_rules(), Limits::default());
+    let pretty = cfg.pretty(&g);
+    assert!(g.ids().any(|id| g.leader(id) != id));
+
+    // Only one node of each equality class is reachable, so the classes of
+    // the remaining nodes are singletons.
+    assert!(g.compact(&mut cfg, false) > 0);
+    g.verify().unwrap();
+    assert_eq!(cfg.pretty(&g), pretty);
+    for id in g.ids() {
+        assert_eq!(g.leader(id), id);
+        assert_eq!(g.equal_nodes(id), [id]);
+    }
+}
+
+#[test]
+fn visit() {
+    struct Order(Vec<String>);
+    impl Visitor for Order {
+        fn pre(&mut self, cfg: &Cfg, _g: &Graph) -> bool {
+            self.0.push(format!("pre {}", kind(cfg)));
+            !matches!(cfg, Cfg::If(_))
+        }
+        fn post(&mut self, cfg: &Cfg, _g: &Graph) {
+            self.0.push(format!("post {}", kind(cfg)));
+        }
+    }
+    fn kind(cfg: &Cfg) -> &'static str {
+        match cfg {
+            Cfg::Block(_) => "block",
+            Cfg::Seq(_) => "seq",
+            Cfg::Loop(_) => "loop",
+            Cfg::If(_) => "if",
+            Cfg::Repeat { .. } => "repeat",
+            Cfg::InputCells { .. } => "input_cells",
+        }
+    }
+
+    let mut g = Graph::new();
+    let cfg = g.parse(b"+[>[-]<-]").unwrap();
+    let mut order = Order(Vec::new());
+    cfg.visit(&mut order, &g);
+    assert_eq!(
+        order.0,
+        [
+            "pre seq",
+            "pre block",
+            "post block",
+            "pre loop",
+            "pre seq",
+            "pre block",
+            "post block",
+            "pre loop",
+            "pre block",
+            "post block",
+            "post loop",
+            "pre block",
+            "post block",
+            "post seq",
+            "post loop",
+            "post seq",
+        ],
+    );
+
+    // Removing loops leaves adjacent blocks, which are merged.
+    struct RemoveLoops;
+    impl Rewriter for RemoveLoops {
+        fn pre(&mut self, cfg: &mut Cfg, g: &mut Graph) -> bool {
+            if let Cfg::Loop(_) = cfg {
+                *cfg = Cfg::Block(Block::new(g.fresh_block_id()));
+                return false;
+            }
+            true
+        }
+    }
+    let mut cfg = g.parse(b"+[-]>+[>]<").unwrap();
+    cfg.visit_mut(&mut RemoveLoops, &mut g);
+    assert_eq!(cfg.pretty(&g), g.parse(b"+>+<").unwrap().pretty(&g));
+}
+
+#[test]
+fn typed_ids() {
+    let mut g = Graph::new();
+    let cfg = g.parse(b",[->+<]").unwrap();
+    let ctrl = cfg.insert(&mut g);
+    let input = g.fresh_input();
+    let byte = Node::Const(1).insert(&mut g);
+    assert_eq!(CtrlNode::new(ctrl.id(), &g), Some(ctrl));
+    assert_eq!(ByteNode::new(ctrl.id(), &g), None);
+    assert_eq!(ByteNode::new(input.id(), &g), Some(input.byte()));
+    assert_eq!(InputNode::new(input.id(), &g), Some(input));
+    assert_eq!(InputNode::new(byte.id(), &g), None);
+    assert_eq!(EffectNode::new(byte.id(), &g), None);
+    let cond = g
+        .ids()
+        .find_map(|id| match g[id] {
+            Node::Loop(cond, _) => Some(cond),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(g[cond], Node::NonZero(Offset(0)));
+    assert_eq!(BoolNode::new(cond.id(), &g), Some(cond));
+    assert_eq!(BoolNode::new(byte.id(), &g), None);
+    assert_eq!(CtrlNode::new(cond.id(), &g), None);
+    assert_eq!(Cfg::from_node(ctrl, &g), cfg);
+}
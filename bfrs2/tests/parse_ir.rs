@@ -0,0 +1,168 @@
+mod common;
+
+use std::{fs, path::Path};
+
+use bfrs2::{
+    egraph::Graph,
+    optimize::Pipeline,
+    parse_ir::{IrError, IrErrorKind},
+    pretty::{PrettyOptions, Syntax},
+};
+use common::{test_optimize_ir, unindent};
+
+/// Parses the printed IR of a program and checks that it prints the same.
+fn round_trip(src: &[u8], pipeline: &Pipeline) -> String {
+    let mut g = Graph::new();
+    let mut cfg = g.parse(src).unwrap();
+    pipeline.run(&mut cfg, &mut g);
+    let ir = cfg.pretty(&g);
+    let mut g2 = Graph::new();
+    let cfg2 = g2.parse_ir(&ir).unwrap();
+    cfg2.verify(&g2).unwrap();
+    assert_eq!(cfg2.pretty(&g2), ir);
+    ir
+}
+
+/// Parses the IR of a program printed in the syntax of bfrs1 and checks that it
+/// prints the same.
+fn round_trip_bfrs1(src: &[u8], pipeline: &Pipeline) {
+    let options = PrettyOptions {
+        syntax: Syntax::Bfrs1,
+        ..PrettyOptions::default()
+    };
+    let mut g = Graph::new();
+    let mut cfg = g.parse(src).unwrap();
+    pipeline.run(&mut cfg, &mut g);
+    let ir = cfg.pretty_with(&g, &options);
+    let mut g2 = Graph::new();
+    let cfg2 = g2.parse_ir_with(&ir, Syntax::Bfrs1).unwrap();
+    cfg2.verify(&g2).unwrap();
+    assert_eq!(cfg2.pretty_with(&g2, &options), ir);
+}
+
+fn visit_programs(dir: &Path, each: &mut impl FnMut(&Path)) {
+    let mut entries = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            visit_programs(&path, each);
+        } else if path.extension().is_some_and(|ext| ext == "b") {
+            each(&path);
+        }
+    }
+}
+
+#[test]
+fn round_trip_programs() {
+    let pipelines = [Pipeline::parse("closed-form").unwrap(), Pipeline::default()];
+    visit_programs(Path::new("../tests"), &mut |path| {
+        let src = fs::read(path).unwrap();
+        for pipeline in &pipelines {
+            round_trip(&src, pipeline);
+            round_trip_bfrs1(&src, pipeline);
+        }
+    });
+}
+
+#[test]
+fn parse_bfrs1_ir() {
+    // The `.ir` files of the tests are printed by bfrs1.
+    visit_programs(Path::new("../tests"), &mut |path| {
+        let Ok(ir) = fs::read_to_string(path.with_extension("ir")) else {
+            return;
+        };
+        let mut g = Graph::new();
+        let cfg = g.parse_ir_with(&ir, Syntax::Bfrs1).unwrap();
+        cfg.verify(&g).unwrap();
+    });
+}
+
+#[test]
+fn identity_stores() {
+    // A store of a cell's own value is not printed, and neither is the copy,
+    // which only it uses.
+    let mut g = Graph::new();
+    let cfg = g
+        .parse_ir("let c0 = p[0]\nlet c1 = p[1]\np[0] = c0\np[1] = c1 + 1\np[2] = p[2]\n")
+        .unwrap();
+    let ir = cfg.pretty(&g);
+    assert_eq!(ir, "let c1 = p[1]\np[1] = c1 + 1\n");
+    let mut g2 = Graph::new();
+    let cfg2 = g2.parse_ir(&ir).unwrap();
+    assert_eq!(cfg2.pretty(&g2), ir);
+}
+
+#[test]
+fn optimize_ir() {
+    test_optimize_ir(
+        "
+            while p[0] != 0 { guard_shift(1); let c0 = p[0]; let c1 = p[1]; p[0] = c0 - 1; p[1] = c1 + 3 }
+        ",
+        |cfg, g| cfg.opt_closed_form_add(g),
+        "
+            if p[0] != 0 {
+                guard_shift(1)
+                let c0 = p[0]
+                let c1 = p[1]
+                p[0] = 0
+                p[1] = c0 * 3 + c1
+            }
+        ",
+    );
+    test_optimize_ir(
+        r#"
+            // Effects and stores read the cells on entry.
+            p[0] = 2
+            output(['a', p[0]])
+            repeat p[0] * 2 times {
+                guard_shift(-1)
+                output("\"'")
+                p -= 1
+            }
+            input_cells(step: 1, until: '\n')
+        "#,
+        |_, _| {},
+        r#"
+            output(['a', p[0]])
+            p[0] = 2
+            repeat p[0] * 2 times {
+                guard_shift(-1)
+                output("\"'")
+                p -= 1
+            }
+            input_cells(step: 1, until: '\n')
+        "#,
+    );
+}
+
+#[test]
+fn parse_errors() {
+    let err = |src| Graph::new().parse_ir(src).unwrap_err();
+    let kind = |line, kind| IrError { line, kind };
+    assert_eq!(
+        err("p[0] = c0"),
+        kind(1, IrErrorKind::UnboundName("c0".into())),
+    );
+    assert_eq!(
+        err("p[0] = 1\nwhile p[0] != 0 {\n"),
+        kind(2, IrErrorKind::UnclosedBrace),
+    );
+    assert_eq!(err("}"), kind(1, IrErrorKind::UnopenedBrace));
+    assert_eq!(
+        err("p[0] = 256"),
+        kind(1, IrErrorKind::ConstOutOfRange("256".into())),
+    );
+    assert_eq!(err("output(\"\\q\")"), kind(1, IrErrorKind::BadEscape),);
+    assert_eq!(
+        err("p[0] = 1 p[1] = 2"),
+        kind(1, IrErrorKind::UnexpectedToken("p".into())),
+    );
+    assert_eq!(err("p += 1 $"), kind(1, IrErrorKind::UnexpectedChar('$')));
+    assert_eq!(
+        unindent(&err("output(").to_string()),
+        "line 1: unexpected end of IR\n",
+    );
+}
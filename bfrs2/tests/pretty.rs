@@ -0,0 +1,103 @@
+mod common;
+
+use bfrs2::{
+    egraph::Graph,
+    pretty::{PrettyOptions, Syntax},
+};
+use common::unindent;
+
+#[test]
+fn bfrs1_syntax() {
+    let mut g = Graph::new();
+    let cfg = g.parse(b"+[->+<]>.,").unwrap();
+    let options = PrettyOptions {
+        syntax: Syntax::Bfrs1,
+        ..PrettyOptions::default()
+    };
+    assert_eq!(
+        cfg.pretty_with(&g, &options),
+        unindent(
+            "
+                {
+                    @0 = @0 + 1
+                }
+                while @0 != 0 {
+                    guard_shift 1
+                    @0 = @0 - 1
+                    @1 = @1 + 1
+                }
+                {
+                    guard_shift 1
+                    output @1
+                    in0 = input
+                    @1 = in0
+                    shift 1
+                }
+            ",
+        ),
+    );
+}
+
+#[test]
+fn annotations() {
+    let mut g = Graph::new();
+    let cfg = g.parse(b"+[->+<]>.,").unwrap();
+    let options = PrettyOptions {
+        guards: false,
+        block_ids: true,
+        node_ids: true,
+        ..PrettyOptions::default()
+    };
+    let annotated = cfg.pretty_with(&g, &options);
+    assert_eq!(
+        annotated,
+        unindent(
+            "
+                // b0
+                let c0 = p[0]
+                p[0] = c0 + 1 // n2
+                while p[0] != 0 {
+                    // b2
+                    let c0 = p[0]
+                    let c1 = p[1]
+                    p[0] = c0 - 1 // n5
+                    p[1] = c1 + 1 // n7
+                }
+                // b1
+                output([p[1]]) // n10
+                let in0 = input() // n11
+                p[1] = in0 // n11
+                p += 1
+            ",
+        ),
+    );
+
+    // Annotations are comments in the IR.
+    let mut g2 = Graph::new();
+    let cfg2 = g2.parse_ir(&annotated).unwrap();
+    let options = PrettyOptions {
+        guards: false,
+        ..PrettyOptions::default()
+    };
+    assert_eq!(cfg2.pretty(&g2), cfg.pretty_with(&g, &options));
+}
+
+#[test]
+fn color() {
+    let mut g = Graph::new();
+    let cfg = g
+        .parse_ir("while p[0] != 0 {\n    p[0] = p[0] - 1\n}\noutput(\"hi\")\n")
+        .unwrap();
+    let options = PrettyOptions {
+        color: true,
+        ..PrettyOptions::default()
+    };
+    assert_eq!(
+        cfg.pretty_with(&g, &options),
+        "\x1b[1;35mwhile\x1b[0m p[0] != 0 {\n    \
+         \x1b[1;35mlet\x1b[0m c0 = p[0]\n    \
+         p[0] = c0 - \x1b[33m1\x1b[0m\n\
+         }\n\
+         \x1b[36moutput\x1b[0m(\x1b[33m\"hi\"\x1b[0m)\n",
+    );
+}
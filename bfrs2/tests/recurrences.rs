@@ -0,0 +1,56 @@
+//! Closed forms of loops, which need guards to be outlined. This sets global
+//! state, so these tests are in their own binary.
+
+mod common;
+
+use bfrs2::optimize::{unsound_outline_guards, Pipeline};
+use common::test_optimize;
+
+#[test]
+fn triangular_and_geometric() {
+    unsound_outline_guards(true);
+    test_optimize(
+        ">[-]>[-]<<[[->+>+<<]>>[-<<+>>]<<-]",
+        |cfg, g| {
+            Pipeline::parse("(closed-form,peel,copy-const)*")
+                .unwrap()
+                .run(cfg, g);
+        },
+        "
+            guard_shift(1)
+            guard_shift(2)
+            p[1] = 0
+            p[2] = 0
+            if p[0] != 0 {
+                guard_shift(1)
+                guard_shift(2)
+                let c0 = p[0]
+                p[0] = 0
+                p[1] = c0 * -1 + c0 * c0 - choose(c0 - 1, 2) + 1
+                p[2] = 0
+            }
+        ",
+    );
+    test_optimize(
+        ">>[-]<<[->[->+<]>[-<++>]<<]",
+        |cfg, g| {
+            Pipeline::parse("(closed-form,peel,copy-const)*")
+                .unwrap()
+                .run(cfg, g);
+        },
+        "
+            guard_shift(1)
+            guard_shift(2)
+            p[2] = 0
+            if p[0] != 0 {
+                guard_shift(1)
+                guard_shift(2)
+                let c0 = p[0]
+                let c1 = p[1]
+                p[0] = 0
+                p[1] = c1 * pow(2, c0 - 1) * 2
+                p[2] = 0
+            }
+        ",
+    );
+}
@@ -0,0 +1,136 @@
+use std::{fs, path::Path};
+
+use bfrs2::{
+    cfg::Cfg,
+    egraph::Graph,
+    optimize::Pipeline,
+    rewrite::{arithmetic_rules, Limits},
+    serialize::{DeserializeError, MAGIC},
+    verify::VerifyError,
+};
+
+/// Checks that a loaded e-graph and CFG are identical to the originals.
+fn assert_same(g: &Graph, cfg: &Cfg, g2: &Graph, cfg2: &Cfg) {
+    assert!(cfg2 == cfg);
+    assert_eq!(cfg2.pretty(g2), cfg.pretty(g));
+    assert_eq!(g2.len(), g.len());
+    assert_eq!(g2.eclasses(), g.eclasses());
+    assert_eq!(g2.pass(), g.pass());
+    for id in g.ids() {
+        assert_eq!(g2[id], g[id]);
+        assert_eq!(g2.find(&g[id]), Some(id));
+        assert_eq!(g2.canonical(id), g.canonical(id));
+        assert_eq!(g2.versions(id), g.versions(id));
+        assert_eq!(g2.leader(id), g.leader(id));
+        assert_eq!(g2.entry(id).creator(), g.entry(id).creator());
+        assert_eq!(g2.facts(id), g.facts(id));
+    }
+}
+
+fn round_trip(src: &[u8], pipeline: &Pipeline) {
+    let mut g = Graph::new();
+    let mut cfg = g.parse(src).unwrap();
+    pipeline.run(&mut cfg, &mut g);
+
+    let (mut g2, cfg2) = Graph::deserialize(&g.serialize(&cfg)).unwrap();
+    assert_same(&g, &cfg, &g2, &cfg2);
+    let (mut g3, cfg3) = Graph::deserialize_json(&g.serialize_json(&cfg)).unwrap();
+    assert_same(&g, &cfg, &g3, &cfg3);
+
+    // Fresh IDs continue from the original counters.
+    let block = g.fresh_block_id();
+    let input = g.fresh_input();
+    for g2 in [&mut g2, &mut g3] {
+        assert_eq!(g2.fresh_block_id(), block);
+        let input2 = g2.fresh_input();
+        assert_eq!(g2[input2], g[input]);
+    }
+}
+
+#[test]
+fn round_trip_programs() {
+    let pipelines = [Pipeline::parse("closed-form").unwrap(), Pipeline::default()];
+    for name in ["move_right.b", "mul.b", "sudoku_start.b"] {
+        let src = fs::read(Path::new("../tests").join(name)).unwrap();
+        for pipeline in &pipelines {
+            round_trip(&src, pipeline);
+        }
+    }
+    round_trip(b",[>,]<[-]+++++[.-]>>[-]<<[>+<-]", &Pipeline::default());
+}
+
+#[test]
+fn round_trip_equalities() {
+    let mut g = Graph::new();
+    let mut cfg = g.parse(b"+++[->++<]>.").unwrap();
+    Pipeline::default().run(&mut cfg, &mut g);
+    g.saturate(&arithmetic_rules(), Limits::default());
+    assert!(g.ids().any(|id| g.leader(id) != id));
+
+    let (g2, cfg2) = Graph::deserialize(&g.serialize(&cfg)).unwrap();
+    assert_same(&g, &cfg, &g2, &cfg2);
+    let (g3, cfg3) = Graph::deserialize_json(&g.serialize_json(&cfg)).unwrap();
+    assert_same(&g, &cfg, &g3, &cfg3);
+}
+
+#[test]
+fn json_format() {
+    let mut g = Graph::new();
+    let cfg = g.parse(b"+.").unwrap();
+    let json = g.serialize_json(&cfg);
+    assert!(json.starts_with("{\n  \"version\": 3,\n  \"graph\": {\n"));
+    assert!(json.contains(r#"{"kind": "const", "value": 1}"#));
+    assert!(json.contains(r#""creator": "parse""#));
+}
+
+#[test]
+fn deserialize_errors() {
+    let mut g = Graph::new();
+    let cfg = g.parse(b"+[->+<]").unwrap();
+    let bytes = g.serialize(&cfg);
+    assert_eq!(
+        Graph::deserialize(b"bfrs").unwrap_err(),
+        DeserializeError::BadMagic,
+    );
+    assert!(matches!(
+        Graph::deserialize(&bytes[..bytes.len() - 1]).unwrap_err(),
+        DeserializeError::Syntax(_),
+    ));
+    let mut future = MAGIC.to_vec();
+    future.extend_from_slice(&[4, 1, 1, 8]);
+    assert_eq!(
+        Graph::deserialize(&future).unwrap_err(),
+        DeserializeError::UnsupportedVersion(4),
+    );
+
+    let json = g.serialize_json(&cfg);
+    assert_eq!(
+        Graph::deserialize_json(&json.replacen("\"parse\"", "\"lex\"", 1)).unwrap_err(),
+        DeserializeError::UnknownPass("lex".into()),
+    );
+    assert_eq!(
+        Graph::deserialize_json(&json.replacen("\"lhs\": 0", "\"lhs\": 99", 1)).unwrap_err(),
+        DeserializeError::BadNodeId(99),
+    );
+    assert_eq!(
+        Graph::deserialize_json(&json.replacen("\"version\": 3,", "", 1)).unwrap_err(),
+        DeserializeError::MissingField("version"),
+    );
+
+    // The loop body copies from another block.
+    assert!(matches!(
+        Graph::deserialize_json(&json.replacen("\"id\": 2,", "\"id\": 0,", 1)).unwrap_err(),
+        DeserializeError::Invalid(VerifyError::ForeignCopy { .. }),
+    ));
+
+    let mut g = Graph::new();
+    let cfg = g.parse(b",+.").unwrap();
+    let json = g.serialize_json(&cfg);
+    assert_eq!(
+        Graph::deserialize_json(&json.replacen("\"input\": 0}", "\"input\": 1}", 1)).unwrap_err(),
+        DeserializeError::WrongKind {
+            id: 1,
+            expected: "input",
+        },
+    );
+}
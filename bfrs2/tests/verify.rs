@@ -0,0 +1,136 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use bfrs2::{
+    block::Effect,
+    cfg::{Cfg, Seq},
+    egraph::{Graph, Pass},
+    node::{BlockId, Node, Offset},
+    optimize::Pipeline,
+    verify::VerifyError,
+};
+
+/// Optimizes the source with the pipeline, verifying after each pass, and
+/// returns the pretty-printed result.
+fn optimize_verified(src: &str, pipeline: &str) -> String {
+    let mut g = Graph::new();
+    let mut cfg = g.parse(src.as_bytes()).unwrap();
+    let pipeline = Pipeline::parse(pipeline).unwrap().with_verify(true);
+    pipeline.run(&mut cfg, &mut g);
+    cfg.pretty(&g)
+}
+
+#[test]
+fn unflattened_seq() {
+    let mut g = Graph::new();
+    let Cfg::Seq(seq) = g.parse(b"+[-]>+").unwrap() else {
+        panic!("not a sequence");
+    };
+    let mut cfgs = seq.into_iter().collect::<Vec<_>>();
+    let last = cfgs.pop().unwrap();
+    let cfg = Cfg::Seq(Seq::from_unflattened(vec![last.clone(), last.clone()]));
+    assert_eq!(cfg.verify(&g), Err(VerifyError::AdjacentBlocks));
+    let cfg = Cfg::Seq(Seq::from_unflattened(vec![last.clone()]));
+    assert_eq!(cfg.verify(&g), Err(VerifyError::SingletonSeq));
+    let nested = Cfg::Seq(Seq::from_unflattened(cfgs));
+    let cfg = Cfg::Seq(Seq::from_unflattened(vec![nested, last]));
+    assert_eq!(cfg.verify(&g), Err(VerifyError::NestedSeq));
+}
+
+#[test]
+fn block_invariants() {
+    let mut g = Graph::new();
+    let Cfg::Block(block) = g.parse(b",>.").unwrap() else {
+        panic!("not a block");
+    };
+    assert_eq!(block.verify(&g), Ok(()));
+
+    let mut foreign = block.clone();
+    let copy = Node::Copy(Offset(0), BlockId(100)).insert(&mut g);
+    foreign.push_effect(Effect::Output(vec![copy]), &mut g);
+    assert!(matches!(
+        foreign.verify(&g),
+        Err(VerifyError::ForeignCopy { .. }),
+    ));
+
+    let mut duplicate = block.clone();
+    duplicate.push_effect(block.effects(&g)[0].clone(), &mut g);
+    assert!(matches!(
+        duplicate.verify(&g),
+        Err(VerifyError::DuplicateInput(_)),
+    ));
+
+    let Cfg::Block(other) = g.parse(b">").unwrap() else {
+        panic!("not a block");
+    };
+    let mut chained = block.clone();
+    chained.set_effect_token(other.effect_token().unwrap(), &g);
+    assert!(matches!(
+        chained.verify(&g),
+        Err(VerifyError::ForeignEffect { .. }),
+    ));
+
+    let mut unguarded = block.clone();
+    unguarded.guarded_right = Offset(-1);
+    assert!(matches!(
+        unguarded.verify(&g),
+        Err(VerifyError::UnguardedStore { .. }),
+    ));
+    assert_eq!(g.verify(), Ok(()));
+}
+
+#[test]
+fn unroll_zero_iterations() {
+    // The loop runs zero times, so unrolling removes it and the blocks around
+    // it are merged.
+    assert_eq!(
+        optimize_verified("[-][.-]+", "closed-form,copy-const,unroll"),
+        "p[0] = 1\n",
+    );
+}
+
+#[test]
+fn facts_dead_loop() {
+    // The cell is zero, so the loop never runs and is removed, which leaves
+    // the blocks around it adjacent.
+    assert_eq!(
+        optimize_verified("[-][>]+", "closed-form,facts"),
+        "p[0] = 1\n"
+    );
+}
+
+/// Collects the Brainfuck programs in a directory, recursively.
+fn programs(dir: &Path, paths: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            programs(&path, paths);
+        } else if path.extension().is_some_and(|ext| ext == "b") {
+            paths.push(path);
+        }
+    }
+}
+
+#[test]
+fn all_passes_verified() {
+    let passes = (Pass::ALL.into_iter())
+        .filter(|pass| pass.is_runnable())
+        .map(Pass::name)
+        .collect::<Vec<_>>();
+    let pipeline = Pipeline::parse(&format!("({})*", passes.join(",")))
+        .unwrap()
+        .with_verify(true);
+    let mut paths = Vec::new();
+    programs(Path::new("../tests"), &mut paths);
+    paths.sort();
+    for path in paths {
+        let src = fs::read(&path).unwrap();
+        let mut g = Graph::new();
+        let Ok(mut cfg) = g.parse(&src) else {
+            continue;
+        };
+        pipeline.run(&mut cfg, &mut g);
+    }
+}